@@ -0,0 +1,173 @@
+use std::f64::consts::PI;
+
+use super::CYCLES_PER_SECOND;
+
+// Sub-sample positions a step can start at and the output samples each step is spread over
+const PHASES: usize = 32;
+const TAPS: usize = 16;
+// Cutoff as a fraction of the output sample rate, a bit under Nyquist to leave room for the window
+const CUTOFF: f64 = 0.45;
+// Capacitor charge factor per clock of the DMG and CGB output stage
+const DMG_CHARGE: f64 = 0.999958;
+const CGB_CHARGE: f64 = 0.998943;
+
+// Band-limited step synthesis, every change of the input level adds a windowed sinc step to a delta buffer
+// that is integrated into output samples, so square waves don't alias at any sample rate
+#[derive(Debug)]
+pub(crate) struct BlipBuffer {
+    // Output samples per clock
+    factor: f64,
+    base_factor: f64,
+    // Output position of the first clock of the frame
+    offset: f64,
+    deltas: Vec<f32>,
+    integrator: f32,
+    kernel: [[f32; TAPS]; PHASES],
+}
+
+impl BlipBuffer {
+    pub(crate) fn new(sample_rate: u32) -> BlipBuffer {
+        let factor: f64 = sample_rate as f64 / CYCLES_PER_SECOND as f64;
+        BlipBuffer {
+            factor,
+            base_factor: factor,
+            offset: 0.0,
+            deltas: Vec::new(),
+            integrator: 0.0,
+            kernel: BlipBuffer::create_kernel(),
+        }
+    }
+
+    // Blackman windowed sinc impulses for every phase, normalized so each step adds exactly its delta
+    fn create_kernel() -> [[f32; TAPS]; PHASES] {
+        let mut kernel: [[f32; TAPS]; PHASES] = [[0.0; TAPS]; PHASES];
+        for (phase, taps) in kernel.iter_mut().enumerate() {
+            let fraction: f64 = phase as f64 / PHASES as f64;
+            let mut impulse: [f64; TAPS] = [0.0; TAPS];
+            for (tap, value) in impulse.iter_mut().enumerate() {
+                let x: f64 = tap as f64 - (TAPS / 2) as f64 + 1.0 - fraction;
+                let sinc: f64 = if x == 0.0 { 1.0 } else { (2.0 * PI * CUTOFF * x).sin() / (2.0 * PI * CUTOFF * x) };
+                let window_position: f64 = (x + (TAPS / 2) as f64) / TAPS as f64;
+                let window: f64 = 0.42 - 0.5 * (2.0 * PI * window_position).cos() + 0.08 * (4.0 * PI * window_position).cos();
+                *value = sinc * window.max(0.0);
+            }
+            let sum: f64 = impulse.iter().sum();
+            for (tap, value) in taps.iter_mut().zip(impulse) {
+                *tap = (value / sum) as f32;
+            }
+        }
+        kernel
+    }
+
+    // Scales the number of output samples per clock, used to keep the audio queue from draining or growing
+    pub(crate) fn set_rate_adjustment(&mut self, adjustment: f64) {
+        self.factor = self.base_factor * adjustment;
+    }
+
+    pub(crate) fn add_delta(&mut self, time: u32, delta: f32) {
+        let position: f64 = self.offset + time as f64 * self.factor;
+        let index: usize = position as usize;
+        let phase: usize = ((position - index as f64) * PHASES as f64) as usize;
+        if self.deltas.len() < index + TAPS {
+            self.deltas.resize(index + TAPS, 0.0);
+        }
+        for (value, &tap) in self.deltas[index..index + TAPS].iter_mut().zip(&self.kernel[phase]) {
+            *value += delta * tap;
+        }
+    }
+
+    // Integrates every output sample that lies before the end of the frame
+    pub(crate) fn end_frame(&mut self, clocks: u32, output: &mut Vec<f32>) {
+        let position: f64 = self.offset + clocks as f64 * self.factor;
+        let count: usize = position as usize;
+        if self.deltas.len() < count {
+            self.deltas.resize(count, 0.0);
+        }
+        for delta in self.deltas.drain(..count) {
+            self.integrator += delta;
+            output.push(self.integrator);
+        }
+        self.offset = position - count as f64;
+    }
+}
+
+// The capacitor between the mixer and the output removes the DC offset of the DACs
+#[derive(Debug)]
+pub(crate) struct HighPassFilter {
+    capacitor: f32,
+    charge: f32,
+}
+
+impl HighPassFilter {
+    pub(crate) fn new(sample_rate: u32, cgb: bool) -> HighPassFilter {
+        let charge: f64 = if cgb { CGB_CHARGE } else { DMG_CHARGE };
+        HighPassFilter {
+            capacitor: 0.0,
+            charge: charge.powf(CYCLES_PER_SECOND as f64 / sample_rate as f64) as f32,
+        }
+    }
+
+    pub(crate) fn process(&mut self, input: f32) -> f32 {
+        let output: f32 = input - self.capacitor;
+        self.capacitor = input - output * self.charge;
+        output
+    }
+}
+
+// Turns the per clock output of the mixer into interleaved samples at the host rate,
+// two channels for the stereo mix and four for per channel stems
+#[derive(Debug)]
+pub(crate) struct AudioResampler {
+    pub(crate) sample_rate: u32,
+    buffers: Vec<BlipBuffer>,
+    high_pass_filters: Vec<HighPassFilter>,
+    levels: Vec<f32>,
+    time: u32,
+    samples: Vec<Vec<f32>>,
+}
+
+impl AudioResampler {
+    pub(crate) fn new(sample_rate: u32, cgb: bool, channels: usize) -> AudioResampler {
+        AudioResampler {
+            sample_rate,
+            buffers: (0..channels).map(|_| BlipBuffer::new(sample_rate)).collect(),
+            high_pass_filters: (0..channels).map(|_| HighPassFilter::new(sample_rate, cgb)).collect(),
+            levels: vec![0.0; channels],
+            time: 0,
+            samples: vec![Vec::new(); channels],
+        }
+    }
+
+    // One clock of mixer output, only changes of the level cost anything
+    pub(crate) fn push(&mut self, levels: &[f32]) {
+        for ((buffer, level), &new_level) in self.buffers.iter_mut().zip(self.levels.iter_mut()).zip(levels) {
+            if new_level != *level {
+                buffer.add_delta(self.time, new_level - *level);
+                *level = new_level;
+            }
+        }
+        self.time += 1;
+    }
+
+    pub(crate) fn set_rate_adjustment(&mut self, adjustment: f64) {
+        for buffer in self.buffers.iter_mut() {
+            buffer.set_rate_adjustment(adjustment);
+        }
+    }
+
+    pub(crate) fn end_frame(&mut self, output: &mut Vec<f32>) {
+        for (buffer, samples) in self.buffers.iter_mut().zip(self.samples.iter_mut()) {
+            buffer.end_frame(self.time, samples);
+        }
+        self.time = 0;
+        let count: usize = self.samples.first().map_or(0, |samples| samples.len());
+        for index in 0..count {
+            for (samples, high_pass_filter) in self.samples.iter().zip(self.high_pass_filters.iter_mut()) {
+                output.push(high_pass_filter.process(samples[index]));
+            }
+        }
+        for samples in self.samples.iter_mut() {
+            samples.clear();
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+use super::bit_logic;
+use super::gameboy::Gameboy;
+use super::palette;
+
+const KEY0: u16 = 0xff4c;
+
+// rpgbe's own DMG boot ROM, so the logo scroll doesn't need a dump of Nintendo's.
+// It scrolls the logo from the cartridge header, chimes, locks up on a bad header checksum
+// like the original and hands over with the DMG registers by disabling itself at 0x00fe
+const BUILT_IN_BOOT_ROM_CODE: [u8; 190] = [
+    0x31, 0xfe, 0xff,       // 0000: ld sp,$fffe
+    0xaf,                   // 0003: xor a
+    0x21, 0xff, 0x9f,       // 0004: ld hl,$9fff
+    // clear_vram:
+    0x32,                   // 0007: ld (hl-),a
+    0xcb, 0x7c,             // 0008: bit 7,h
+    0x20, 0xfb,             // 000a: jr nz,clear_vram
+    0x3e, 0x80,             // 000c: ld a,$80
+    0xe0, 0x26,             // 000e: ldh (NR52),a
+    0xe0, 0x11,             // 0010: ldh (NR11),a
+    0x3e, 0xf3,             // 0012: ld a,$f3
+    0xe0, 0x12,             // 0014: ldh (NR12),a
+    0xe0, 0x25,             // 0016: ldh (NR51),a
+    0x3e, 0x77,             // 0018: ld a,$77
+    0xe0, 0x24,             // 001a: ldh (NR50),a
+    0x3e, 0xfc,             // 001c: ld a,$fc
+    0xe0, 0x47,             // 001e: ldh (BGP),a
+    0x11, 0x04, 0x01,       // 0020: ld de,$0104
+    0x21, 0x10, 0x80,       // 0023: ld hl,$8010
+    // decode_logo:
+    0x1a,                   // 0026: ld a,(de)
+    0xcd, 0x90, 0x00,       // 0027: call scale_nibble
+    0x1a,                   // 002a: ld a,(de)
+    0xcb, 0x37,             // 002b: swap a
+    0xcd, 0x90, 0x00,       // 002d: call scale_nibble
+    0x13,                   // 0030: inc de
+    0x7b,                   // 0031: ld a,e
+    0xfe, 0x34,             // 0032: cp $34
+    0x20, 0xf0,             // 0034: jr nz,decode_logo
+    0x3e, 0x01,             // 0036: ld a,$01
+    0x21, 0x04, 0x99,       // 0038: ld hl,$9904
+    0xcd, 0xa6, 0x00,       // 003b: call map_row
+    0x21, 0x24, 0x99,       // 003e: ld hl,$9924
+    0xcd, 0xa6, 0x00,       // 0041: call map_row
+    0x3e, 0x64,             // 0044: ld a,$64
+    0xe0, 0x42,             // 0046: ldh (SCY),a
+    0x3e, 0x91,             // 0048: ld a,$91
+    0xe0, 0x40,             // 004a: ldh (LCDC),a
+    // scroll_logo:
+    0x06, 0x02,             // 004c: ld b,$02
+    0xcd, 0xae, 0x00,       // 004e: call wait_frames
+    0xf0, 0x42,             // 0051: ldh a,(SCY)
+    0x3d,                   // 0053: dec a
+    0xe0, 0x42,             // 0054: ldh (SCY),a
+    0x20, 0xf4,             // 0056: jr nz,scroll_logo
+    0x3e, 0xc1,             // 0058: ld a,$c1
+    0xe0, 0x13,             // 005a: ldh (NR13),a
+    0x3e, 0x87,             // 005c: ld a,$87
+    0xe0, 0x14,             // 005e: ldh (NR14),a
+    0x06, 0x3c,             // 0060: ld b,$3c
+    0xcd, 0xae, 0x00,       // 0062: call wait_frames
+    0x21, 0x34, 0x01,       // 0065: ld hl,$0134
+    0x06, 0x19,             // 0068: ld b,$19
+    0xaf,                   // 006a: xor a
+    // header_checksum:
+    0x96,                   // 006b: sub (hl)
+    0x3d,                   // 006c: dec a
+    0x23,                   // 006d: inc hl
+    0x05,                   // 006e: dec b
+    0x20, 0xfa,             // 006f: jr nz,header_checksum
+    0xbe,                   // 0071: cp (hl)
+    // lock_up:
+    0x20, 0xfe,             // 0072: jr nz,lock_up
+    0xfa, 0x4d, 0x01,       // 0074: ld a,($014d)
+    0xa7,                   // 0077: and a
+    0x0e, 0xb0,             // 0078: ld c,$b0
+    0x20, 0x02,             // 007a: jr nz,set_registers
+    0x0e, 0x80,             // 007c: ld c,$80
+    // set_registers:
+    0x06, 0x01,             // 007e: ld b,$01
+    0xc5,                   // 0080: push bc
+    0xf1,                   // 0081: pop af
+    0x01, 0x13, 0x00,       // 0082: ld bc,$0013
+    0x11, 0xd8, 0x00,       // 0085: ld de,$00d8
+    0x21, 0x4d, 0x01,       // 0088: ld hl,$014d
+    0x31, 0xfe, 0xff,       // 008b: ld sp,$fffe
+    0x18, 0x6e,             // 008e: jr hand_off
+    // scale_nibble:
+    0xd5,                   // 0090: push de
+    0x4f,                   // 0091: ld c,a
+    0x06, 0x04,             // 0092: ld b,$04
+    // scale_bit:
+    0xcb, 0x21,             // 0094: sla c
+    0xcb, 0x13,             // 0096: rl e
+    0x7b,                   // 0098: ld a,e
+    0x1f,                   // 0099: rra
+    0xcb, 0x13,             // 009a: rl e
+    0x05,                   // 009c: dec b
+    0x20, 0xf5,             // 009d: jr nz,scale_bit
+    0x7b,                   // 009f: ld a,e
+    0x22,                   // 00a0: ld (hl+),a
+    0x23,                   // 00a1: inc hl
+    0x22,                   // 00a2: ld (hl+),a
+    0x23,                   // 00a3: inc hl
+    0xd1,                   // 00a4: pop de
+    0xc9,                   // 00a5: ret
+    // map_row:
+    0x06, 0x0c,             // 00a6: ld b,$0c
+    // map_tile:
+    0x22,                   // 00a8: ld (hl+),a
+    0x3c,                   // 00a9: inc a
+    0x05,                   // 00aa: dec b
+    0x20, 0xfb,             // 00ab: jr nz,map_tile
+    0xc9,                   // 00ad: ret
+    // wait_frames:
+    0xf0, 0x44,             // 00ae: ldh a,(LY)
+    0xfe, 0x90,             // 00b0: cp $90
+    0x20, 0xfa,             // 00b2: jr nz,wait_frames
+    // wait_frames_end:
+    0xf0, 0x44,             // 00b4: ldh a,(LY)
+    0xfe, 0x90,             // 00b6: cp $90
+    0x28, 0xfa,             // 00b8: jr z,wait_frames_end
+    0x05,                   // 00ba: dec b
+    0x20, 0xf1,             // 00bb: jr nz,wait_frames
+    0xc9,                   // 00bd: ret
+];
+
+const fn build_boot_rom() -> [u8; 0x100] {
+    let mut boot_rom: [u8; 0x100] = [0; 0x100];
+    let mut i: usize = 0;
+    while i < BUILT_IN_BOOT_ROM_CODE.len() {
+        boot_rom[i] = BUILT_IN_BOOT_ROM_CODE[i];
+        i += 1;
+    }
+    // hand_off: ldh (0xff50),a
+    boot_rom[0xfe] = 0xe0;
+    boot_rom[0xff] = 0x50;
+    boot_rom
+}
+
+const BUILT_IN_BOOT_ROM: [u8; 0x100] = build_boot_rom();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Model {
+    Dmg0,
+    Dmg,
+    Mgb,
+    Sgb,
+    Sgb2,
+    Cgb,
+    Agb,
+}
+
+// I/O registers left behind by every boot ROM
+const COMMON_IO_REGISTERS: [(u16, u8); 40] = [
+    (0xff00, 0xcf), (0xff01, 0x00), (0xff02, 0x7e), (0xff05, 0x00), (0xff06, 0x00), (0xff07, 0xf8), (0xff0f, 0xe1),
+    (0xff10, 0x80), (0xff11, 0xbf), (0xff12, 0xf3), (0xff13, 0xff), (0xff14, 0xbf),
+    (0xff16, 0x3f), (0xff17, 0x00), (0xff18, 0xff), (0xff19, 0xbf),
+    (0xff1a, 0x7f), (0xff1b, 0xff), (0xff1c, 0x9f), (0xff1d, 0xff), (0xff1e, 0xbf),
+    (0xff20, 0xff), (0xff21, 0x00), (0xff22, 0x00), (0xff23, 0xbf),
+    (0xff24, 0x77), (0xff25, 0xf3), (0xff26, 0xf1),
+    (0xff40, 0x91), (0xff41, 0x85), (0xff42, 0x00), (0xff43, 0x00), (0xff44, 0x00), (0xff45, 0x00), (0xff46, 0xff),
+    (0xff47, 0xfc), (0xff4a, 0x00), (0xff4b, 0x00), (0xff50, 0x01), (0xffff, 0x00),
+];
+
+impl Model {
+    pub(crate) fn from_name(name: &str) -> Option<Model> {
+        match name {
+            "dmg0" => Some(Model::Dmg0),
+            "dmg" => Some(Model::Dmg),
+            "mgb" => Some(Model::Mgb),
+            "sgb" => Some(Model::Sgb),
+            "sgb2" => Some(Model::Sgb2),
+            "cgb" => Some(Model::Cgb),
+            "agb" => Some(Model::Agb),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_cgb(self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+
+    pub(crate) fn is_sgb(self) -> bool {
+        matches!(self, Model::Sgb | Model::Sgb2)
+    }
+
+    // A, F, B, C, D, E, H, L, with the half carry and carry flags of DMG and MGB depending on the header checksum
+    fn get_registers(self, cgb_cartridge: bool, header_checksum: u8) -> [u8; 8] {
+        let checksum_flags: u8 = if header_checksum != 0 { 0xb0 } else { 0x80 };
+        match self {
+            Model::Dmg0 => [0x01, 0x00, 0xff, 0x13, 0x00, 0xc1, 0x84, 0x03],
+            Model::Dmg => [0x01, checksum_flags, 0x00, 0x13, 0x00, 0xd8, 0x01, 0x4d],
+            Model::Mgb => [0xff, checksum_flags, 0x00, 0x13, 0x00, 0xd8, 0x01, 0x4d],
+            Model::Sgb => [0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xc0, 0x60],
+            Model::Sgb2 => [0xff, 0x00, 0x00, 0x14, 0x00, 0x00, 0xc0, 0x60],
+            Model::Cgb if cgb_cartridge => [0x11, 0x80, 0x00, 0x00, 0xff, 0x56, 0x00, 0x0d],
+            Model::Cgb => [0x11, 0x80, 0x00, 0x00, 0x00, 0x08, 0x00, 0x7c],
+            // The AGB boot ROM is the CGB one with B incremented, which games use to detect it
+            Model::Agb if cgb_cartridge => [0x11, 0x00, 0x01, 0x00, 0xff, 0x56, 0x00, 0x0d],
+            Model::Agb => [0x11, 0x00, 0x01, 0x00, 0x00, 0x08, 0x00, 0x7c],
+        }
+    }
+
+    fn get_io_registers(self) -> &'static [(u16, u8)] {
+        match self {
+            Model::Dmg0 => &[(0xff04, 0x18)],
+            Model::Dmg | Model::Mgb => &[(0xff04, 0xab)],
+            Model::Sgb | Model::Sgb2 => &[(0xff26, 0xf0)],
+            Model::Cgb | Model::Agb => &[(0xff02, 0x7f), (0xff46, 0x00)],
+        }
+    }
+}
+
+fn write_palette_colors(palette_ram: &mut [u8; 64], palette: usize, colors: [u16; 4]) {
+    for (index, color) in colors.iter().enumerate() {
+        let offset: usize = palette * 8 + index * 2;
+        palette_ram[offset..offset + 2].copy_from_slice(&color.to_le_bytes());
+    }
+}
+
+impl Gameboy {
+    pub(crate) fn is_cgb_cartridge(&self) -> bool {
+        // 0x80 marks CGB enhanced carts, 0xc0 CGB only carts
+        bit_logic::check_bit(self.header[0x143], 7)
+    }
+
+    // Up to 16 characters, CGB carts use the last byte for the CGB flag
+    pub(crate) fn get_title(&self) -> String {
+        let end: usize = if self.is_cgb_cartridge() { 0x143 } else { 0x144 };
+        self.header[0x134..end].iter()
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' })
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    pub(crate) fn detect_model(&self, sgb: bool) -> Model {
+        if sgb {
+            Model::Sgb
+        } else if self.is_cgb_cartridge() {
+            Model::Cgb
+        } else {
+            Model::Dmg
+        }
+    }
+
+    fn set_model(&mut self, model: Model) {
+        self.model = model;
+        self.sgb_mode = model.is_sgb();
+        self.cgb_mode = model.is_cgb() && self.is_cgb_cartridge();
+        self.cgb_compatibility = false;
+    }
+
+    // What the CGB boot ROM leaves in palette RAM for a DMG cartridge, picked by its title
+    fn load_title_palette(&mut self) {
+        let [bg, obj0, obj1]: [[u16; 4]; 3] = palette::get_title_palette_colors(&self.header);
+        write_palette_colors(&mut self.bg_palette_ram, 0, bg);
+        write_palette_colors(&mut self.obj_palette_ram, 0, obj0);
+        write_palette_colors(&mut self.obj_palette_ram, 1, obj1);
+        self.cgb_compatibility = true;
+    }
+
+    fn set_registers(&mut self, [a, f, b, c, d, e, h, l]: [u8; 8]) {
+        self.a = a;
+        self.zero = bit_logic::check_bit(f, 7);
+        self.subtract = bit_logic::check_bit(f, 6);
+        self.half_carry = bit_logic::check_bit(f, 5);
+        self.carry = bit_logic::check_bit(f, 4);
+        self.b = b;
+        self.c = c;
+        self.d = d;
+        self.e = e;
+        self.h = h;
+        self.l = l;
+    }
+
+    // Starts from the state the boot ROM of the model hands over to the cartridge with
+    pub(crate) fn skip_boot_rom(&mut self, model: Model) {
+        self.set_model(model);
+        self.set_registers(model.get_registers(self.is_cgb_cartridge(), self.header[0x14d]));
+        self.sp = 0xfffe;
+        self.pc = 0x0100;
+        for &(address, value) in COMMON_IO_REGISTERS.iter().chain(model.get_io_registers()) {
+            self.bus.write(address, value);
+        }
+        self.sync_sound_channels();
+        self.boot_rom_enabled = false;
+        if model.is_cgb() && !self.is_cgb_cartridge() {
+            self.load_title_palette();
+        }
+    }
+
+    // DMG, MGB and SGB boot ROMs are 256 bytes, CGB ones 2304 bytes with a hole for the cartridge header
+    pub(crate) fn load_boot_rom_from_path(&mut self, boot_rom_path: PathBuf, model: Model) -> Result<(), String> {
+        let mut boot_rom: Vec<u8> = Vec::new();
+        File::open(&boot_rom_path)
+            .and_then(|mut file| file.read_to_end(&mut boot_rom))
+            .map_err(|error| format!("{}: {}", boot_rom_path.display(), error))?;
+        let expected_size: usize = if model.is_cgb() { 0x900 } else { 0x100 };
+        if boot_rom.len() != expected_size {
+            return Err(format!("{}: boot ROM should be {} bytes for {:?}, not {}", boot_rom_path.display(), expected_size, model, boot_rom.len()));
+        }
+        self.boot_rom = boot_rom;
+        self.start_boot_rom(model);
+        Ok(())
+    }
+
+    // The built-in boot ROM only knows the DMG hand-over state, other models start at 0x0100 directly
+    pub(crate) fn load_built_in_boot_rom(&mut self, model: Model) {
+        if model != Model::Dmg {
+            self.skip_boot_rom(model);
+            return;
+        }
+        self.boot_rom = BUILT_IN_BOOT_ROM.to_vec();
+        self.start_boot_rom(model);
+    }
+
+    fn start_boot_rom(&mut self, model: Model) {
+        self.set_model(model);
+        // Until the boot ROM decides, CGB hardware runs with its color features on
+        self.cgb_mode = model.is_cgb();
+        self.set_registers([0; 8]);
+        self.sp = 0x0000;
+        self.pc = 0x0000;
+        self.bus.io.clear();
+        self.bus.interrupt_enable = 0;
+        self.boot_rom_enabled = true;
+    }
+
+    pub(crate) fn read_from_boot_rom(&self, address: u16) -> Option<u8> {
+        match address {
+            0x0000..=0x00ff | 0x0200..=0x08ff if self.boot_rom_enabled => self.boot_rom.get(address as usize).copied(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn finish_boot_rom(&mut self) {
+        self.boot_rom_enabled = false;
+        // The CGB boot ROM writes KEY0 bit 2 to run DMG cartridges with its compatibility palettes
+        if self.model.is_cgb() && bit_logic::check_bit(self.bus.io[KEY0], 2) {
+            self.cgb_mode = false;
+            self.cgb_compatibility = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrong_size_boot_rom_is_an_error() {
+        let path: PathBuf = std::env::temp_dir().join(format!("rpgbe-boot-{}.bin", std::process::id()));
+        std::fs::write(&path, [0u8; 0x100]).unwrap();
+        let mut gameboy: Gameboy = Gameboy::new();
+        assert!(gameboy.load_boot_rom_from_path(path.clone(), Model::Cgb).is_err());
+        assert!(!gameboy.boot_rom_enabled);
+        assert!(gameboy.load_boot_rom_from_path(path.clone(), Model::Dmg).is_ok());
+        assert!(gameboy.boot_rom_enabled);
+        std::fs::remove_file(&path).unwrap();
+        assert!(gameboy.load_boot_rom_from_path(path, Model::Dmg).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use std::ops::{Index, IndexMut};
+
+use super::mapper::{self, Mapper};
+
+// Two 8 KiB banks, bank 1 only exists on CGB
+#[derive(Debug)]
+pub(crate) struct Vram {
+    pub(crate) bank: u8,
+    banks: Vec<u8>,
+}
+
+impl Vram {
+    fn new() -> Vram {
+        Vram { bank: 0, banks: vec![0; 0x4000] }
+    }
+
+    pub(crate) fn read(&self, bank: u8, address: u16) -> u8 {
+        self.banks[(bank as usize) * 0x2000 + (address & 0x1fff) as usize]
+    }
+
+    pub(crate) fn write(&mut self, bank: u8, address: u16, value: u8) {
+        self.banks[(bank as usize) * 0x2000 + (address & 0x1fff) as usize] = value;
+    }
+}
+
+// Eight 4 KiB banks, 0xc000 always maps bank 0 and 0xd000 the selected one
+#[derive(Debug)]
+pub(crate) struct Wram {
+    pub(crate) bank: u8,
+    banks: Vec<u8>,
+}
+
+impl Wram {
+    fn new() -> Wram {
+        Wram { bank: 1, banks: vec![0; 0x8000] }
+    }
+
+    // Echo RAM at 0xe000-0xfdff lands on the same offsets
+    fn get_offset(&self, address: u16) -> usize {
+        let offset: usize = (address & 0x1fff) as usize;
+        if offset < 0x1000 {
+            offset
+        } else {
+            offset - 0x1000 + (self.bank as usize) * 0x1000
+        }
+    }
+
+    pub(crate) fn read(&self, address: u16) -> u8 {
+        self.banks[self.get_offset(address)]
+    }
+
+    pub(crate) fn write(&mut self, address: u16, value: u8) {
+        let offset: usize = self.get_offset(address);
+        self.banks[offset] = value;
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Oam([u8; 0xa0]);
+
+impl Oam {
+    pub(crate) fn read(&self, address: u16) -> u8 {
+        self.0[(address - 0xfe00) as usize]
+    }
+
+    pub(crate) fn write(&mut self, address: u16, value: u8) {
+        self.0[(address - 0xfe00) as usize] = value;
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Hram([u8; 0x7f]);
+
+impl Hram {
+    pub(crate) fn read(&self, address: u16) -> u8 {
+        self.0[(address - 0xff80) as usize]
+    }
+
+    pub(crate) fn write(&mut self, address: u16, value: u8) {
+        self.0[(address - 0xff80) as usize] = value;
+    }
+}
+
+// Raw register values of 0xff00-0xff7f, indexed by their address
+#[derive(Debug)]
+pub(crate) struct IoRegisters([u8; 0x80]);
+
+impl IoRegisters {
+    pub(crate) fn clear(&mut self) {
+        self.0.fill(0);
+    }
+}
+
+impl Index<u16> for IoRegisters {
+    type Output = u8;
+
+    fn index(&self, address: u16) -> &u8 {
+        &self.0[(address - 0xff00) as usize]
+    }
+}
+
+impl IndexMut<u16> for IoRegisters {
+    fn index_mut(&mut self, address: u16) -> &mut u8 {
+        &mut self.0[(address - 0xff00) as usize]
+    }
+}
+
+// Storage of the address space, register side effects stay with the Gameboy
+#[derive(Debug)]
+pub(crate) struct Bus {
+    pub(crate) mapper: Box<dyn Mapper>,
+    pub(crate) vram: Vram,
+    pub(crate) wram: Wram,
+    pub(crate) oam: Oam,
+    pub(crate) io: IoRegisters,
+    pub(crate) hram: Hram,
+    pub(crate) interrupt_enable: u8,
+}
+
+impl Bus {
+    pub(crate) fn new() -> Bus {
+        Bus {
+            mapper: mapper::from_cartridge(Vec::new()),
+            vram: Vram::new(),
+            wram: Wram::new(),
+            oam: Oam([0; 0xa0]),
+            io: IoRegisters([0; 0x80]),
+            hram: Hram([0; 0x7f]),
+            interrupt_enable: 0,
+        }
+    }
+
+    pub(crate) fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x7fff => self.mapper.read_rom(address),
+            0x8000..=0x9fff => self.vram.read(self.vram.bank, address),
+            0xa000..=0xbfff => self.mapper.read_ram(address),
+            0xc000..=0xfdff => self.wram.read(address),
+            0xfe00..=0xfe9f => self.oam.read(address),
+            0xfea0..=0xfeff => 0xff,
+            0xff00..=0xff7f => self.io[address],
+            0xff80..=0xfffe => self.hram.read(address),
+            0xffff => self.interrupt_enable,
+        }
+    }
+
+    pub(crate) fn write(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x7fff => self.mapper.write_control(address, value),
+            0x8000..=0x9fff => self.vram.write(self.vram.bank, address, value),
+            0xa000..=0xbfff => self.mapper.write_ram(address, value),
+            0xc000..=0xfdff => self.wram.write(address, value),
+            0xfe00..=0xfe9f => self.oam.write(address, value),
+            0xfea0..=0xfeff => {},
+            0xff00..=0xff7f => self.io[address] = value,
+            0xff80..=0xfffe => self.hram.write(address, value),
+            0xffff => self.interrupt_enable = value,
+        }
+    }
+}
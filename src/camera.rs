@@ -0,0 +1,188 @@
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+pub(crate) const CAMERA_WIDTH: usize = 128;
+pub(crate) const CAMERA_HEIGHT: usize = 112;
+
+// Feeds the Pocket Camera sensor, frames are CAMERA_WIDTH x CAMERA_HEIGHT luminance values where 0 is black
+pub(crate) trait ImageSource: Debug {
+    fn capture(&mut self) -> Vec<u8>;
+}
+
+pub(crate) fn from_path(path: PathBuf) -> Result<Box<dyn ImageSource>, String> {
+    if path.as_os_str() == "test" {
+        Ok(Box::new(TestPattern::new()))
+    } else if path.is_dir() {
+        Ok(Box::new(ImageSequence::load_from_path(path)?))
+    } else {
+        Ok(Box::new(StaticImage::load_from_path(path)?))
+    }
+}
+
+// Decodes a PNG and stretches it to the sensor size
+fn load_png(path: &Path) -> Result<Vec<u8>, String> {
+    let file = File::open(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|error| format!("{}: {}", path.display(), error))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|error| format!("{}: {}", path.display(), error))?;
+    let channels: usize = info.color_type.samples();
+    let (width, height): (usize, usize) = (info.width as usize, info.height as usize);
+    let mut image: Vec<u8> = vec![0; CAMERA_WIDTH * CAMERA_HEIGHT];
+    for y in 0..CAMERA_HEIGHT {
+        for x in 0..CAMERA_WIDTH {
+            let offset: usize = ((y * height / CAMERA_HEIGHT) * width + x * width / CAMERA_WIDTH) * channels;
+            image[y * CAMERA_WIDTH + x] = match channels {
+                1 | 2 => buffer[offset],
+                _ => ((buffer[offset] as u32 * 299 + buffer[offset + 1] as u32 * 587 + buffer[offset + 2] as u32 * 114) / 1000) as u8,
+            };
+        }
+    }
+    Ok(image)
+}
+
+#[derive(Debug)]
+pub(crate) struct StaticImage {
+    image: Vec<u8>,
+}
+
+impl StaticImage {
+    pub(crate) fn load_from_path(path: PathBuf) -> Result<StaticImage, String> {
+        Ok(StaticImage { image: load_png(&path)? })
+    }
+}
+
+impl ImageSource for StaticImage {
+    fn capture(&mut self) -> Vec<u8> {
+        self.image.clone()
+    }
+}
+
+// Every PNG of a directory in file name order, one per capture and looping at the end
+#[derive(Debug)]
+pub(crate) struct ImageSequence {
+    images: Vec<Vec<u8>>,
+    index: usize,
+}
+
+impl ImageSequence {
+    pub(crate) fn load_from_path(path: PathBuf) -> Result<ImageSequence, String> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(&path)
+            .map_err(|error| format!("{}: {}", path.display(), error))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("png")))
+            .collect();
+        paths.sort();
+        if paths.is_empty() {
+            return Err(format!("{}: No PNG files found", path.display()));
+        }
+        let images: Vec<Vec<u8>> = paths.iter().map(|path| load_png(path)).collect::<Result<_, _>>()?;
+        Ok(ImageSequence { images, index: 0 })
+    }
+}
+
+impl ImageSource for ImageSequence {
+    fn capture(&mut self) -> Vec<u8> {
+        let image: Vec<u8> = self.images[self.index].clone();
+        self.index = (self.index + 1) % self.images.len();
+        image
+    }
+}
+
+// A diagonal gradient with a checkerboard square moving one pixel per capture
+#[derive(Debug)]
+pub(crate) struct TestPattern {
+    frame: usize,
+}
+
+impl TestPattern {
+    pub(crate) fn new() -> TestPattern {
+        TestPattern { frame: 0 }
+    }
+}
+
+impl ImageSource for TestPattern {
+    fn capture(&mut self) -> Vec<u8> {
+        let mut image: Vec<u8> = vec![0; CAMERA_WIDTH * CAMERA_HEIGHT];
+        let square_x: usize = self.frame % (CAMERA_WIDTH - 32);
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                image[y * CAMERA_WIDTH + x] = if (square_x..square_x + 32).contains(&x) && (40..72).contains(&y) {
+                    if ((x / 4) + (y / 4)) % 2 == 0 { 0xff } else { 0x00 }
+                } else {
+                    ((x + y) * 255 / (CAMERA_WIDTH + CAMERA_HEIGHT)) as u8
+                };
+            }
+        }
+        self.frame += 1;
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_png(name: &str, width: u32, height: u32, color_type: png::ColorType, data: &[u8]) -> PathBuf {
+        let path: PathBuf = std::env::temp_dir().join(format!("rpgbe-camera-{}-{}.png", name, std::process::id()));
+        let mut encoder = png::Encoder::new(File::create(&path).unwrap(), width, height);
+        encoder.set_color(color_type);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header().unwrap().write_image_data(data).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_pattern_moves_a_checkerboard_over_a_gradient() {
+        let mut test_pattern: TestPattern = TestPattern::new();
+        let image: Vec<u8> = test_pattern.capture();
+        assert_eq!(image.len(), CAMERA_WIDTH * CAMERA_HEIGHT);
+        assert_eq!(image[0], 0x00);
+        assert_eq!(image[CAMERA_WIDTH * CAMERA_HEIGHT - 1], 252);
+        assert_eq!(image[40 * CAMERA_WIDTH], 0xff);
+        assert_eq!(image[40 * CAMERA_WIDTH + 4], 0x00);
+        assert_eq!(image[44 * CAMERA_WIDTH + 4], 0xff);
+        assert_eq!(image[40 * CAMERA_WIDTH + 32], 76);
+        // The square moves right by a pixel, uncovering the gradient
+        let image: Vec<u8> = test_pattern.capture();
+        assert_eq!(image[40 * CAMERA_WIDTH], 42);
+        assert_eq!(image[40 * CAMERA_WIDTH + 1], 0xff);
+        assert_eq!(image[40 * CAMERA_WIDTH + 32], 0xff);
+    }
+
+    #[test]
+    fn grey_pngs_are_stretched_to_the_sensor() {
+        let path: PathBuf = write_png("grey", 2, 1, png::ColorType::Grayscale, &[0x10, 0xf0]);
+        let image: Vec<u8> = load_png(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(image[0], 0x10);
+        assert_eq!(image[63], 0x10);
+        assert_eq!(image[64], 0xf0);
+        assert_eq!(image[CAMERA_WIDTH * CAMERA_HEIGHT - 1], 0xf0);
+        let path: PathBuf = write_png("grey-alpha", 1, 1, png::ColorType::GrayscaleAlpha, &[0x80, 0x00]);
+        let image: Vec<u8> = load_png(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(image.iter().all(|&value| value == 0x80));
+    }
+
+    #[test]
+    fn rgb_pngs_are_converted_to_luminance() {
+        // Red, green, blue and white quadrants
+        let data: [u8; 12] = [0xff, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff];
+        let path: PathBuf = write_png("rgb", 2, 2, png::ColorType::Rgb, &data);
+        let image: Vec<u8> = load_png(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(image[0], 76);
+        assert_eq!(image[CAMERA_WIDTH - 1], 149);
+        assert_eq!(image[56 * CAMERA_WIDTH], 29);
+        assert_eq!(image[CAMERA_WIDTH * CAMERA_HEIGHT - 1], 0xff);
+        // Rows switch halfway down
+        assert_eq!(image[55 * CAMERA_WIDTH], 76);
+        let path: PathBuf = write_png("rgba", 1, 1, png::ColorType::Rgba, &[0x00, 0xff, 0x00, 0x00]);
+        let image: Vec<u8> = load_png(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(image[0], 149);
+    }
+}
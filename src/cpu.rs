@@ -1540,6 +1540,10 @@ impl Gameboy {
             0x10 => {
                 // STOP
                 if IS_CPU_DEBUG_MODE { println!("STOP"); }
+                if self.cgb_mode && self.prepare_speed_switch {
+                    self.double_speed = !self.double_speed;
+                    self.prepare_speed_switch = false;
+                }
             },
             0x11 => {
                 // LD DE, u16
@@ -1,9 +1,19 @@
-use super::{WIDTH, HEIGHT, TIME_BETWEEN_AUDIO_SAMPLING};
+use std::path::PathBuf;
+
+use super::{WIDTH, HEIGHT, SGB_WIDTH, SGB_HEIGHT, SAMPLE_RATE};
+use super::blip::AudioResampler;
+use super::boot::Model;
+use super::bus::Bus;
+use super::mixer::Mixer;
+use super::scope::Scope;
+use super::vgm::VgmLogger;
+use super::palette::{self, Palette};
 
 // CPU
 // GPU
 pub const SCREEN_DATA_SIZE: u32 = (WIDTH as u32) * (HEIGHT as u32) * 3;
 pub(crate) const SCANLINE_COUNTER_START: u16 = 456;
+pub(crate) const SGB_SCREEN_DATA_SIZE: u32 = (SGB_WIDTH as u32) * (SGB_HEIGHT as u32) * 3;
 // Memory
 // SPU
 // Timer
@@ -28,32 +38,62 @@ pub(crate) struct Gameboy {
     pub(crate) interrupts_enabled: bool,
     pub(crate) pending_interrupt_enable: bool,
     pub(crate) one_instruction_passed: bool,
+    pub(crate) double_speed: bool,
+    pub(crate) prepare_speed_switch: bool,
+    pub(crate) dma_cycles: u32,
 
     // GPU
     pub(crate) scanline_counter: i32,
     pub(crate) screen_data: [u8; SCREEN_DATA_SIZE as usize],
     pub(crate) scanline_bg: [bool; WIDTH as usize],
+    pub(crate) scanline_bg_priority: [bool; WIDTH as usize],
+    pub(crate) screen_shades: [u8; (WIDTH as usize) * (HEIGHT as usize)],
+    pub(crate) scanline: u8,
+    pub(crate) stat_line: bool,
+    pub(crate) lcd_skip_frame: bool,
+    pub(crate) palette: Palette,
+    pub(crate) bg_palette_index: u8,
+    pub(crate) bg_palette_ram: [u8; 64],
+    pub(crate) obj_palette_index: u8,
+    pub(crate) obj_palette_ram: [u8; 64],
 
     // Memory
     pub(crate) gamepad_state: u8,
-    pub(crate) rom_banking: bool,
-    pub(crate) enable_ram: bool,
-    pub(crate) mbc1: bool,
-    pub(crate) mbc2: bool,
-    pub(crate) current_rom_bank: u8,
-    pub(crate) current_ram_bank: u8,
-    pub(crate) ram_banks: Vec<u8>,
-    pub(crate) cartridge: Vec<u8>,
-    pub(crate) rom: Vec<u8>,
+    pub(crate) model: Model,
+    pub(crate) boot_rom: Vec<u8>,
+    pub(crate) boot_rom_enabled: bool,
+    pub(crate) cgb_mode: bool,
+    pub(crate) cgb_compatibility: bool,
+    pub(crate) hdma_source: u16,
+    pub(crate) hdma_destination: u16,
+    pub(crate) hdma_length: u8,
+    pub(crate) hdma_active: bool,
+    pub(crate) header: Vec<u8>,
+    pub(crate) save_path: PathBuf,
+    pub(crate) bus: Bus,
 
     // SPU
     pub(crate) audio_data: Vec<f32>,
-    pub(crate) audio_fill_timer: u8, // CYCLES_PER_SECOND / SAMPLE_RATE (44100)
+    pub(crate) audio_resampler: AudioResampler,
+    // The mix again while recording, at a fixed ratio so the rate control never bends the pitch of a recording
+    pub(crate) record_resampler: Option<AudioResampler>,
+    pub(crate) record_data: Vec<f32>,
+    // Each channel on its own, only resampled while stems are recorded
+    pub(crate) stem_resampler: Option<AudioResampler>,
+    pub(crate) stem_data: Vec<f32>,
+    pub(crate) mixer: Mixer,
+    pub(crate) vgm_logger: Option<VgmLogger>,
+    pub(crate) scope: Option<Scope>,
+    pub(crate) frame_sequencer: u8,
     pub(crate) sound_channel_1: SoundChannel1,
     pub(crate) sound_channel_2: SoundChannel2,
     pub(crate) sound_channel_3: SoundChannel3,
     pub(crate) sound_channel_4: SoundChannel4,
 
+    // SGB
+    pub(crate) sgb_mode: bool,
+    pub(crate) sgb: Sgb,
+
     // Timer
     pub(crate) timer_counter: i32,
     pub(crate) divider_counter: i32,
@@ -61,39 +101,39 @@ pub(crate) struct Gameboy {
 
 impl Gameboy {
     pub fn new() -> Gameboy {
-        let mut rom_vec = vec![0; 0x10000];
-
-        rom_vec[0xff05] = 0x00;
-        rom_vec[0xff06] = 0x00;
-        rom_vec[0xff07] = 0x00;
-        rom_vec[0xff10] = 0x80;
-        rom_vec[0xff11] = 0xbf;
-        rom_vec[0xff12] = 0xf3;
-        rom_vec[0xff14] = 0xbf;
-        rom_vec[0xff16] = 0x3f;
-        rom_vec[0xff17] = 0x00;
-        rom_vec[0xff19] = 0xbf;
-        rom_vec[0xff1a] = 0x7f;
-        rom_vec[0xff1b] = 0xff;
-        rom_vec[0xff1c] = 0x9f;
-        rom_vec[0xff1e] = 0xbf;
-        rom_vec[0xff20] = 0xff;
-        rom_vec[0xff21] = 0x00;
-        rom_vec[0xff22] = 0x00;
-        rom_vec[0xff23] = 0xbf;
-        rom_vec[0xff24] = 0x77;
-        rom_vec[0xff25] = 0xf3;
-        rom_vec[0xff26] = 0xf1;
-        rom_vec[0xff40] = 0x91;
-        rom_vec[0xff42] = 0x00;
-        rom_vec[0xff43] = 0x00;
-        rom_vec[0xff45] = 0x00;
-        rom_vec[0xff47] = 0xfc;
-        rom_vec[0xff48] = 0xff;
-        rom_vec[0xff49] = 0xff;
-        rom_vec[0xff4a] = 0x00;
-        rom_vec[0xff4b] = 0x00;
-        rom_vec[0xffff] = 0x00;
+        let mut bus: Bus = Bus::new();
+
+        bus.io[0xff05] = 0x00;
+        bus.io[0xff06] = 0x00;
+        bus.io[0xff07] = 0x00;
+        bus.io[0xff10] = 0x80;
+        bus.io[0xff11] = 0xbf;
+        bus.io[0xff12] = 0xf3;
+        bus.io[0xff14] = 0xbf;
+        bus.io[0xff16] = 0x3f;
+        bus.io[0xff17] = 0x00;
+        bus.io[0xff19] = 0xbf;
+        bus.io[0xff1a] = 0x7f;
+        bus.io[0xff1b] = 0xff;
+        bus.io[0xff1c] = 0x9f;
+        bus.io[0xff1e] = 0xbf;
+        bus.io[0xff20] = 0xff;
+        bus.io[0xff21] = 0x00;
+        bus.io[0xff22] = 0x00;
+        bus.io[0xff23] = 0xbf;
+        bus.io[0xff24] = 0x77;
+        bus.io[0xff25] = 0xf3;
+        bus.io[0xff26] = 0xf1;
+        bus.io[0xff40] = 0x91;
+        bus.io[0xff42] = 0x00;
+        bus.io[0xff43] = 0x00;
+        bus.io[0xff45] = 0x00;
+        bus.io[0xff47] = 0xfc;
+        bus.io[0xff48] = 0xff;
+        bus.io[0xff49] = 0xff;
+        bus.io[0xff4a] = 0x00;
+        bus.io[0xff4b] = 0x00;
+        bus.interrupt_enable = 0x00;
 
         Gameboy {
             // CPU
@@ -114,28 +154,55 @@ impl Gameboy {
             interrupts_enabled: false,
             pending_interrupt_enable: false,
             one_instruction_passed: false,
+            double_speed: false,
+            prepare_speed_switch: false,
+            dma_cycles: 0,
             // GPU
             scanline_counter: SCANLINE_COUNTER_START as i32,
             screen_data: [0; SCREEN_DATA_SIZE as usize],
             scanline_bg: [false; WIDTH as usize],
+            scanline_bg_priority: [false; WIDTH as usize],
+            screen_shades: [0; (WIDTH as usize) * (HEIGHT as usize)],
+            scanline: 0,
+            stat_line: false,
+            lcd_skip_frame: false,
+            palette: palette::GREY,
+            bg_palette_index: 0,
+            bg_palette_ram: [0xff; 64],
+            obj_palette_index: 0,
+            obj_palette_ram: [0xff; 64],
             // Memory
             gamepad_state: 0xff,
-            rom_banking: false,
-            enable_ram: false,
-            mbc1: false,
-            mbc2: false,
-            current_rom_bank: 1,
-            current_ram_bank: 0,
-            ram_banks: vec![0; 0x8000],
-            cartridge: Vec::new(),
-            rom: rom_vec,
+            model: Model::Dmg,
+            boot_rom: Vec::new(),
+            boot_rom_enabled: false,
+            cgb_mode: false,
+            cgb_compatibility: false,
+            hdma_source: 0,
+            hdma_destination: 0,
+            hdma_length: 0x7f,
+            hdma_active: false,
+            header: vec![0; 0x150],
+            save_path: PathBuf::new(),
+            bus,
             // SPU
             audio_data: Vec::new(),
-            audio_fill_timer: TIME_BETWEEN_AUDIO_SAMPLING,
+            audio_resampler: AudioResampler::new(SAMPLE_RATE, false, 2),
+            record_resampler: None,
+            record_data: Vec::new(),
+            stem_resampler: None,
+            stem_data: Vec::new(),
+            mixer: Mixer::new(),
+            vgm_logger: None,
+            scope: None,
+            frame_sequencer: 0,
             sound_channel_1: SoundChannel1::new(),
             sound_channel_2: SoundChannel2::new(),
             sound_channel_3: SoundChannel3::new(),
             sound_channel_4: SoundChannel4::new(),
+            // SGB
+            sgb_mode: false,
+            sgb: Sgb::new(),
             // Timer
             timer_counter: 0,
             divider_counter: 0,
@@ -146,16 +213,16 @@ impl Gameboy {
 #[derive(Debug)]
 pub(crate) struct SoundChannel1 {
     pub(crate) enabled: bool,
+    pub(crate) dac_enabled: bool,
     pub(crate) amplitude: i16,
     pub(crate) frequency: i16,
     pub(crate) frequency_timer: u16,
-    pub(crate) frame_sequence: u8,
-    pub(crate) frame_sequence_timer: u16,
     pub(crate) wave_duty_position: u8,
     pub(crate) envelope_enabled: bool,
     pub(crate) envelope_sweeps: u8,
-    pub(crate) length: u8,
+    pub(crate) length: u16,
     pub(crate) sweep_enabled: bool,
+    pub(crate) sweep_negated: bool,
     pub(crate) sweep_period: u8,
     pub(crate) sweep_shadow: i16,
 }
@@ -163,35 +230,55 @@ pub(crate) struct SoundChannel1 {
 #[derive(Debug)]
 pub(crate) struct SoundChannel2 {
     pub(crate) enabled: bool,
+    pub(crate) dac_enabled: bool,
     pub(crate) amplitude: i16,
     pub(crate) frequency_timer: u16,
-    pub(crate) frame_sequence: u8,
-    pub(crate) frame_sequence_timer: u16,
     pub(crate) wave_duty_position: u8,
     pub(crate) envelope_enabled: bool,
     pub(crate) envelope_sweeps: u8,
-    pub(crate) length: u8,
+    pub(crate) length: u16,
 }
 
 #[derive(Debug)]
 pub(crate) struct SoundChannel3 {
     pub(crate) enabled: bool,
+    pub(crate) dac_enabled: bool,
     pub(crate) frequency_timer: u16,
-    pub(crate) frame_sequence: u8,
-    pub(crate) frame_sequence_timer: u16,
     pub(crate) wave_index: u8,
+    pub(crate) wave_ram_accessible: bool,
+    pub(crate) sample_buffer: u8,
     pub(crate) length: u16,
 }
 
 #[derive(Debug)]
 pub(crate) struct SoundChannel4 {
     pub(crate) enabled: bool,
+    pub(crate) dac_enabled: bool,
     pub(crate) amplitude: i16,
-    pub(crate) frequency_timer: u16,
-    pub(crate) frame_sequence: u8,
-    pub(crate) frame_sequence_timer: u16,
+    pub(crate) frequency_timer: u32,
     pub(crate) envelope_enabled: bool,
     pub(crate) envelope_sweeps: u8,
-    pub(crate) length: u8,
+    pub(crate) length: u16,
     pub(crate) lfsr: u16,
-}
\ No newline at end of file
+}
+
+#[derive(Debug)]
+pub(crate) struct Sgb {
+    pub(crate) previous_lines: u8,
+    pub(crate) packet: [u8; 16],
+    pub(crate) packet_bits: u8,
+    pub(crate) receiving: bool,
+    pub(crate) packets: Vec<[u8; 16]>,
+    pub(crate) palettes: [[u16; 4]; 4],
+    pub(crate) system_palettes: Vec<u16>,
+    pub(crate) attribute_map: [u8; 360],
+    pub(crate) attribute_files: Vec<u8>,
+    pub(crate) mask: u8,
+    pub(crate) pending_transfer: Option<(u8, u8)>,
+    pub(crate) border_tiles: Vec<u8>,
+    pub(crate) border_map: Vec<u8>,
+    pub(crate) border_palettes: [u16; 64],
+    pub(crate) players: u8,
+    pub(crate) current_player: u8,
+    pub(crate) screen_data: Vec<u8>,
+}
@@ -0,0 +1,197 @@
+use std::fs;
+use std::path::Path;
+
+use super::bit_logic;
+use super::boot::Model;
+use super::bus::Bus;
+use super::gameboy::Gameboy;
+use super::mapper;
+
+const GBS_MAGIC: &[u8] = b"GBS";
+const HEADER_SIZE: usize = 0x70;
+// The player code lives below the load address, interrupt handlers at their vectors and the driver after them
+const DRIVER_ADDRESS: u16 = 0x68;
+const VBLANK_VECTOR: usize = 0x40;
+const TIMER_VECTOR: usize = 0x50;
+// The format leaves everything below 0x400 to the player
+const MIN_LOAD_ADDRESS: u16 = 0x400;
+const OPCODE_JP: u8 = 0xc3;
+const OPCODE_CALL: u8 = 0xcd;
+const OPCODE_RETI: u8 = 0xd9;
+
+// Game Boy Sound rip, the sound driver of a game with INIT and PLAY routines
+#[derive(Debug)]
+pub(crate) struct GbsFile {
+    pub(crate) song_count: u8,
+    // Counted from 0, the header counts songs from 1
+    pub(crate) first_song: u8,
+    load_address: u16,
+    init_address: u16,
+    play_address: u16,
+    stack_pointer: u16,
+    timer_modulo: u8,
+    timer_control: u8,
+    pub(crate) title: String,
+    pub(crate) author: String,
+    pub(crate) copyright: String,
+    data: Vec<u8>,
+}
+
+impl GbsFile {
+    pub(crate) fn load_from_path(path: &Path) -> Result<GbsFile, String> {
+        let file: Vec<u8> = fs::read(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+        GbsFile::parse(&file).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    pub(crate) fn parse(file: &[u8]) -> Result<GbsFile, String> {
+        if !file.starts_with(GBS_MAGIC) || file.len() < HEADER_SIZE {
+            return Err("Not a GBS file".to_string());
+        }
+        let read_u16 = |offset: usize| -> u16 { u16::from_le_bytes([file[offset], file[offset + 1]]) };
+        let read_string = |offset: usize| -> String {
+            file[offset..offset + 0x20].iter().take_while(|&&byte| byte != 0).map(|&byte| byte as char).collect()
+        };
+        let gbs: GbsFile = GbsFile {
+            song_count: file[0x04],
+            first_song: file[0x05].saturating_sub(1),
+            load_address: read_u16(0x06),
+            init_address: read_u16(0x08),
+            play_address: read_u16(0x0a),
+            stack_pointer: read_u16(0x0c),
+            timer_modulo: file[0x0e],
+            timer_control: file[0x0f],
+            title: read_string(0x10),
+            author: read_string(0x30),
+            copyright: read_string(0x50),
+            data: file[HEADER_SIZE..].to_vec(),
+        };
+        if gbs.load_address < MIN_LOAD_ADDRESS {
+            return Err(format!("Load address {:04x} overlaps the player code", gbs.load_address));
+        }
+        if gbs.song_count == 0 {
+            return Err("No songs".to_string());
+        }
+        if !(1..=gbs.song_count).contains(&file[0x05]) {
+            return Err(format!("First song {} isn't one of the {} songs", file[0x05], gbs.song_count));
+        }
+        Ok(gbs)
+    }
+
+    // PLAY runs from the timer interrupt when TAC enables the timer, from VBlank otherwise
+    fn uses_timer(&self) -> bool {
+        bit_logic::check_bit(self.timer_control, 2)
+    }
+
+    // The data at its load address, RST vectors jumping to their counterparts relative to it,
+    // PLAY called from the interrupt handler and a driver that calls INIT and waits for interrupts
+    fn create_rom(&self, song: u8) -> Vec<u8> {
+        let size: usize = (self.load_address as usize + self.data.len()).div_ceil(0x4000) * 0x4000;
+        let mut rom: Vec<u8> = vec![0xff; size.max(0x8000)];
+        let load_address: usize = self.load_address as usize;
+        rom[load_address..load_address + self.data.len()].copy_from_slice(&self.data);
+        for vector in (0..VBLANK_VECTOR).step_by(8) {
+            let [low, high] = (self.load_address + vector as u16).to_le_bytes();
+            rom[vector..vector + 3].copy_from_slice(&[OPCODE_JP, low, high]);
+        }
+        for vector in (VBLANK_VECTOR..DRIVER_ADDRESS as usize).step_by(8) {
+            rom[vector] = OPCODE_RETI;
+        }
+        let play_vector: usize = if self.uses_timer() { TIMER_VECTOR } else { VBLANK_VECTOR };
+        let [play_low, play_high] = self.play_address.to_le_bytes();
+        rom[play_vector..play_vector + 4].copy_from_slice(&[OPCODE_CALL, play_low, play_high, OPCODE_RETI]);
+        let [sp_low, sp_high] = self.stack_pointer.to_le_bytes();
+        let [init_low, init_high] = self.init_address.to_le_bytes();
+        let interrupt_enable: u8 = if self.uses_timer() { 0x04 } else { 0x01 };
+        let driver: [u8; 24] = [
+            0x31, sp_low, sp_high, // ld sp, stack pointer
+            0x3e, interrupt_enable, 0xe0, 0xff, // ld a, interrupt; ldh (IE), a
+            0x3e, self.timer_modulo, 0xe0, 0x06, // ld a, modulo; ldh (TMA), a
+            0x3e, self.timer_control, 0xe0, 0x07, // ld a, control; ldh (TAC), a
+            0x3e, song, // ld a, song
+            OPCODE_CALL, init_low, init_high, // call INIT
+            0xfb, // ei
+            0x76, // halt
+            0x18, 0xfd, // jr halt
+        ];
+        let driver_address: usize = DRIVER_ADDRESS as usize;
+        rom[driver_address..driver_address + driver.len()].copy_from_slice(&driver);
+        rom
+    }
+}
+
+impl Gameboy {
+    // Restarts a DMG from scratch with the song selected, the APU is power cycled so no notes carry over
+    pub(crate) fn start_gbs_song(&mut self, gbs: &GbsFile, song: u8) {
+        self.bus = Bus::new();
+        self.bus.mapper = mapper::for_gbs(gbs.create_rom(song));
+        self.header = vec![0; 0x150];
+        self.skip_boot_rom(Model::Dmg);
+        self.write_to_memory(0xff26, 0x00);
+        self.write_to_memory(0xff26, 0x80);
+        self.write_to_memory(0xff24, 0x77);
+        self.write_to_memory(0xff25, 0xf3);
+        self.halted = false;
+        self.interrupts_enabled = false;
+        self.pending_interrupt_enable = false;
+        self.pc = DRIVER_ADDRESS;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_file(song_count: u8, first_song: u8, load_address: u16) -> Vec<u8> {
+        let mut file: Vec<u8> = vec![0; HEADER_SIZE];
+        file[0..3].copy_from_slice(GBS_MAGIC);
+        file[0x03] = 1;
+        file[0x04] = song_count;
+        file[0x05] = first_song;
+        file[0x06..0x08].copy_from_slice(&load_address.to_le_bytes());
+        file[0x08..0x0a].copy_from_slice(&(load_address + 0x10).to_le_bytes());
+        file[0x0a..0x0c].copy_from_slice(&(load_address + 0x20).to_le_bytes());
+        file[0x0c..0x0e].copy_from_slice(&0xdffeu16.to_le_bytes());
+        file[0x10..0x15].copy_from_slice(b"Title");
+        file.extend_from_slice(&[0x00; 0x30]);
+        file
+    }
+
+    #[test]
+    fn header_is_parsed() {
+        let gbs: GbsFile = GbsFile::parse(&create_file(12, 3, 0x400)).unwrap();
+        assert_eq!(gbs.song_count, 12);
+        assert_eq!(gbs.first_song, 2);
+        assert_eq!(gbs.load_address, 0x400);
+        assert_eq!(gbs.init_address, 0x410);
+        assert_eq!(gbs.play_address, 0x420);
+        assert_eq!(gbs.title, "Title");
+        assert_eq!(gbs.data.len(), 0x30);
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected() {
+        assert!(GbsFile::parse(&create_file(12, 1, 0x400)[..HEADER_SIZE - 1]).is_err());
+        let mut file: Vec<u8> = create_file(12, 1, 0x400);
+        file[0] = b'X';
+        assert!(GbsFile::parse(&file).is_err());
+        // Load addresses below 0x400 overlap the player
+        assert!(GbsFile::parse(&create_file(12, 1, 0x3ff)).is_err());
+        assert!(GbsFile::parse(&create_file(12, 1, 0x80)).is_err());
+        // The first song counts from 1 and has to exist
+        assert!(GbsFile::parse(&create_file(12, 0, 0x400)).is_err());
+        assert!(GbsFile::parse(&create_file(12, 13, 0x400)).is_err());
+        assert!(GbsFile::parse(&create_file(12, 12, 0x400)).is_ok());
+        assert!(GbsFile::parse(&create_file(0, 1, 0x400)).is_err());
+    }
+
+    #[test]
+    fn rom_jumps_to_the_driver_routines() {
+        let gbs: GbsFile = GbsFile::parse(&create_file(12, 1, 0x400)).unwrap();
+        let rom: Vec<u8> = gbs.create_rom(5);
+        assert_eq!(rom[0x08..0x0b], [OPCODE_JP, 0x08, 0x04]);
+        assert_eq!(rom[VBLANK_VECTOR..VBLANK_VECTOR + 4], [OPCODE_CALL, 0x20, 0x04, OPCODE_RETI]);
+        assert_eq!(rom[TIMER_VECTOR], OPCODE_RETI);
+        let driver: usize = DRIVER_ADDRESS as usize;
+        assert_eq!(rom[driver + 15..driver + 20], [0x3e, 5, OPCODE_CALL, 0x10, 0x04]);
+    }
+}
@@ -1,9 +1,16 @@
 use super::bit_logic;
 use super::WIDTH;
 use super::gameboy::{SCANLINE_COUNTER_START, Gameboy};
+use super::palette::{self, Color};
+
+const LCDC: u16 = 0xff40;
+const STAT: u16 = 0xff41;
+const LY: u16 = 0xff44;
+const LYC: u16 = 0xff45;
 
 const VERTICAL_BLANK_SCAN_LINE: u8 = 144;
 const VERTICAL_BLANK_SCAN_LINE_MAX: u8 = 153;
+const LINE_153_LY_BOUNDS: i32 = 452; // 456 - 4
 
 impl Gameboy {
     fn get_color(&self, address: u16, color_num: u8) -> u8 {
@@ -26,234 +33,390 @@ impl Gameboy {
         }
     }
 
-    fn render_tiles(&mut self) {
-        let mut unsig: bool = true;
-        let (lcd_control, scroll_y, scroll_x, window_y, window_x): (u8, u8, u8, u8, u8) = (self.read_from_memory(0xff40), self.read_from_memory(0xff42), self.read_from_memory(0xff43), self.read_from_memory(0xff4a), self.read_from_memory(0xff4b) - 7);
-        let ff44 = self.read_from_memory(0xff44);
-        let using_window: bool = bit_logic::check_bit(lcd_control, 5) && window_y <= ff44;
+    fn get_tile_color_num(&self, bank: u8, tile_location: u16, line: u8, column: u8) -> u8 {
+        let temp_address: u16 = tile_location + (line as u16) * 2;
+        let (data_1, data_2): (u8, u8) = (self.read_from_vram(bank, temp_address), self.read_from_vram(bank, temp_address + 1));
+        let color_bit: u8 = 7 - column;
+        (bit_logic::bit_value(data_2, color_bit) << 1) | bit_logic::bit_value(data_1, color_bit)
+    }
 
-        let tile_data: u16 = if bit_logic::check_bit(lcd_control, 4) {
-            0x8000
-        } else {
-            unsig = false;
-            0x8800
-        };
+    fn get_cgb_color(&self, palette_ram: &[u8; 64], palette: u8, color_num: u8) -> Color {
+        let index: usize = (palette as usize) * 8 + (color_num as usize) * 2;
+        palette::cgb_color_to_rgb(bit_logic::compose_bytes(palette_ram[index], palette_ram[index + 1]))
+    }
 
-        let background_memory: u16 = if !using_window {
-            if bit_logic::check_bit(lcd_control, 3) {
-                0x9c00
-            } else {
-                0x9800
-            }
-        } else if bit_logic::check_bit(lcd_control, 6) {
-            0x9c00
-        } else {
-            0x9800
-        };
+    fn set_pixel(&mut self, pixel: u16, scanline: u8, shade: u8, (red, green, blue): Color) {
+        self.screen_shades[(pixel as usize) + (scanline as usize) * (WIDTH as usize)] = shade;
+        let xy: usize = (pixel as usize) * 3 + (scanline as usize) * (WIDTH as usize) * 3;
+        self.screen_data[xy] = red;
+        self.screen_data[xy + 1] = green;
+        self.screen_data[xy + 2] = blue;
+    }
 
-        let y_pos: u8 = if !using_window {
-            scroll_y + ff44
-        } else {
-            ff44 - window_y
-        };
+    fn render_tiles(&mut self) {
+        let (lcd_control, scroll_y, scroll_x, window_y, window_x): (u8, u8, u8, u8, i16) = (self.read_from_memory(LCDC), self.read_from_memory(0xff42), self.read_from_memory(0xff43), self.read_from_memory(0xff4a), (self.read_from_memory(0xff4b) as i16) - 7);
+        let ff44 = self.read_from_memory(LY);
+        let using_window: bool = bit_logic::check_bit(lcd_control, 5) && window_y <= ff44;
+        let unsig: bool = bit_logic::check_bit(lcd_control, 4);
 
-        let tile_row: u16 = (y_pos as u16) / 8 * 32;
-        let mut x_pos: u8;
-        let mut tile_address: u16;
-        let mut tile_num: i16;
-        let mut tile_location: u16;
-        let mut color_bit: i32;
-        let mut color_num: i32;
-        let line: u8 = (y_pos % 8) * 2;
-        let mut xy: u32;
         for pixel in 0..WIDTH {
-            x_pos = (pixel + (scroll_x as u16)) as u8;
-            if using_window && pixel >= (window_x as u16) {
-                x_pos = (pixel - (window_x as u16)) as u8;
-            }
-            tile_address = background_memory + tile_row + (x_pos / 8) as u16;
-            tile_num = if unsig {
-                self.read_from_memory(tile_address) as i16
+            let (background_memory, x_pos, y_pos): (u16, u8, u8) = if using_window && (pixel as i16) >= window_x {
+                (if bit_logic::check_bit(lcd_control, 6) { 0x9c00 } else { 0x9800 }, ((pixel as i16) - window_x) as u8, ff44 - window_y)
             } else {
-                (self.read_from_memory(tile_address) as i8) as i16
+                (if bit_logic::check_bit(lcd_control, 3) { 0x9c00 } else { 0x9800 }, (pixel as u8).wrapping_add(scroll_x), ff44.wrapping_add(scroll_y))
             };
-            tile_location = tile_data;
-            if unsig {
-                tile_location += (tile_num * 16) as u16;
+
+            let tile_address: u16 = background_memory + ((y_pos / 8) as u16) * 32 + (x_pos / 8) as u16;
+            let tile_num: u8 = self.read_from_vram(0, tile_address);
+            // Bank 1 of the tile map holds the CGB attributes
+            let attributes: u8 = if self.cgb_mode { self.read_from_vram(1, tile_address) } else { 0 };
+            let tile_location: u16 = if unsig {
+                0x8000 + (tile_num as u16) * 16
             } else {
-                tile_location += ((tile_num + 128) * 16) as u16;
-            }
-            let (data_1, data_2): (u8, u8) = {
-                let temp_address: u16 = tile_location + (line as u16);
-                (self.read_from_memory(temp_address), self.read_from_memory(temp_address + 1))
+                (0x9000 + ((tile_num as i8) as i32) * 16) as u16
             };
+            let line: u8 = if bit_logic::check_bit(attributes, 6) { 7 - y_pos % 8 } else { y_pos % 8 };
+            let column: u8 = if bit_logic::check_bit(attributes, 5) { 7 - x_pos % 8 } else { x_pos % 8 };
+            let color_num: u8 = self.get_tile_color_num(bit_logic::bit_value(attributes, 3), tile_location, line, column);
 
-            color_bit = -(((x_pos % 8) as i32) - 7);
-            color_num = (if bit_logic::check_bit(data_2, color_bit as u8) { 1 } else { 0 } << 1) | if bit_logic::check_bit(data_1, color_bit as u8) { 1 } else { 0 };
-
-            let (red, green, blue): (u8, u8, u8) = match self.get_color(0xff47, color_num as u8) {
-                0 => (255, 255, 255),
-                1 => (0xcc, 0xcc, 0xcc),
-                2 => (0x77, 0x77, 0x77),
-                _ => (0, 0, 0),
+            let shade: u8 = self.get_color(0xff47, color_num);
+            let color: Color = if self.cgb_mode {
+                self.get_cgb_color(&self.bg_palette_ram, attributes & 0x7, color_num)
+            } else if self.cgb_compatibility {
+                self.get_cgb_color(&self.bg_palette_ram, 0, shade)
+            } else {
+                self.palette.bg[shade as usize]
             };
 
-            if ff44 > 143 || pixel > 159 {
-                continue;
-            }
-
-            self.scanline_bg[pixel as usize] = red == 255;
+            self.scanline_bg[pixel as usize] = color_num == 0;
+            self.scanline_bg_priority[pixel as usize] = bit_logic::check_bit(attributes, 7);
+            self.set_pixel(pixel, ff44, shade, color);
+        }
+    }
 
-            xy = (pixel as u32) * 3 + (ff44 as u32) * (WIDTH as u32) * 3;
-            self.screen_data[xy as usize] = red;
-            self.screen_data[(xy + 1) as usize] = green;
-            self.screen_data[(xy + 2) as usize] = blue;
+    fn render_blank_tiles(&mut self) {
+        let ff44 = self.read_from_memory(LY);
+        for pixel in 0..WIDTH {
+            self.scanline_bg[pixel as usize] = true;
+            self.scanline_bg_priority[pixel as usize] = false;
+            self.set_pixel(pixel, ff44, 0, self.palette.bg[0]);
         }
     }
 
     fn render_sprites(&mut self) {
-        let y_size: i32 = if bit_logic::check_bit(self.read_from_memory(0xff40), 2) { 16 } else { 8 };
-        let mut temp_address: u16;
-        let mut scanline: i32;
-        let mut line: i32;
-        let mut data_address: u16;
-        let mut color_bit: i32;
-        let mut color_num: u32;
-        let mut pixel: u32;
-        let mut xy: i64;
-        for sprite in 0u16..40 {
-            temp_address = 0xfe00 + (sprite * 4);
-            let (y_pos, x_pos, tile_location, attributes): (u8, u8, u8, u8) =
-                (self.read_from_memory(temp_address) - 16,
-                self.read_from_memory(temp_address + 1) - 8,
+        let lcd_control: u8 = self.read_from_memory(LCDC);
+        let y_size: i32 = if bit_logic::check_bit(lcd_control, 2) { 16 } else { 8 };
+        let scanline: u8 = self.read_from_memory(LY);
+
+        // Only the first 10 sprites in OAM that cover the line are drawn
+        let mut sprites: Vec<u16> = (0u16..40)
+            .map(|sprite| 0xfe00 + sprite * 4)
+            .filter(|&temp_address| {
+                let y_pos: i32 = (self.read_from_memory(temp_address) as i32) - 16;
+                (scanline as i32) >= y_pos && (scanline as i32) < y_pos + y_size
+            })
+            .take(10)
+            .collect();
+        // The CGB prioritizes by OAM index only, the DMG by X first (the stable sort keeps OAM order on ties)
+        if !self.cgb_mode {
+            sprites.sort_by_key(|&temp_address| self.read_from_memory(temp_address + 1));
+        }
+
+        let mut sprite_drawn: [bool; WIDTH as usize] = [false; WIDTH as usize];
+        for temp_address in sprites {
+            let (y_pos, x_pos, tile_location, attributes): (i32, i32, u8, u8) =
+                ((self.read_from_memory(temp_address) as i32) - 16,
+                (self.read_from_memory(temp_address + 1) as i32) - 8,
                 self.read_from_memory(temp_address + 2),
                 self.read_from_memory(temp_address + 3));
-            
-            scanline = self.read_from_memory(0xff44) as i32;
-            if (scanline >= (y_pos as i32)) && (scanline < ((y_pos as i32) + y_size)) {
-                line = scanline - (y_pos as i32);
 
-                if bit_logic::check_bit(attributes, 6) {
-                    line = -(line - y_size);
+            let mut line: i32 = (scanline as i32) - y_pos;
+            if bit_logic::check_bit(attributes, 6) {
+                line = y_size - 1 - line;
+            }
+            let data_address: u16 = 0x8000 + (if y_size == 16 { tile_location & 0xfe } else { tile_location } as u16) * 16;
+            let bank: u8 = if self.cgb_mode { bit_logic::bit_value(attributes, 3) } else { 0 };
+
+            for tile_pixel in 0u8..8 {
+                let pixel: i32 = x_pos + (tile_pixel as i32);
+                if pixel < 0 || pixel >= WIDTH as i32 || sprite_drawn[pixel as usize] {
+                    continue;
                 }
+                let column: u8 = if bit_logic::check_bit(attributes, 5) { 7 - tile_pixel } else { tile_pixel };
+                let color_num: u8 = self.get_tile_color_num(bank, data_address, line as u8, column);
+                if color_num == 0 {
+                    continue;
+                }
+                // A higher priority sprite hides the ones below it even when the background covers it
+                sprite_drawn[pixel as usize] = true;
 
-                data_address = 0x8000 + (tile_location as u16) * 16 + (line * 2) as u16;
-                let (data_1, data_2): (u8, u8) = (self.read_from_memory(data_address), self.read_from_memory(data_address + 1));
-                for tile_pixel in (0u8..=7).rev() {
-                    color_bit = tile_pixel as i32;
-                    if bit_logic::check_bit(attributes, 5) {
-                        color_bit = -(color_bit - 7);
-                    }
-                    color_num = (if bit_logic::check_bit(data_2, color_bit as u8) { 1 } else { 0 } << 1) | if bit_logic::check_bit(data_1, color_bit as u8) { 1 } else { 0 };
-                    
-                    let (red, green, blue): (u8, u8, u8) = match self.get_color(if bit_logic::check_bit(attributes, 4) { 0xff49 } else { 0xff48 }, color_num as u8) {
-                        0 => (255, 255, 255),
-                        1 => (0xcc, 0xcc, 0xcc),
-                        2 => (0x77, 0x77, 0x77),
-                        _ => (0, 0, 0),
-                    };
-
-                    if red == 255 {
-                        continue;
-                    }
-
-                    pixel = (x_pos as u32) + (7_u32 - (tile_pixel as u32));
-                    if pixel > 159 || scanline <= 0 || scanline > 143  {
-                        continue;
-                    }
-
-                    if self.scanline_bg[pixel as usize] || !bit_logic::check_bit(attributes, 7) {
-                        xy = (pixel as i64) * 3 + (scanline as i64) * (WIDTH as i64) * 3;
-                        self.screen_data[xy as usize] = red;
-                        self.screen_data[(xy + 1) as usize] = green;
-                        self.screen_data[(xy + 2) as usize] = blue;
-                    }
+                let above_bg: bool = self.scanline_bg[pixel as usize] || if self.cgb_mode {
+                    // LCDC bit 0 is the master priority on CGB
+                    !bit_logic::check_bit(lcd_control, 0) || (!bit_logic::check_bit(attributes, 7) && !self.scanline_bg_priority[pixel as usize])
+                } else {
+                    !bit_logic::check_bit(attributes, 7)
+                };
+                if !above_bg {
+                    continue;
                 }
+
+                let (shade, colors): (u8, [Color; 4]) = if bit_logic::check_bit(attributes, 4) {
+                    (self.get_color(0xff49, color_num), self.palette.obj1)
+                } else {
+                    (self.get_color(0xff48, color_num), self.palette.obj0)
+                };
+                let color: Color = if self.cgb_mode {
+                    self.get_cgb_color(&self.obj_palette_ram, attributes & 0x7, color_num)
+                } else if self.cgb_compatibility {
+                    self.get_cgb_color(&self.obj_palette_ram, bit_logic::bit_value(attributes, 4), shade)
+                } else {
+                    colors[shade as usize]
+                };
+                self.set_pixel(pixel as u16, scanline, shade, color);
             }
         }
     }
 
     fn draw_scanline(&mut self) {
-        let control: u8 =  self.read_from_memory(0xff40);
-        if bit_logic::check_bit(control, 0) {
+        let control: u8 = self.read_from_memory(LCDC);
+        if self.cgb_mode || bit_logic::check_bit(control, 0) {
             self.render_tiles();
+        } else {
+            self.render_blank_tiles();
         }
         if bit_logic::check_bit(control, 1) {
             self.render_sprites();
         }
     }
 
-    fn is_lcd_enabled(&self) -> bool {
-        bit_logic::check_bit(self.read_from_memory(0xff40), 7)
+    pub(crate) fn write_palette_index(index: &mut u8, value: u8) {
+        *index = value & 0xbf;
     }
 
-    fn set_lcd_status(&mut self) {
-        let mut status: u8 = self.read_from_memory(0xff41);
-        if !self.is_lcd_enabled() {
-            self.scanline_counter = SCANLINE_COUNTER_START as i32;
-            self.rom[0xff44_usize] = 0;
-            self.write_to_memory(0xff41, bit_logic::set_bit(status & 252, 0));
-            return;
+    pub(crate) fn write_palette_data(palette_ram: &mut [u8; 64], index: &mut u8, value: u8) {
+        palette_ram[(*index & 0x3f) as usize] = value;
+        // Bit 7 of the index register enables auto-increment after writes
+        if bit_logic::check_bit(*index, 7) {
+            *index = 0x80 | ((*index + 1) & 0x3f);
+        }
+    }
+
+    pub(crate) fn clear_screen(&mut self) {
+        let (red, green, blue): Color = self.palette.bg[0];
+        for pixel in self.screen_data.chunks_exact_mut(3) {
+            pixel.copy_from_slice(&[red, green, blue]);
+        }
+    }
+
+    pub(crate) fn is_lcd_enabled(&self) -> bool {
+        bit_logic::check_bit(self.read_from_memory(LCDC), 7)
+    }
+
+    fn get_lcd_mode(&self) -> u8 {
+        self.bus.io[STAT] & 0x3
+    }
+
+    fn set_lcd_mode(&mut self, mode: u8) {
+        self.bus.io[STAT] = (self.bus.io[STAT] & 0xfc) | mode;
+    }
+
+    // LY reads as 0 for all but the first few cycles of line 153
+    fn get_ly(&self) -> u8 {
+        if self.scanline == VERTICAL_BLANK_SCAN_LINE_MAX && self.scanline_counter < LINE_153_LY_BOUNDS {
+            0
+        } else {
+            self.scanline
         }
-        let current_line: u8 = self.read_from_memory(0xff44);
-        let current_mode: u8 = status & 0x3;
-        let mode: u8;
-        let mut req_int: bool = false;
-
-        if current_line >= 144 {
-            mode = 1;
-            status = bit_logic::reset_bit(bit_logic::set_bit(status, 0), 1);
-            req_int = bit_logic::check_bit(status, 4);
+    }
+
+    // All STAT sources are OR-ed into a single line, the interrupt is only requested on its rising edge
+    fn update_stat_line(&mut self) {
+        let mut status: u8 = self.bus.io[STAT];
+        let coincidence: bool = self.bus.io[LY] == self.bus.io[LYC];
+        status = bit_logic::set_bit_to(coincidence, status, 2);
+        self.bus.io[STAT] = status;
+
+        let line: bool = match status & 0x3 {
+            0 => bit_logic::check_bit(status, 3),
+            // The OAM source also fires when line 144 starts
+            1 => bit_logic::check_bit(status, 4) || (self.scanline == VERTICAL_BLANK_SCAN_LINE && bit_logic::check_bit(status, 5)),
+            2 => bit_logic::check_bit(status, 5),
+            _ => false,
+        } || (coincidence && bit_logic::check_bit(status, 6));
+
+        if line && !self.stat_line {
+            self.request_interrupt(1);
+        }
+        self.stat_line = line;
+    }
+
+    fn set_lcd_status(&mut self) {
+        self.bus.io[LY] = self.get_ly();
+        let current_mode: u8 = self.get_lcd_mode();
+        let mode: u8 = if self.scanline >= VERTICAL_BLANK_SCAN_LINE {
+            1
         } else {
             const MODE_2_BOUNDS: i32 = 376; // 456 - 80
             const MODE_3_BOUNDS: i32 = 204; // mode_2_bounds - 172
             if self.scanline_counter >= MODE_2_BOUNDS {
-                mode = 2;
-                status = bit_logic::reset_bit(bit_logic::set_bit(status, 1), 0);
-                req_int = bit_logic::check_bit(status, 5);
+                2
             } else if self.scanline_counter >= MODE_3_BOUNDS {
-                mode = 3;
-                status = bit_logic::set_bit(bit_logic::set_bit(status, 1), 0);
+                3
             } else {
-                mode = 0;
-                status = bit_logic::reset_bit(bit_logic::reset_bit(status, 1), 0);
-                req_int = bit_logic::check_bit(status, 3);
+                0
+            }
+        };
+        if current_mode != mode {
+            self.set_lcd_mode(mode);
+            if mode == 0 {
+                if !self.lcd_skip_frame {
+                    self.draw_scanline();
+                }
+                if self.hdma_active {
+                    self.do_hdma_block();
+                }
             }
         }
-        if req_int && current_mode != mode {
-            self.request_interrupt(1);
+        self.update_stat_line();
+    }
+
+    pub(crate) fn write_lcd_status(&mut self, value: u8) {
+        if self.is_lcd_enabled() && !self.cgb_mode {
+            // DMG bug: for one cycle the write behaves as if every enable bit but OAM were set
+            self.bus.io[STAT] |= 0x58;
+            self.update_stat_line();
         }
-        if current_line == self.read_from_memory(0xff45) {
-            status = bit_logic::set_bit(status, 2);
-            if bit_logic::check_bit(status, 6) {
-                self.request_interrupt(1);
-            }
-        } else {
-            status = bit_logic::reset_bit(status, 2);
+        self.bus.io[STAT] = (value & 0x78) | (self.bus.io[STAT] & 0x7) | 0x80;
+        self.update_stat_line();
+    }
+
+    // A match made by writing LYC raises the STAT interrupt right away
+    pub(crate) fn write_lcd_y_compare(&mut self, value: u8) {
+        self.bus.io[LYC] = value;
+        if self.is_lcd_enabled() {
+            self.update_stat_line();
+        }
+    }
+
+    pub(crate) fn write_lcd_control(&mut self, value: u8) {
+        let was_enabled: bool = self.is_lcd_enabled();
+        self.bus.io[LCDC] = value;
+        let enabled: bool = bit_logic::check_bit(value, 7);
+        if was_enabled && !enabled {
+            self.scanline = 0;
+            self.scanline_counter = SCANLINE_COUNTER_START as i32;
+            self.bus.io[LY] = 0;
+            self.set_lcd_mode(0);
+            self.stat_line = false;
+            self.clear_screen();
+        } else if !was_enabled && enabled {
+            // The first frame after the LCD is switched on is not displayed
+            self.lcd_skip_frame = true;
+            self.update_stat_line();
         }
-        self.write_to_memory(0xff41, status);
     }
 
     pub(crate) fn update_graphics(&mut self, cycles: u8) {
-        self.set_lcd_status();
-        if self.is_lcd_enabled() {
-            self.scanline_counter -= cycles as i32;
-        } else {
+        if !self.is_lcd_enabled() {
             return;
         }
+        self.scanline_counter -= cycles as i32;
         if self.scanline_counter <= 0 {
-            let current_line = {
-                self.rom[0xff44_usize] += 1;
-                self.read_from_memory(0xff44)
+            self.scanline_counter += SCANLINE_COUNTER_START as i32;
+            self.scanline = if self.scanline == VERTICAL_BLANK_SCAN_LINE_MAX {
+                0
+            } else {
+                self.scanline + 1
             };
-            self.scanline_counter = SCANLINE_COUNTER_START as i32;
-            if current_line == VERTICAL_BLANK_SCAN_LINE {
+            if self.scanline == VERTICAL_BLANK_SCAN_LINE {
                 self.request_interrupt(0);
-            } else if current_line > VERTICAL_BLANK_SCAN_LINE_MAX {
-                self.rom[0xff44_usize] = 0;
-            } else if current_line < VERTICAL_BLANK_SCAN_LINE {
-                self.draw_scanline();
+                self.lcd_skip_frame = false;
+                if self.sgb_mode {
+                    self.update_sgb_frame();
+                }
             }
         }
+        self.set_lcd_status();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_gameboy(cgb_mode: bool) -> Gameboy {
+        let mut gameboy: Gameboy = Gameboy::new();
+        gameboy.cgb_mode = cgb_mode;
+        gameboy.bus.io[LCDC] = 0x80;
+        gameboy.bus.io[STAT] = 0x80;
+        gameboy.bus.io[0xff0f] = 0;
+        gameboy
+    }
+
+    fn take_stat_interrupt(gameboy: &mut Gameboy) -> bool {
+        let requested: bool = bit_logic::check_bit(gameboy.bus.io[0xff0f], 1);
+        gameboy.bus.io[0xff0f] = 0;
+        requested
+    }
+
+    #[test]
+    fn stat_interrupt_fires_on_rising_edge_only() {
+        let mut gameboy: Gameboy = create_gameboy(false);
+        gameboy.bus.io[STAT] |= 0x40;
+        gameboy.bus.io[LY] = 10;
+        gameboy.bus.io[LYC] = 10;
+        gameboy.update_stat_line();
+        assert!(take_stat_interrupt(&mut gameboy));
+        assert!(bit_logic::check_bit(gameboy.bus.io[STAT], 2));
+        gameboy.update_stat_line();
+        assert!(!take_stat_interrupt(&mut gameboy));
+        gameboy.bus.io[LY] = 11;
+        gameboy.update_stat_line();
+        assert!(!take_stat_interrupt(&mut gameboy));
+        gameboy.bus.io[LYC] = 11;
+        gameboy.update_stat_line();
+        assert!(take_stat_interrupt(&mut gameboy));
+    }
+
+    #[test]
+    fn stat_line_held_high_by_another_source_blocks_the_edge() {
+        let mut gameboy: Gameboy = create_gameboy(false);
+        // HBlank source enabled while in mode 0
+        gameboy.bus.io[STAT] |= 0x48;
+        gameboy.bus.io[LY] = 1;
+        gameboy.bus.io[LYC] = 2;
+        gameboy.update_stat_line();
+        assert!(take_stat_interrupt(&mut gameboy));
+        gameboy.bus.io[LYC] = 1;
+        gameboy.update_stat_line();
+        assert!(!take_stat_interrupt(&mut gameboy));
+    }
+
+    #[test]
+    fn lyc_write_raises_coincidence() {
+        let mut gameboy: Gameboy = create_gameboy(true);
+        gameboy.bus.io[STAT] |= 0x40;
+        gameboy.bus.io[LY] = 5;
+        gameboy.write_to_memory(LYC, 5);
+        assert!(bit_logic::check_bit(gameboy.bus.io[STAT], 2));
+        assert!(take_stat_interrupt(&mut gameboy));
+        gameboy.write_to_memory(LYC, 6);
+        assert!(!bit_logic::check_bit(gameboy.bus.io[STAT], 2));
+        assert!(!take_stat_interrupt(&mut gameboy));
+    }
+
+    #[test]
+    fn stat_write_quirk_is_dmg_only() {
+        let mut gameboy: Gameboy = create_gameboy(false);
+        gameboy.bus.io[LY] = 1;
+        gameboy.bus.io[LYC] = 2;
+        gameboy.write_lcd_status(0x00);
+        assert!(take_stat_interrupt(&mut gameboy));
+
+        let mut gameboy: Gameboy = create_gameboy(true);
+        gameboy.bus.io[LY] = 1;
+        gameboy.bus.io[LYC] = 2;
+        gameboy.write_lcd_status(0x00);
+        assert!(!take_stat_interrupt(&mut gameboy));
     }
-}
\ No newline at end of file
+}
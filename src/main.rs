@@ -1,69 +1,323 @@
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::thread;
-use std::time::Instant;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use sdl2::audio::{ AudioQueue, AudioSpecDesired };
-use sdl2::controller::Button;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::controller::{Axis, Button};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::pixels::PixelFormatEnum;
 
 pub const WIDTH: u16 = 160;
 pub const HEIGHT: u16 = 144;
+pub const SGB_WIDTH: u16 = 256;
+pub const SGB_HEIGHT: u16 = 224;
 
 pub const CYCLES_PER_SECOND: u32 = 4_194_304;
 pub const FRAMES_PER_SECOND: f64 = 59.727500569606;
 pub const CYCLES_PER_FRAME: f64 = (CYCLES_PER_SECOND as f64) / FRAMES_PER_SECOND;
-pub const TIME_BETWEEN_FRAMES_IN_NANOSECONDS: f64 = (1_000.0 / FRAMES_PER_SECOND) * 1_000_000.0;
-pub const DURATION_BETWEEN_FRAMES: Duration = Duration::from_nanos(TIME_BETWEEN_FRAMES_IN_NANOSECONDS as u64);
 
-pub const SAMPLE_RATE: u16 = 44_100;
-pub const TIME_BETWEEN_AUDIO_SAMPLING: u8 = (CYCLES_PER_SECOND / SAMPLE_RATE as u32) as u8;
-
-use std::time::Duration;
+pub const SAMPLE_RATE: u32 = 44_100;
 
 const TAC: u16 = 0xff07;
 
 mod bit_logic;
+mod blip;
+mod boot;
+mod bus;
+mod camera;
 mod cpu;
 mod gameboy;
+mod gbs;
 mod gpu;
+mod mapper;
 mod memory;
+mod mixer;
+mod palette;
+mod patch;
+mod post_process;
+mod recorder;
+mod scope;
+mod sgb;
 mod spu;
+mod sync;
 mod timer;
+mod vgm;
+mod visualizer;
 
+use boot::Model;
 use gameboy::Gameboy;
+use gbs::GbsFile;
+use palette::{ColorCorrection, Palette};
+use post_process::{ColorCorrector, FrameBlender};
+use recorder::{AudioRecorder, SampleFormat};
+use scope::{Scope, ScopeCsvWriter};
+use sync::{FramePacer, RateControl, SyncMode};
+use visualizer::Visualizer;
+
+#[derive(Debug, Default)]
+struct Options {
+    rom_path: Option<PathBuf>,
+    palette_path: Option<PathBuf>,
+    color_correction: Option<ColorCorrection>,
+    frame_blend: bool,
+    sgb: bool,
+    model: Option<Model>,
+    boot_rom_path: Option<PathBuf>,
+    camera_path: Option<PathBuf>,
+    patch_paths: Vec<PathBuf>,
+    sample_rate: Option<u32>,
+    sync_mode: Option<SyncMode>,
+    channel_volumes: Vec<(usize, f32)>,
+    channel_pans: Vec<(usize, f32)>,
+    record_audio_path: Option<PathBuf>,
+    record_stems: bool,
+    record_format: Option<SampleFormat>,
+    record_vgm_path: Option<PathBuf>,
+    gbs_render: Option<(u8, f64)>,
+    visualizer: bool,
+    scope_csv_path: Option<PathBuf>,
+}
+
+fn parse_options() -> Options {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--palette" => { options.palette_path = Some(PathBuf::from(args.next().expect("No palette path given"))) },
+            "--color-correction" => {
+                options.color_correction = Some(ColorCorrection::from_name(&args.next().expect("No color correction given")).expect("Invalid color correction, expected none, accurate, gba or balanced"));
+            },
+            "--frame-blend" => { options.frame_blend = true },
+            "--sgb" => { options.sgb = true },
+            "--model" => {
+                options.model = Some(Model::from_name(&args.next().expect("No model given")).expect("Invalid model, expected dmg0, dmg, mgb, sgb, sgb2, cgb or agb"));
+            },
+            "--boot-rom" => { options.boot_rom_path = Some(PathBuf::from(args.next().expect("No boot ROM path given"))) },
+            "--patch" => { options.patch_paths.push(PathBuf::from(args.next().expect("No patch path given"))) },
+            "--sample-rate" => { options.sample_rate = Some(args.next().and_then(|rate| rate.parse().ok()).expect("Invalid sample rate")) },
+            "--sync" => {
+                options.sync_mode = Some(SyncMode::from_name(&args.next().expect("No sync mode given")).expect("Invalid sync mode, expected audio or video"));
+            },
+            "--channel-volume" => { options.channel_volumes.push(parse_channel_setting(&args.next().expect("No channel volume given"))) },
+            "--channel-pan" => { options.channel_pans.push(parse_channel_setting(&args.next().expect("No channel pan given"))) },
+            "--record-audio" => { options.record_audio_path = Some(PathBuf::from(args.next().expect("No recording path given"))) },
+            "--record-stems" => { options.record_stems = true },
+            "--record-format" => {
+                options.record_format = Some(SampleFormat::from_name(&args.next().expect("No sample format given")).expect("Invalid sample format, expected s16 or f32"));
+            },
+            "--record-vgm" => { options.record_vgm_path = Some(PathBuf::from(args.next().expect("No VGM path given"))) },
+            "--gbs-render" => {
+                let track: u8 = args.next().and_then(|track| track.parse().ok()).filter(|&track| track > 0).expect("Invalid GBS track");
+                let seconds: f64 = args.next().and_then(|seconds| seconds.parse().ok()).expect("Invalid GBS render length");
+                options.gbs_render = Some((track - 1, seconds));
+            },
+            "--visualizer" => { options.visualizer = true },
+            "--scope-csv" => { options.scope_csv_path = Some(PathBuf::from(args.next().expect("No scope CSV path given"))) },
+            "--camera" => { options.camera_path = Some(PathBuf::from(args.next().expect("No camera image path given"))) },
+            _ => { options.rom_path = Some(PathBuf::from(arg)) },
+        }
+    }
+    options
+}
+
+// Sound channel 1 to 4 and a value, like 3:0.5
+fn parse_channel_setting(setting: &str) -> (usize, f32) {
+    setting.split_once(':')
+        .and_then(|(channel, value)| Some((channel.parse::<usize>().ok().filter(|channel| (1..=4).contains(channel))? - 1, value.parse().ok()?)))
+        .expect("Invalid channel setting, expected channel:value with a channel from 1 to 4")
+}
+
+fn start_recording(gameboy: &mut Gameboy, path: &Path, format: SampleFormat, stems: bool) -> Option<AudioRecorder> {
+    match AudioRecorder::create(path, gameboy.audio_resampler.sample_rate, format, stems) {
+        Ok(recorder) => {
+            gameboy.set_recording_enabled(true, stems);
+            eprintln!("Recording audio to {}", path.display());
+            Some(recorder)
+        },
+        Err(error) => {
+            eprintln!("Unable to record audio to {}: {}", path.display(), error);
+            None
+        },
+    }
+}
+
+// Recordings started with R are named after the ROM and the time they started
+fn get_recording_path(rom_path: &Path) -> PathBuf {
+    let time: u64 = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+    let stem: String = rom_path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+    rom_path.with_file_name(format!("{}-{}.wav", stem, time))
+}
+
+fn start_scope_csv(gameboy: &mut Gameboy, path: &Path) -> Option<ScopeCsvWriter> {
+    match ScopeCsvWriter::create(path) {
+        Ok(writer) => {
+            gameboy.scope.get_or_insert_with(Scope::new);
+            Some(writer)
+        },
+        Err(error) => {
+            eprintln!("Unable to dump the scope to {}: {}", path.display(), error);
+            None
+        },
+    }
+}
+
+// The CSV is written every frame, before the scope wraps around
+fn write_scope_csv(gameboy: &Gameboy, scope_csv: &mut Option<ScopeCsvWriter>) {
+    if let (Some(writer), Some(scope)) = (scope_csv.as_mut(), gameboy.scope.as_ref()) {
+        if let Err(error) = writer.write(scope) {
+            eprintln!("Stopped dumping the scope: {}", error);
+            *scope_csv = None;
+        }
+    }
+}
+
+fn start_gbs_song(gameboy: &mut Gameboy, gbs: &GbsFile, song: u8) {
+    gameboy.start_gbs_song(gbs, song);
+    eprintln!("Track {}/{}", song + 1, gbs.song_count);
+}
+
+fn get_gbs_render_path(gbs_path: &Path, song: u8) -> PathBuf {
+    let stem: String = gbs_path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+    gbs_path.with_file_name(format!("{}-{}.wav", stem, song + 1))
+}
+
+// Plays a GBS track for a fixed time as fast as possible, straight into a WAV file
+fn render_gbs(gameboy: &mut Gameboy, gbs: &GbsFile, song: u8, seconds: f64, mut recorder: AudioRecorder, mut scope_csv: Option<ScopeCsvWriter>) {
+    start_gbs_song(gameboy, gbs, song);
+    let frames: u64 = (seconds * FRAMES_PER_SECOND).ceil() as u64;
+    for _ in 0..frames {
+        run_frame(gameboy);
+        gameboy.flush_audio();
+        recorder.write(&gameboy.record_data, &gameboy.stem_data).expect("Unable to write recording");
+        write_scope_csv(gameboy, &mut scope_csv);
+        gameboy.audio_data.clear();
+        gameboy.record_data.clear();
+        gameboy.stem_data.clear();
+    }
+}
 
 fn main() {
-    let rom_path = std::env::args().nth(1).expect("No ROM path given");
+    if std::env::args().nth(1).as_deref() == Some("patch") {
+        patch::create_bps_from_args(std::env::args().skip(2));
+        return;
+    }
+    let options = parse_options();
+    let rom_path = options.rom_path.expect("No ROM path given");
+
+    let mut palettes: Vec<Palette> = palette::PRESETS.iter().map(|(_, preset)| *preset).collect();
+    // "auto" takes the CGB boot ROM palette of the title once the cartridge is loaded
+    let auto_palette: bool = options.palette_path.as_ref().is_some_and(|palette_path| palette_path.as_os_str() == "auto");
+    if let Some(palette_path) = options.palette_path.filter(|_| !auto_palette) {
+        palettes.insert(0, Palette::load_from_path(palette_path).expect("Invalid palette file"));
+    }
+    let mut palette_index: usize = 0;
+    let mut frame_blender = FrameBlender::new(options.frame_blend);
+    let mut color_corrector = ColorCorrector::new(options.color_correction.unwrap_or(ColorCorrection::Accurate));
+
+    let mut gameboy = Gameboy::new();
+    gameboy.palette = palettes[palette_index];
+
+    for (channel, volume) in options.channel_volumes {
+        gameboy.mixer.set_volume(channel, volume);
+    }
+    for (channel, pan) in options.channel_pans {
+        gameboy.mixer.set_pan(channel, Some(pan));
+    }
+    let record_format: SampleFormat = options.record_format.unwrap_or(SampleFormat::Int16);
+
+    let gbs: Option<GbsFile> = rom_path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gbs"))
+        .then(|| GbsFile::load_from_path(&rom_path).expect("Invalid GBS file"));
+    let mut gbs_song: u8 = 0;
+    if gbs.is_none() && options.gbs_render.is_some() {
+        eprintln!("--gbs-render needs a GBS file");
+        std::process::exit(1);
+    }
+    if let Some(gbs) = &gbs {
+        eprintln!("{} - {} ({})", gbs.title, gbs.author, gbs.copyright);
+        if let Some((song, seconds)) = options.gbs_render {
+            if song >= gbs.song_count {
+                eprintln!("Track {} isn't one of the {} tracks", song + 1, gbs.song_count);
+                std::process::exit(1);
+            }
+            let default_path: PathBuf = get_gbs_render_path(&rom_path, song);
+            gameboy.set_sample_rate(options.sample_rate.unwrap_or(SAMPLE_RATE));
+            let Some(recorder) = start_recording(&mut gameboy, &options.record_audio_path.unwrap_or(default_path), record_format, options.record_stems) else {
+                std::process::exit(1);
+            };
+            let scope_csv: Option<ScopeCsvWriter> = options.scope_csv_path.and_then(|path| start_scope_csv(&mut gameboy, &path));
+            render_gbs(&mut gameboy, gbs, song, seconds, recorder, scope_csv);
+            return;
+        }
+        gbs_song = gbs.first_song;
+        start_gbs_song(&mut gameboy, gbs, gbs_song);
+    } else {
+        if let Err(error) = gameboy.load_cartridge_from_path(rom_path.clone(), options.patch_paths) {
+            eprintln!("Unable to load the ROM, {}", error);
+            std::process::exit(1);
+        }
+        if auto_palette {
+            palettes.insert(0, palette::for_title(&gameboy.header));
+            gameboy.palette = palettes[palette_index];
+        }
+        if let Some(camera_path) = options.camera_path {
+            gameboy.bus.mapper.set_image_source(camera::from_path(camera_path).expect("Invalid camera image source"));
+        }
+        let model: Model = options.model.unwrap_or_else(|| gameboy.detect_model(options.sgb));
+        match options.boot_rom_path {
+            Some(boot_rom_path) if boot_rom_path.as_os_str() == "builtin" => { gameboy.load_built_in_boot_rom(model) },
+            Some(boot_rom_path) => {
+                if let Err(error) = gameboy.load_boot_rom_from_path(boot_rom_path, model) {
+                    eprintln!("Skipping the boot ROM, {}", error);
+                    gameboy.skip_boot_rom(model);
+                }
+            },
+            None => { gameboy.skip_boot_rom(model) },
+        }
+    }
+    let (screen_width, screen_height): (u16, u16) = if gameboy.sgb_mode { (SGB_WIDTH, SGB_HEIGHT) } else { (WIDTH, HEIGHT) };
 
     let sdl_context = sdl2::init().expect("Couldn't init sdl");
     let video_subsystem = sdl_context.video().expect("Couldn't init sdl video");
     let audio_subsystem = sdl_context.audio().expect("Couldn't init sdl audio");
     let game_controller_subsystem = sdl_context.game_controller().expect("Couldn't init sdl game_controller");
 
-    let window = video_subsystem.window("RPGBE", WIDTH.into(), HEIGHT.into())
+    let window = video_subsystem.window("RPGBE", screen_width.into(), screen_height.into())
         .position_centered()
         .resizable()
         .build()
         .expect("Couldn't create window from video");
 
-    let mut canvas = window.into_canvas()
-        .accelerated()
-        .build()
-        .expect("Couldn't create canvas from window");
+    if options.record_vgm_path.is_some() {
+        gameboy.start_vgm_logging();
+    }
+    let sync_mode: SyncMode = options.sync_mode.unwrap_or(SyncMode::Audio);
+    let mut canvas_builder = window.into_canvas().accelerated();
+    if sync_mode == SyncMode::Video {
+        canvas_builder = canvas_builder.present_vsync();
+    }
+    let mut canvas = canvas_builder.build().expect("Couldn't create canvas from window");
 
     let texture_creator = canvas.texture_creator();
 
-    let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, WIDTH.into(), HEIGHT.into()).expect("Couldn't create texture from texture_creator.create_texture_streaming");
+    let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, screen_width.into(), screen_height.into()).expect("Couldn't create texture from texture_creator.create_texture_streaming");
 
     let desired_spec = AudioSpecDesired {
-        freq: Some(SAMPLE_RATE as i32),
+        freq: Some(options.sample_rate.unwrap_or(SAMPLE_RATE) as i32),
         channels: Some(2),
         samples: None,
     };
 
     let device: AudioQueue<f32> = audio_subsystem.open_queue(None, &desired_spec).expect("Couldn't get a desired audio device");
+    // The device may not support the rate asked for
+    gameboy.set_sample_rate(device.spec().freq as u32);
+    let rate_control = RateControl::new(device.spec().freq as u32);
+    let mut frame_pacer = FramePacer::new(FRAMES_PER_SECOND);
+
+    let mut recorder: Option<AudioRecorder> = options.record_audio_path
+        .and_then(|path| start_recording(&mut gameboy, &path, record_format, options.record_stems));
     device.resume();
 
     let number_of_joystics = game_controller_subsystem.num_joysticks().expect("Couldn't find any joysticks");
@@ -75,21 +329,96 @@ fn main() {
             game_controller_subsystem.open(id).ok()
         });
 
-    let mut event_pump = sdl_context.event_pump().expect("Couldn't get event_pump from sdl_context");
+    let mut scope_csv: Option<ScopeCsvWriter> = options.scope_csv_path.and_then(|path| start_scope_csv(&mut gameboy, &path));
+    let mut visualizer: Option<Visualizer> = None;
+    if options.visualizer {
+        gameboy.scope.get_or_insert_with(Scope::new);
+        visualizer = Some(Visualizer::new(&video_subsystem));
+    }
 
-    let mut gameboy = Gameboy::new();
+    let mut event_pump = sdl_context.event_pump().expect("Couldn't get event_pump from sdl_context");
 
-    gameboy.load_cartridge_from_path(PathBuf::from(rom_path));
 
-    let mut start: Instant;
-    let mut cycles_this_frame: f64;
-    let mut cycles: u8;
+    // Mouse position or left stick, for cartridges with an accelerometer
+    let mut tilt: (f32, f32) = (0.0, 0.0);
     'running: loop {
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit {..} => {
                     break 'running
                 },
+                // With the visualizer open closing either window no longer quits on its own
+                Event::Window { window_id, win_event: WindowEvent::Close, .. } => {
+                    if visualizer.as_ref().is_some_and(|visualizer| visualizer.get_window_id() == window_id) {
+                        visualizer = None;
+                    } else if window_id == canvas.window().id() {
+                        break 'running
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::V), repeat: false, .. } => {
+                    visualizer = match visualizer.take() {
+                        Some(_) => None,
+                        None => {
+                            gameboy.scope.get_or_insert_with(Scope::new);
+                            Some(Visualizer::new(&video_subsystem))
+                        },
+                    };
+                },
+                Event::KeyDown { keycode: Some(Keycode::P), repeat: false, .. } => {
+                    palette_index = (palette_index + 1) % palettes.len();
+                    gameboy.palette = palettes[palette_index];
+                },
+                Event::KeyDown { keycode: Some(Keycode::C), repeat: false, .. } => {
+                    let index = palette::COLOR_CORRECTIONS.iter().position(|&correction| correction == color_corrector.get_correction()).unwrap_or(0);
+                    color_corrector.set_correction(palette::COLOR_CORRECTIONS[(index + 1) % palette::COLOR_CORRECTIONS.len()]);
+                },
+                Event::KeyDown { keycode: Some(Keycode::R), repeat: false, .. } => {
+                    if recorder.take().is_some() {
+                        gameboy.set_recording_enabled(false, false);
+                        eprintln!("Stopped recording audio");
+                    } else {
+                        recorder = start_recording(&mut gameboy, &get_recording_path(&rom_path), record_format, options.record_stems);
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::L), repeat: false, .. } => {
+                    if let Some(vgm_logger) = gameboy.vgm_logger.as_mut() {
+                        vgm_logger.set_loop_point();
+                        eprintln!("Set the VGM loop point");
+                    }
+                },
+                // Left and right arrows change the GBS track
+                Event::KeyDown { keycode: Some(key_down @ (Keycode::Left | Keycode::Right)), repeat: false, .. } => {
+                    if let Some(gbs) = &gbs {
+                        let song_count: u8 = gbs.song_count.max(1);
+                        gbs_song = if key_down == Keycode::Right { (gbs_song + 1) % song_count } else { (gbs_song + song_count - 1) % song_count };
+                        start_gbs_song(&mut gameboy, gbs, gbs_song);
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::F), repeat: false, .. } => {
+                    frame_blender.enabled = !frame_blender.enabled;
+                },
+                // 1 to 4 mute a sound channel, with shift they solo it
+                Event::KeyDown { keycode: Some(key_down @ (Keycode::Num1 | Keycode::Num2 | Keycode::Num3 | Keycode::Num4)), keymod, repeat: false, .. } => {
+                    let channel: usize = (key_down.into_i32() - Keycode::Num1.into_i32()) as usize;
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        gameboy.mixer.toggle_solo(channel);
+                    } else {
+                        gameboy.mixer.toggle_muted(channel);
+                    }
+                },
+                Event::MouseMotion { x, y, .. } => {
+                    let (window_width, window_height) = canvas.window().size();
+                    tilt = ((x as f32) / (window_width as f32) * 2.0 - 1.0, (y as f32) / (window_height as f32) * 2.0 - 1.0);
+                    gameboy.bus.mapper.set_tilt(tilt.0, tilt.1);
+                },
+                Event::ControllerAxisMotion { axis: Axis::LeftX, value, .. } => {
+                    tilt.0 = (value as f32) / (i16::MAX as f32);
+                    gameboy.bus.mapper.set_tilt(tilt.0, tilt.1);
+                },
+                Event::ControllerAxisMotion { axis: Axis::LeftY, value, .. } => {
+                    tilt.1 = (value as f32) / (i16::MAX as f32);
+                    gameboy.bus.mapper.set_tilt(tilt.0, tilt.1);
+                },
                 Event::KeyDown { keycode: Some(key_down), repeat: false, .. } => {
                     let key_code: i8 = match key_down {
                         Keycode::W => 2, // UP
@@ -158,35 +487,90 @@ fn main() {
             }
         }
 
-        start = Instant::now();
-        cycles_this_frame = 0.0;
-        while cycles_this_frame <= CYCLES_PER_FRAME {
-            cycles = if !gameboy.halted {
-                gameboy.update() * 4
-            } else {
-                4
-            };
-            gameboy.update_timer(cycles);
-            gameboy.update_graphics(cycles);
-            gameboy.update_audio(cycles);
-            cycles_this_frame += (cycles + do_interrupts(&mut gameboy)) as f64;
-        }
+        run_frame(&mut gameboy);
 
-        texture.update(None, &gameboy.screen_data, 480).expect("Couldn't update texture from main");
+        let screen_data: &[u8] = if gameboy.sgb_mode {
+            &gameboy.sgb.screen_data
+        } else if gameboy.cgb_mode || gameboy.cgb_compatibility {
+            color_corrector.process(&gameboy.screen_data)
+        } else {
+            &gameboy.screen_data
+        };
+        texture.update(None, frame_blender.process(screen_data), (screen_width as usize) * 3).expect("Couldn't update texture from main");
         canvas.clear();
         canvas.copy(&texture, None, None).expect("Couldn't copy canvas");
         canvas.present();
+        if let Some(visualizer) = visualizer.as_mut() {
+            visualizer.draw(&gameboy);
+        }
+        write_scope_csv(&gameboy, &mut scope_csv);
 
+        gameboy.flush_audio();
+        if let Some(Err(error)) = recorder.as_mut().map(|recorder| recorder.write(&gameboy.record_data, &gameboy.stem_data)) {
+            eprintln!("Stopped recording audio: {}", error);
+            recorder = None;
+            gameboy.set_recording_enabled(false, false);
+        }
         let _ = device.queue_audio(&gameboy.audio_data);
         gameboy.audio_data.clear();
-
-        let elapsed_time = start.elapsed();
-        if elapsed_time <= DURATION_BETWEEN_FRAMES {
-            thread::sleep(DURATION_BETWEEN_FRAMES - elapsed_time);
+        gameboy.record_data.clear();
+        gameboy.stem_data.clear();
+
+        // 32-bit float samples for two channels
+        let mut queued_samples: u32 = device.size() / 8;
+        if rate_control.is_overfilled(queued_samples) {
+            device.clear();
+            queued_samples = 0;
+        }
+        match sync_mode {
+            SyncMode::Audio => { thread::sleep(rate_control.get_wait(queued_samples)) },
+            SyncMode::Video => {
+                gameboy.set_audio_rate_adjustment(rate_control.get_rate_adjustment(queued_samples));
+                thread::sleep(frame_pacer.get_wait(Instant::now()));
+            },
+        }
+        #[cfg(debug_assertions)]
+        {
+            let mut title: String = format!("RPGBE - audio latency {} ms", rate_control.get_latency(queued_samples).as_millis());
+            if let Some(refresh) = frame_pacer.get_measured_refresh() {
+                title.push_str(&format!(", display {:.1} Hz", refresh));
+            }
+            let _ = canvas.window_mut().set_title(&title);
+        }
+    }
+    gameboy.write_save_file();
+    if let (Some(vgm_path), Some(vgm_logger)) = (options.record_vgm_path, gameboy.vgm_logger.take()) {
+        let title: String = gbs.as_ref().map_or_else(|| gameboy.get_title(), |gbs| gbs.title.clone());
+        if let Err(error) = fs::write(&vgm_path, vgm_logger.finish(&title)) {
+            eprintln!("Unable to write {}: {}", vgm_path.display(), error);
         }
     }
 }
 
+fn run_frame(gameboy: &mut Gameboy) {
+    let mut cycles_this_frame: f64 = 0.0;
+    let mut cycles: u8;
+    let mut lcd_cycles: u8;
+    while cycles_this_frame <= CYCLES_PER_FRAME {
+        // The CPU is stalled while a CGB DMA transfer is running
+        cycles = if gameboy.dma_cycles > 0 {
+            gameboy.dma_cycles -= 4;
+            4
+        } else if !gameboy.halted {
+            gameboy.update() * 4
+        } else {
+            4
+        };
+        // Double speed only affects the CPU and timer, the LCD and sound keep their normal pace
+        lcd_cycles = if gameboy.double_speed { cycles / 2 } else { cycles };
+        gameboy.update_timer(cycles);
+        gameboy.update_graphics(lcd_cycles);
+        gameboy.update_audio(lcd_cycles);
+        gameboy.bus.mapper.tick(lcd_cycles as u32);
+        cycles_this_frame += (lcd_cycles + do_interrupts(gameboy)) as f64;
+    }
+}
+
 fn key_pressed(gameboy: &mut Gameboy, key: u8) {
     let previously_unset: bool = !bit_logic::check_bit(gameboy.gamepad_state, key);
 
@@ -194,7 +578,7 @@ fn key_pressed(gameboy: &mut Gameboy, key: u8) {
 
     let button: bool = key > 3;
 
-    let key_req: u8 = gameboy.rom[0xff00_usize];
+    let key_req: u8 = gameboy.bus.io[0xff00];
     let should_request_interrupt: bool = (button && !bit_logic::check_bit(key_req, 5)) || (!button && !bit_logic::check_bit(key_req, 4));
 
     if should_request_interrupt && !previously_unset {
@@ -227,7 +611,7 @@ fn service_interrupt(gameboy: &mut Gameboy, interrupt_id: u8) {
 
 fn do_interrupts(gameboy: &mut Gameboy) -> u8 {
     let (req, enabled): (u8, u8) = (gameboy.read_from_memory(0xff0f), gameboy.read_from_memory(0xffff));
-    let potential_for_interrupts: u8 = req & enabled;
+    let potential_for_interrupts: u8 = req & enabled & 0x1f;
     if potential_for_interrupts == 0 {
         return 0;
     }
@@ -0,0 +1,237 @@
+use std::fmt::Debug;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::CYCLES_PER_SECOND;
+use super::camera::ImageSource;
+
+mod gbs;
+mod huc1;
+mod huc3;
+mod mbc1;
+mod mbc2;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+mod pocket_camera;
+mod rom_only;
+mod tama5;
+
+use gbs::Gbs;
+use huc1::Huc1;
+use huc3::Huc3;
+use mbc1::Mbc1;
+use mbc2::Mbc2;
+use mbc6::Mbc6;
+use mbc7::Mbc7;
+use mmm01::Mmm01;
+use pocket_camera::PocketCamera;
+use rom_only::RomOnly;
+use tama5::Tama5;
+
+// Cartridge hardware behind 0x0000-0x7fff and 0xa000-0xbfff
+pub(crate) trait Mapper: Debug {
+    fn read_rom(&self, address: u16) -> u8;
+    fn write_control(&mut self, address: u16, value: u8);
+    fn read_ram(&self, address: u16) -> u8;
+    fn write_ram(&mut self, address: u16, value: u8);
+
+    // Battery backed RAM (and clock state) written to the .sav file
+    fn save_data(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn load_save_data(&mut self, _data: &[u8]) {}
+
+    // Called with normal speed T-cycles for mappers with their own clock
+    fn tick(&mut self, _cycles: u32) {}
+
+    // Tilt from -1.0 to 1.0 on both axes, for cartridges with an accelerometer
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+
+    // What the Pocket Camera sensor sees
+    fn set_image_source(&mut self, _image_source: Box<dyn ImageSource>) {}
+}
+
+pub(crate) fn from_cartridge(cartridge: Vec<u8>) -> Box<dyn Mapper> {
+    let header: &[u8] = get_header(&cartridge);
+    let cartridge_type: u8 = header.get(0x147).copied().unwrap_or(0);
+    let ram_size: usize = get_ram_size(header);
+    match cartridge_type {
+        0x01..=0x03 => Box::new(Mbc1::new(cartridge, ram_size, cartridge_type == 0x03)),
+        0x05 | 0x06 => Box::new(Mbc2::new(cartridge, cartridge_type == 0x06)),
+        0x0b..=0x0d => Box::new(Mmm01::new(cartridge, ram_size, cartridge_type == 0x0d)),
+        0x20 => Box::new(Mbc6::new(cartridge)),
+        0x22 => Box::new(Mbc7::new(cartridge)),
+        0xfc => Box::new(PocketCamera::new(cartridge)),
+        0xfd => Box::new(Tama5::new(cartridge)),
+        0xfe => Box::new(Huc3::new(cartridge, ram_size)),
+        0xff => Box::new(Huc1::new(cartridge, ram_size)),
+        _ => Box::new(RomOnly::new(cartridge, ram_size, cartridge_type == 0x09)),
+    }
+}
+
+pub(crate) fn for_gbs(rom: Vec<u8>) -> Box<dyn Mapper> {
+    Box::new(Gbs::new(rom))
+}
+
+// MMM01 multicarts start in the menu in the last 32 KiB, bank 0 holds the header of the first game instead
+fn get_header(cartridge: &[u8]) -> &[u8] {
+    match cartridge.len().checked_sub(0x8000).map(|offset| &cartridge[offset..]) {
+        Some(menu) if matches!(menu.get(0x147), Some(0x0b..=0x0d)) => menu,
+        _ => cartridge,
+    }
+}
+
+fn get_ram_size(cartridge: &[u8]) -> usize {
+    match cartridge.get(0x149) {
+        Some(1) => 0x800,
+        Some(2) => 0x2000,
+        Some(3) => 0x8000,
+        Some(4) => 0x20000,
+        Some(5) => 0x10000,
+        _ => 0,
+    }
+}
+
+// Banks past the end of the ROM wrap around like the unconnected upper address lines
+fn read_rom_bank(rom: &[u8], bank: usize, address: u16) -> u8 {
+    let bank_count: usize = (rom.len() / 0x4000).max(1);
+    rom.get((bank % bank_count) * 0x4000 + (address & 0x3fff) as usize).copied().unwrap_or(0xff)
+}
+
+fn get_ram_offset(ram: &[u8], bank: usize, address: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+    Some(((bank * 0x2000) + (address & 0x1fff) as usize) % ram.len())
+}
+
+fn load_ram(ram: &mut [u8], data: &[u8]) {
+    let length: usize = ram.len().min(data.len());
+    ram[..length].copy_from_slice(&data[..length]);
+}
+
+fn get_unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs())
+}
+
+// Seconds counted by a cartridge clock, it keeps running while the emulator is closed
+#[derive(Debug)]
+pub(crate) struct RealTimeClock {
+    pub(crate) seconds: u64,
+    cycles: u32,
+}
+
+impl RealTimeClock {
+    pub(crate) fn new() -> RealTimeClock {
+        RealTimeClock { seconds: 0, cycles: 0 }
+    }
+
+    pub(crate) fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles;
+        if self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.seconds += 1;
+        }
+    }
+
+    // Appended to the save data as the clock value and the time it was saved at
+    pub(crate) fn save(&self, data: &mut Vec<u8>) {
+        data.extend_from_slice(&self.seconds.to_le_bytes());
+        data.extend_from_slice(&get_unix_time().to_le_bytes());
+    }
+
+    pub(crate) fn load(&mut self, data: &[u8]) {
+        if data.len() < 16 {
+            return;
+        }
+        let seconds: u64 = u64::from_le_bytes(data[0..8].try_into().unwrap());
+        let saved_at: u64 = u64::from_le_bytes(data[8..16].try_into().unwrap());
+        self.seconds = seconds + get_unix_time().saturating_sub(saved_at);
+    }
+}
+
+// Every byte of a bank holds the bank number, so any read shows which bank is mapped
+#[cfg(test)]
+fn create_test_rom(banks: usize) -> Vec<u8> {
+    (0..banks).flat_map(|bank| [bank as u8; 0x4000]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_cartridge(cartridge_type: u8, ram_size: u8) -> Vec<u8> {
+        let mut rom: Vec<u8> = create_test_rom(128);
+        rom[0x147] = cartridge_type;
+        rom[0x149] = ram_size;
+        rom
+    }
+
+    fn get_mapper_name(mapper: &dyn Mapper) -> String {
+        format!("{:?}", mapper).chars().take_while(|character| character.is_alphanumeric()).collect()
+    }
+
+    // Loads patterned save data into a new mapper and checks it saves the same again
+    fn assert_save_round_trips(cartridge: Vec<u8>, length: usize, clock: bool) {
+        let mut mapper: Box<dyn Mapper> = from_cartridge(cartridge);
+        let mut data: Vec<u8> = (0..length).map(|i| (i * 7) as u8).collect();
+        if clock {
+            data.extend_from_slice(&1000u64.to_le_bytes());
+            data.extend_from_slice(&get_unix_time().to_le_bytes());
+        }
+        mapper.load_save_data(&data);
+        let saved: Vec<u8> = mapper.save_data().expect("Mapper has no save data");
+        assert_eq!(saved.len(), data.len());
+        assert_eq!(saved[..length], data[..length]);
+        if clock {
+            let seconds: u64 = u64::from_le_bytes(saved[length..length + 8].try_into().unwrap());
+            assert!((1000..=1001).contains(&seconds));
+        }
+    }
+
+    #[test]
+    fn cartridge_type_picks_the_mapper() {
+        for (cartridge_type, name) in [
+            (0x00, "RomOnly"),
+            (0x03, "Mbc1"),
+            (0x06, "Mbc2"),
+            (0x0d, "Mmm01"),
+            (0x20, "Mbc6"),
+            (0x22, "Mbc7"),
+            (0xfc, "PocketCamera"),
+            (0xfd, "Tama5"),
+            (0xfe, "Huc3"),
+            (0xff, "Huc1"),
+        ] {
+            assert_eq!(get_mapper_name(from_cartridge(create_cartridge(cartridge_type, 0)).as_ref()), name);
+        }
+    }
+
+    #[test]
+    fn mmm01_is_found_by_the_menu_header() {
+        let mut rom: Vec<u8> = create_cartridge(0x01, 0x00);
+        let menu: usize = rom.len() - 0x8000;
+        rom[menu + 0x147] = 0x0d;
+        rom[menu + 0x149] = 0x03;
+        let mapper: Box<dyn Mapper> = from_cartridge(rom);
+        assert_eq!(get_mapper_name(mapper.as_ref()), "Mmm01");
+        assert_eq!(mapper.save_data().map(|data| data.len()), Some(0x8000));
+        // The menu boots from the last 32 KiB
+        assert_eq!(mapper.read_rom(0x0000), 126);
+        assert_eq!(mapper.read_rom(0x4000), 127);
+    }
+
+    #[test]
+    fn save_memory_and_clocks_persist() {
+        assert_save_round_trips(create_cartridge(0x03, 0x03), 0x8000, false);
+        assert_save_round_trips(create_cartridge(0x06, 0x00), 0x200, false);
+        assert_save_round_trips(create_cartridge(0x0d, 0x04), 0x20000, false);
+        assert_save_round_trips(create_cartridge(0x20, 0x00), 0x8000 + 0x100000, false);
+        assert_save_round_trips(create_cartridge(0x22, 0x00), 0x100, false);
+        assert_save_round_trips(create_cartridge(0xfc, 0x00), 0x20000, false);
+        assert_save_round_trips(create_cartridge(0xfd, 0x00), 0x20, true);
+        assert_save_round_trips(create_cartridge(0xfe, 0x03), 0x8000 + 0x100, true);
+        assert_save_round_trips(create_cartridge(0xff, 0x03), 0x8000, false);
+    }
+}
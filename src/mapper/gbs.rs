@@ -0,0 +1,38 @@
+use super::{Mapper, read_rom_bank};
+
+// What GBS rips expect, banks switched by writes to 0x2000-0x3fff like MBC1 and 8 KiB of RAM that is always enabled
+#[derive(Debug)]
+pub(crate) struct Gbs {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank: u8,
+}
+
+impl Gbs {
+    pub(crate) fn new(rom: Vec<u8>) -> Gbs {
+        Gbs { rom, ram: vec![0; 0x2000], rom_bank: 1 }
+    }
+}
+
+impl Mapper for Gbs {
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(&self.rom, 0, address),
+            _ => read_rom_bank(&self.rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_control(&mut self, address: u16, value: u8) {
+        if let 0x2000..=0x3fff = address {
+            self.rom_bank = value.max(1);
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        self.ram[(address & 0x1fff) as usize]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        self.ram[(address & 0x1fff) as usize] = value;
+    }
+}
@@ -0,0 +1,113 @@
+use super::{Mapper, get_ram_offset, load_ram, read_rom_bank};
+
+// Reads of the IR receiver, bit 0 would be set while light is seen. The IR port is a stub, no second
+// Game Boy is emulated so the receiver never sees light, not even the cartridge's own LED
+pub(crate) const IR_NO_LIGHT: u8 = 0xc0;
+
+#[derive(Debug)]
+pub(crate) struct Huc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    // 0x0e maps the IR port to 0xa000-0xbfff instead of RAM
+    ir_mode: bool,
+    // Stored but not sent anywhere
+    ir_led: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+}
+
+impl Huc1 {
+    pub(crate) fn new(rom: Vec<u8>, ram_size: usize) -> Huc1 {
+        Huc1 { rom, ram: vec![0; ram_size.min(0x8000)], ir_mode: false, ir_led: false, rom_bank: 1, ram_bank: 0 }
+    }
+}
+
+impl Mapper for Huc1 {
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(&self.rom, 0, address),
+            _ => read_rom_bank(&self.rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_control(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1fff => { self.ir_mode = value & 0xf == 0xe },
+            0x2000..=0x3fff => { self.rom_bank = value & 0x3f },
+            0x4000..=0x5fff => { self.ram_bank = value & 0x3 },
+            _ => {},
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if self.ir_mode {
+            return IR_NO_LIGHT;
+        }
+        get_ram_offset(&self.ram, self.ram_bank as usize, address).map_or(0xff, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ir_mode {
+            self.ir_led = value & 0x1 == 1;
+            return;
+        }
+        if let Some(offset) = get_ram_offset(&self.ram, self.ram_bank as usize, address) {
+            self.ram[offset] = value;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.ram.clone())
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::create_test_rom;
+
+    #[test]
+    fn rom_banks_switch() {
+        let mut huc1: Huc1 = Huc1::new(create_test_rom(64), 0x8000);
+        assert_eq!(huc1.read_rom(0x4000), 0x01);
+        huc1.write_control(0x2000, 0x3f);
+        assert_eq!(huc1.read_rom(0x4000), 0x3f);
+        huc1.write_control(0x2000, 0x40);
+        assert_eq!(huc1.read_rom(0x4000), 0x00);
+        assert_eq!(huc1.read_rom(0x0000), 0x00);
+    }
+
+    #[test]
+    fn ram_banks_switch_and_ir_mode_hides_ram() {
+        let mut huc1: Huc1 = Huc1::new(create_test_rom(4), 0x8000);
+        huc1.write_control(0x0000, 0x0a);
+        huc1.write_control(0x4000, 0x03);
+        huc1.write_ram(0xa000, 0x12);
+        huc1.write_control(0x4000, 0x00);
+        assert_eq!(huc1.read_ram(0xa000), 0x00);
+        huc1.write_control(0x4000, 0x03);
+        assert_eq!(huc1.read_ram(0xa000), 0x12);
+        huc1.write_control(0x0000, 0x0e);
+        assert_eq!(huc1.read_ram(0xa000), IR_NO_LIGHT);
+        // Writes go to the IR LED, not to RAM
+        huc1.write_ram(0xa000, 0x01);
+        assert!(huc1.ir_led);
+        huc1.write_control(0x0000, 0x0a);
+        assert_eq!(huc1.read_ram(0xa000), 0x12);
+    }
+
+    #[test]
+    fn battery_ram_is_saved() {
+        let mut huc1: Huc1 = Huc1::new(create_test_rom(4), 0x8000);
+        huc1.write_control(0x4000, 0x02);
+        huc1.write_ram(0xa123, 0x5a);
+        let mut loaded: Huc1 = Huc1::new(create_test_rom(4), 0x8000);
+        loaded.load_save_data(&huc1.save_data().unwrap());
+        loaded.write_control(0x4000, 0x02);
+        assert_eq!(loaded.read_ram(0xa123), 0x5a);
+    }
+}
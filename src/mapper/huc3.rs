@@ -0,0 +1,219 @@
+use super::{Mapper, RealTimeClock, get_ram_offset, load_ram, read_rom_bank};
+use super::huc1::IR_NO_LIGHT;
+
+const MINUTES_PER_DAY: u64 = 1440;
+
+#[derive(Debug)]
+pub(crate) struct Huc3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    // Low nibble written to 0x0000-0x1fff, picks what 0xa000-0xbfff talks to
+    mode: u8,
+    rom_bank: u8,
+    ram_bank: u8,
+    // Nibble wide memory of the RTC chip, 0x00-0x02 hold the minute and 0x03-0x05 the day while latched
+    rtc_memory: [u8; 0x100],
+    rtc_index: u8,
+    rtc_command: u8,
+    rtc_result: u8,
+    // Tone picked by command 0x6e, only stored since the piezo speaker isn't emulated
+    speaker_tone: u8,
+    clock: RealTimeClock,
+}
+
+impl Huc3 {
+    pub(crate) fn new(rom: Vec<u8>, ram_size: usize) -> Huc3 {
+        Huc3 {
+            rom,
+            ram: vec![0; ram_size.min(0x8000)],
+            mode: 0,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc_memory: [0; 0x100],
+            rtc_index: 0,
+            rtc_command: 0,
+            rtc_result: 0,
+            speaker_tone: 0,
+            clock: RealTimeClock::new(),
+        }
+    }
+
+    fn latch_clock(&mut self) {
+        let minutes: u64 = self.clock.seconds / 60;
+        let (minute, day): (u64, u64) = (minutes % MINUTES_PER_DAY, (minutes / MINUTES_PER_DAY) & 0xfff);
+        for i in 0..3 {
+            self.rtc_memory[i] = ((minute >> (i * 4)) & 0xf) as u8;
+            self.rtc_memory[3 + i] = ((day >> (i * 4)) & 0xf) as u8;
+        }
+    }
+
+    fn set_clock(&mut self) {
+        let (mut minute, mut day): (u64, u64) = (0, 0);
+        for i in 0..3 {
+            minute |= (self.rtc_memory[i] as u64) << (i * 4);
+            day |= (self.rtc_memory[3 + i] as u64) << (i * 4);
+        }
+        self.clock.seconds = (day * MINUTES_PER_DAY + minute.min(MINUTES_PER_DAY - 1)) * 60;
+    }
+
+    // Upper nibble is the command, lower nibble its argument
+    fn write_rtc_command(&mut self, value: u8) {
+        let argument: u8 = value & 0xf;
+        self.rtc_command = value >> 4;
+        match self.rtc_command {
+            0x1 => {
+                self.rtc_result = self.rtc_memory[self.rtc_index as usize];
+                self.rtc_index = self.rtc_index.wrapping_add(1);
+            },
+            0x3 => {
+                self.rtc_memory[self.rtc_index as usize] = argument;
+                self.rtc_index = self.rtc_index.wrapping_add(1);
+            },
+            0x4 => { self.rtc_index = (self.rtc_index & 0xf0) | argument },
+            0x5 => { self.rtc_index = (self.rtc_index & 0x0f) | (argument << 4) },
+            0x6 => {
+                match argument {
+                    0x0 => self.latch_clock(),
+                    0x1 => self.set_clock(),
+                    0x2 => { self.rtc_result = 1 },
+                    0xe => { self.speaker_tone = self.rtc_memory[0x27] },
+                    _ => {},
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+impl Mapper for Huc3 {
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(&self.rom, 0, address),
+            _ => read_rom_bank(&self.rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_control(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1fff => { self.mode = value & 0xf },
+            0x2000..=0x3fff => { self.rom_bank = value & 0x7f },
+            0x4000..=0x5fff => { self.ram_bank = value & 0x3 },
+            _ => {},
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        match self.mode {
+            0x0 | 0xa => get_ram_offset(&self.ram, self.ram_bank as usize, address).map_or(0xff, |offset| self.ram[offset]),
+            0xc => (self.rtc_command << 4) | self.rtc_result,
+            // The RTC is always ready for the next command
+            0xd => 0x01,
+            0xe => IR_NO_LIGHT,
+            _ => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        match self.mode {
+            0xa => {
+                if let Some(offset) = get_ram_offset(&self.ram, self.ram_bank as usize, address) {
+                    self.ram[offset] = value;
+                }
+            },
+            0xb => self.write_rtc_command(value),
+            _ => {},
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        let mut data: Vec<u8> = self.ram.clone();
+        data.extend_from_slice(&self.rtc_memory);
+        self.clock.save(&mut data);
+        Some(data)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+        let rtc_data: &[u8] = data.get(self.ram.len()..).unwrap_or(&[]);
+        load_ram(&mut self.rtc_memory, rtc_data);
+        self.clock.load(rtc_data.get(self.rtc_memory.len()..).unwrap_or(&[]));
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.clock.tick(cycles);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::create_test_rom;
+
+    #[test]
+    fn rom_banks_switch() {
+        let mut huc3: Huc3 = Huc3::new(create_test_rom(128), 0x8000);
+        huc3.write_control(0x2000, 0x7f);
+        assert_eq!(huc3.read_rom(0x4000), 0x7f);
+        huc3.write_control(0x2000, 0x00);
+        assert_eq!(huc3.read_rom(0x4000), 0x00);
+    }
+
+    #[test]
+    fn ram_is_only_writable_in_mode_a() {
+        let mut huc3: Huc3 = Huc3::new(create_test_rom(4), 0x8000);
+        huc3.write_ram(0xa000, 0x12);
+        assert_eq!(huc3.read_ram(0xa000), 0x00);
+        huc3.write_control(0x0000, 0x0a);
+        huc3.write_control(0x4000, 0x02);
+        huc3.write_ram(0xa000, 0x12);
+        assert_eq!(huc3.read_ram(0xa000), 0x12);
+        // Mode 0 reads RAM but ignores writes
+        huc3.write_control(0x0000, 0x00);
+        huc3.write_ram(0xa000, 0x34);
+        assert_eq!(huc3.read_ram(0xa000), 0x12);
+        huc3.write_control(0x4000, 0x00);
+        assert_eq!(huc3.read_ram(0xa000), 0x00);
+        huc3.write_control(0x0000, 0x0e);
+        assert_eq!(huc3.read_ram(0xa000), IR_NO_LIGHT);
+    }
+
+    #[test]
+    fn rtc_memory_is_written_and_read_through_commands() {
+        let mut huc3: Huc3 = Huc3::new(create_test_rom(4), 0x8000);
+        huc3.write_control(0x0000, 0x0b);
+        for command in [0x40, 0x51, 0x35, 0x40, 0x10] {
+            huc3.write_ram(0xa000, command);
+        }
+        huc3.write_control(0x0000, 0x0c);
+        assert_eq!(huc3.read_ram(0xa000), 0x15);
+        assert_eq!(huc3.rtc_memory[0x10], 0x5);
+        huc3.write_control(0x0000, 0x0d);
+        assert_eq!(huc3.read_ram(0xa000), 0x01);
+    }
+
+    #[test]
+    fn latched_clock_counts_minutes_and_days() {
+        let mut huc3: Huc3 = Huc3::new(create_test_rom(4), 0x8000);
+        huc3.clock.seconds = (2 * MINUTES_PER_DAY + 0x123) * 60;
+        huc3.write_control(0x0000, 0x0b);
+        huc3.write_ram(0xa000, 0x60);
+        assert_eq!(huc3.rtc_memory[0..6], [0x3, 0x2, 0x1, 0x2, 0x0, 0x0]);
+    }
+
+    #[test]
+    fn ram_rtc_memory_and_clock_are_saved() {
+        let mut huc3: Huc3 = Huc3::new(create_test_rom(4), 0x8000);
+        huc3.write_control(0x0000, 0x0a);
+        huc3.write_control(0x4000, 0x03);
+        huc3.write_ram(0xa123, 0x5a);
+        huc3.rtc_memory[0x27] = 0x9;
+        huc3.clock.seconds = 123456;
+        let mut loaded: Huc3 = Huc3::new(create_test_rom(4), 0x8000);
+        loaded.load_save_data(&huc3.save_data().unwrap());
+        loaded.write_control(0x4000, 0x03);
+        assert_eq!(loaded.read_ram(0xa123), 0x5a);
+        assert_eq!(loaded.rtc_memory, huc3.rtc_memory);
+        // The clock also counts the time the save was lying around
+        assert!((123456..=123457).contains(&loaded.clock.seconds));
+    }
+}
@@ -0,0 +1,213 @@
+use super::{Mapper, get_ram_offset, load_ram, read_rom_bank};
+
+const LOGO_ADDRESS: usize = 0x104;
+const LOGO_SIZE: usize = 0x30;
+
+#[derive(Debug)]
+pub(crate) struct Mbc1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+    // MBC1M multicarts wire BANK2 to ROM address lines 18-19 instead of 19-20
+    multicart: bool,
+    ram_enabled: bool,
+    bank_1: u8,
+    bank_2: u8,
+    mode: bool,
+}
+
+impl Mbc1 {
+    pub(crate) fn new(rom: Vec<u8>, ram_size: usize, battery: bool) -> Mbc1 {
+        let multicart: bool = Mbc1::is_multicart(&rom);
+        Mbc1 {
+            rom,
+            ram: vec![0; ram_size.min(0x8000)],
+            battery,
+            multicart,
+            ram_enabled: false,
+            bank_1: 1,
+            bank_2: 0,
+            mode: false,
+        }
+    }
+
+    // 1 MiB multicarts repeat the boot logo in the header of the game at bank 0x10
+    fn is_multicart(rom: &[u8]) -> bool {
+        if rom.len() != 0x100000 {
+            return false;
+        }
+        let logo: &[u8] = &rom[LOGO_ADDRESS..LOGO_ADDRESS + LOGO_SIZE];
+        let game_logo: &[u8] = &rom[0x40000 + LOGO_ADDRESS..0x40000 + LOGO_ADDRESS + LOGO_SIZE];
+        logo == game_logo
+    }
+
+    fn get_upper_bank(&self) -> usize {
+        if self.multicart {
+            (self.bank_2 as usize) << 4
+        } else {
+            (self.bank_2 as usize) << 5
+        }
+    }
+
+    fn get_lower_bank(&self) -> usize {
+        if self.multicart {
+            (self.bank_1 & 0xf) as usize
+        } else {
+            self.bank_1 as usize
+        }
+    }
+}
+
+impl Mapper for Mbc1 {
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            // Mode 1 lets BANK2 remap the 0x0000 area as well
+            0x0000..=0x3fff if self.mode => read_rom_bank(&self.rom, self.get_upper_bank(), address),
+            0x0000..=0x3fff => read_rom_bank(&self.rom, 0, address),
+            _ => read_rom_bank(&self.rom, self.get_upper_bank() | self.get_lower_bank(), address),
+        }
+    }
+
+    fn write_control(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1fff => { self.ram_enabled = value & 0xf == 0xa },
+            // Bank 0 is translated to 1 before the multicart drops bit 4
+            0x2000..=0x3fff => { self.bank_1 = (value & 0x1f).max(1) },
+            0x4000..=0x5fff => { self.bank_2 = value & 0x3 },
+            _ => { self.mode = value & 0x1 == 1 },
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xff;
+        }
+        let bank: usize = if self.mode { self.bank_2 as usize } else { 0 };
+        get_ram_offset(&self.ram, bank, address).map_or(0xff, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        let bank: usize = if self.mode { self.bank_2 as usize } else { 0 };
+        if let Some(offset) = get_ram_offset(&self.ram, bank, address) {
+            self.ram[offset] = value;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        if self.battery { Some(self.ram.clone()) } else { None }
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::create_test_rom;
+
+    fn create_multicart() -> Vec<u8> {
+        let mut rom: Vec<u8> = create_test_rom(64);
+        for i in 0..LOGO_SIZE {
+            rom[LOGO_ADDRESS + i] = i as u8;
+            rom[0x40000 + LOGO_ADDRESS + i] = i as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn rom_banks_switch_and_bank_0_reads_bank_1() {
+        let mut mbc1: Mbc1 = Mbc1::new(create_test_rom(128), 0, false);
+        assert_eq!(mbc1.read_rom(0x4000), 1);
+        mbc1.write_control(0x2000, 0x05);
+        assert_eq!(mbc1.read_rom(0x4000), 0x05);
+        mbc1.write_control(0x2000, 0x00);
+        assert_eq!(mbc1.read_rom(0x4000), 0x01);
+        mbc1.write_control(0x2000, 0x20);
+        assert_eq!(mbc1.read_rom(0x4000), 0x01);
+        mbc1.write_control(0x2000, 0x02);
+        mbc1.write_control(0x4000, 0x01);
+        assert_eq!(mbc1.read_rom(0x4000), 0x22);
+        assert_eq!(mbc1.read_rom(0x0000), 0x00);
+        // Mode 1 maps bank 0x20 at 0x0000
+        mbc1.write_control(0x6000, 0x01);
+        assert_eq!(mbc1.read_rom(0x0000), 0x20);
+    }
+
+    #[test]
+    fn ram_is_gated_by_the_enable_register() {
+        let mut mbc1: Mbc1 = Mbc1::new(create_test_rom(4), 0x2000, true);
+        mbc1.write_ram(0xa000, 0x12);
+        assert_eq!(mbc1.read_ram(0xa000), 0xff);
+        mbc1.write_control(0x0000, 0x0a);
+        mbc1.write_ram(0xa000, 0x12);
+        assert_eq!(mbc1.read_ram(0xa000), 0x12);
+        mbc1.write_control(0x0000, 0x00);
+        assert_eq!(mbc1.read_ram(0xa000), 0xff);
+        // Only the low nibble counts
+        mbc1.write_control(0x0000, 0xfa);
+        assert_eq!(mbc1.read_ram(0xa000), 0x12);
+    }
+
+    #[test]
+    fn ram_banks_switch_in_mode_1() {
+        let mut mbc1: Mbc1 = Mbc1::new(create_test_rom(4), 0x8000, true);
+        mbc1.write_control(0x0000, 0x0a);
+        mbc1.write_control(0x6000, 0x01);
+        mbc1.write_control(0x4000, 0x02);
+        mbc1.write_ram(0xa000, 0x34);
+        mbc1.write_control(0x4000, 0x00);
+        assert_eq!(mbc1.read_ram(0xa000), 0x00);
+        mbc1.write_control(0x4000, 0x02);
+        assert_eq!(mbc1.read_ram(0xa000), 0x34);
+        // Mode 0 always maps RAM bank 0
+        mbc1.write_control(0x6000, 0x00);
+        assert_eq!(mbc1.read_ram(0xa000), 0x00);
+    }
+
+    #[test]
+    fn multicart_is_detected_by_the_logo_at_0x40000() {
+        assert!(Mbc1::is_multicart(&create_multicart()));
+        assert!(!Mbc1::is_multicart(&create_test_rom(64)));
+        assert!(!Mbc1::is_multicart(&create_test_rom(32)));
+    }
+
+    #[test]
+    fn multicart_banks_use_address_lines_18_and_19() {
+        let mut mbc1: Mbc1 = Mbc1::new(create_multicart(), 0, false);
+        mbc1.write_control(0x4000, 0x02);
+        mbc1.write_control(0x2000, 0x05);
+        assert_eq!(mbc1.read_rom(0x4000), 0x25);
+        // Bit 4 of BANK1 isn't connected
+        mbc1.write_control(0x2000, 0x15);
+        assert_eq!(mbc1.read_rom(0x4000), 0x25);
+        mbc1.write_control(0x6000, 0x01);
+        assert_eq!(mbc1.read_rom(0x0000), 0x20);
+
+        // The same writes on a plain 1 MiB cartridge wrap around bank 0x45
+        let mut mbc1: Mbc1 = Mbc1::new(create_test_rom(64), 0, false);
+        mbc1.write_control(0x4000, 0x02);
+        mbc1.write_control(0x2000, 0x05);
+        assert_eq!(mbc1.read_rom(0x4000), 0x05);
+    }
+
+    #[test]
+    fn battery_ram_is_saved() {
+        let mut mbc1: Mbc1 = Mbc1::new(create_test_rom(4), 0x8000, true);
+        mbc1.write_control(0x0000, 0x0a);
+        mbc1.write_control(0x6000, 0x01);
+        mbc1.write_control(0x4000, 0x03);
+        mbc1.write_ram(0xbfff, 0x5a);
+        let mut loaded: Mbc1 = Mbc1::new(create_test_rom(4), 0x8000, true);
+        loaded.load_save_data(&mbc1.save_data().unwrap());
+        loaded.write_control(0x0000, 0x0a);
+        loaded.write_control(0x6000, 0x01);
+        loaded.write_control(0x4000, 0x03);
+        assert_eq!(loaded.read_ram(0xbfff), 0x5a);
+        assert!(Mbc1::new(create_test_rom(4), 0x8000, false).save_data().is_none());
+    }
+}
@@ -0,0 +1,104 @@
+use super::{Mapper, load_ram, read_rom_bank};
+
+#[derive(Debug)]
+pub(crate) struct Mbc2 {
+    rom: Vec<u8>,
+    // 512 4-bit cells, stored one per byte
+    ram: Vec<u8>,
+    battery: bool,
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl Mbc2 {
+    pub(crate) fn new(rom: Vec<u8>, battery: bool) -> Mbc2 {
+        Mbc2 { rom, ram: vec![0; 0x200], battery, ram_enabled: false, rom_bank: 1 }
+    }
+}
+
+impl Mapper for Mbc2 {
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(&self.rom, 0, address),
+            _ => read_rom_bank(&self.rom, self.rom_bank as usize, address),
+        }
+    }
+
+    // Address bit 8 picks between RAM enable and ROM bank, the whole 0x0000-0x3fff range decodes both
+    fn write_control(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x3fff if address & 0x100 == 0 => { self.ram_enabled = value & 0xf == 0xa },
+            0x0000..=0x3fff => { self.rom_bank = (value & 0xf).max(1) },
+            _ => {},
+        }
+    }
+
+    // The 512 cells repeat through 0xa000-0xbfff and the upper nibble is open bus
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xff;
+        }
+        self.ram[(address & 0x1ff) as usize] | 0xf0
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled {
+            self.ram[(address & 0x1ff) as usize] = value & 0xf;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        if self.battery { Some(self.ram.clone()) } else { None }
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::create_test_rom;
+
+    #[test]
+    fn address_bit_8_picks_the_rom_bank_register() {
+        let mut mbc2: Mbc2 = Mbc2::new(create_test_rom(16), false);
+        mbc2.write_control(0x2100, 0x05);
+        assert_eq!(mbc2.read_rom(0x4000), 0x05);
+        mbc2.write_control(0x0100, 0x0f);
+        assert_eq!(mbc2.read_rom(0x4000), 0x0f);
+        mbc2.write_control(0x3f00, 0x00);
+        assert_eq!(mbc2.read_rom(0x4000), 0x01);
+        // Bit 8 clear goes to the RAM enable instead
+        mbc2.write_control(0x2000, 0x03);
+        assert_eq!(mbc2.read_rom(0x4000), 0x01);
+    }
+
+    #[test]
+    fn ram_is_gated_and_4_bits_wide() {
+        let mut mbc2: Mbc2 = Mbc2::new(create_test_rom(16), true);
+        mbc2.write_ram(0xa000, 0x0c);
+        assert_eq!(mbc2.read_ram(0xa000), 0xff);
+        mbc2.write_control(0x0000, 0x0a);
+        mbc2.write_ram(0xa000, 0xab);
+        assert_eq!(mbc2.read_ram(0xa000), 0xfb);
+        // 512 cells repeat through the whole area
+        assert_eq!(mbc2.read_ram(0xa200), 0xfb);
+        assert_eq!(mbc2.read_ram(0xbe00), 0xfb);
+        mbc2.write_control(0x0000, 0x00);
+        assert_eq!(mbc2.read_ram(0xa000), 0xff);
+    }
+
+    #[test]
+    fn battery_ram_is_saved() {
+        let mut mbc2: Mbc2 = Mbc2::new(create_test_rom(16), true);
+        mbc2.write_control(0x0000, 0x0a);
+        mbc2.write_ram(0xa1ff, 0x07);
+        let mut loaded: Mbc2 = Mbc2::new(create_test_rom(16), true);
+        loaded.load_save_data(&mbc2.save_data().unwrap());
+        loaded.write_control(0x0000, 0x0a);
+        assert_eq!(loaded.read_ram(0xa1ff), 0xf7);
+        assert!(Mbc2::new(create_test_rom(16), false).save_data().is_none());
+    }
+}
@@ -0,0 +1,288 @@
+use super::{Mapper, load_ram};
+
+const FLASH_SIZE: usize = 0x100000;
+const FLASH_SECTOR_SIZE: usize = 0x20000;
+// Macronix MX29F008 identification
+const FLASH_MANUFACTURER_ID: u8 = 0xc2;
+const FLASH_DEVICE_ID: u8 = 0x81;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FlashState {
+    Ready,
+    Unlocked1,
+    Unlocked2,
+    Program,
+    EraseUnlocked,
+    EraseUnlocked1,
+    EraseUnlocked2,
+    Identify,
+}
+
+// Two independent 8 KiB ROM or flash windows and two 4 KiB RAM windows
+#[derive(Debug)]
+pub(crate) struct Mbc6 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    flash: Vec<u8>,
+    ram_enabled: bool,
+    ram_banks: [u8; 2],
+    rom_banks: [u8; 2],
+    flash_selected: [bool; 2],
+    flash_enabled: bool,
+    flash_write_enabled: bool,
+    flash_state: FlashState,
+}
+
+impl Mbc6 {
+    pub(crate) fn new(rom: Vec<u8>) -> Mbc6 {
+        Mbc6 {
+            rom,
+            ram: vec![0; 0x8000],
+            flash: vec![0xff; FLASH_SIZE],
+            ram_enabled: false,
+            ram_banks: [0; 2],
+            rom_banks: [0; 2],
+            flash_selected: [false; 2],
+            flash_enabled: false,
+            flash_write_enabled: false,
+            flash_state: FlashState::Ready,
+        }
+    }
+
+    fn get_window_offset(&self, window: usize, address: u16) -> usize {
+        (self.rom_banks[window] as usize) * 0x2000 + (address & 0x1fff) as usize
+    }
+
+    fn get_ram_offset(&self, address: u16) -> usize {
+        let window: usize = ((address >> 12) & 0x1) as usize;
+        (self.ram_banks[window] as usize) * 0x1000 + (address & 0xfff) as usize
+    }
+
+    fn read_flash(&self, offset: usize) -> u8 {
+        if !self.flash_enabled {
+            return 0xff;
+        }
+        if self.flash_state == FlashState::Identify {
+            return match offset & 0x1 {
+                0 => FLASH_MANUFACTURER_ID,
+                _ => FLASH_DEVICE_ID,
+            };
+        }
+        self.flash[offset % FLASH_SIZE]
+    }
+
+    // JEDEC style command sequences, unlocked by 0xaa to 0x5555 and 0x55 to 0x2aaa
+    fn write_flash(&mut self, offset: usize, value: u8) {
+        if !self.flash_enabled {
+            return;
+        }
+        if value == 0xf0 {
+            self.flash_state = FlashState::Ready;
+            return;
+        }
+        let command_address: usize = offset & 0x7fff;
+        self.flash_state = match (self.flash_state, command_address, value) {
+            (FlashState::Ready, 0x5555, 0xaa) => FlashState::Unlocked1,
+            (FlashState::Unlocked1, 0x2aaa, 0x55) => FlashState::Unlocked2,
+            (FlashState::Unlocked2, 0x5555, 0xa0) => FlashState::Program,
+            (FlashState::Unlocked2, 0x5555, 0x80) => FlashState::EraseUnlocked,
+            (FlashState::Unlocked2, 0x5555, 0x90) => FlashState::Identify,
+            (FlashState::EraseUnlocked, 0x5555, 0xaa) => FlashState::EraseUnlocked1,
+            (FlashState::EraseUnlocked1, 0x2aaa, 0x55) => FlashState::EraseUnlocked2,
+            (FlashState::EraseUnlocked2, 0x5555, 0x10) => {
+                if self.flash_write_enabled {
+                    self.flash.fill(0xff);
+                }
+                FlashState::Ready
+            },
+            (FlashState::EraseUnlocked2, _, 0x30) => {
+                if self.flash_write_enabled {
+                    let sector: usize = (offset % FLASH_SIZE) / FLASH_SECTOR_SIZE * FLASH_SECTOR_SIZE;
+                    self.flash[sector..sector + FLASH_SECTOR_SIZE].fill(0xff);
+                }
+                FlashState::Ready
+            },
+            (FlashState::Program, _, _) => {
+                // Programming can only clear bits, erasing sets them again
+                if self.flash_write_enabled {
+                    self.flash[offset % FLASH_SIZE] &= value;
+                }
+                FlashState::Ready
+            },
+            (FlashState::Identify, _, _) => FlashState::Identify,
+            _ => FlashState::Ready,
+        };
+    }
+}
+
+impl Mapper for Mbc6 {
+    fn read_rom(&self, address: u16) -> u8 {
+        let window: usize = match address {
+            0x0000..=0x3fff => return self.rom.get(address as usize).copied().unwrap_or(0xff),
+            0x4000..=0x5fff => 0,
+            _ => 1,
+        };
+        let offset: usize = self.get_window_offset(window, address);
+        if self.flash_selected[window] {
+            return self.read_flash(offset);
+        }
+        if self.rom.is_empty() {
+            return 0xff;
+        }
+        self.rom[offset % self.rom.len()]
+    }
+
+    fn write_control(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x03ff => { self.ram_enabled = value & 0xf == 0xa },
+            0x0400..=0x07ff => { self.ram_banks[0] = value & 0x7 },
+            0x0800..=0x0bff => { self.ram_banks[1] = value & 0x7 },
+            0x0c00..=0x0fff => { self.flash_enabled = value & 0x1 == 1 },
+            0x1000 => { self.flash_write_enabled = value & 0x1 == 1 },
+            0x2000..=0x27ff => { self.rom_banks[0] = value & 0x7f },
+            0x2800..=0x2fff => { self.flash_selected[0] = value == 0x08 },
+            0x3000..=0x37ff => { self.rom_banks[1] = value & 0x7f },
+            0x3800..=0x3fff => { self.flash_selected[1] = value == 0x08 },
+            0x4000..=0x5fff if self.flash_selected[0] => { self.write_flash(self.get_window_offset(0, address), value) },
+            0x6000..=0x7fff if self.flash_selected[1] => { self.write_flash(self.get_window_offset(1, address), value) },
+            _ => {},
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xff;
+        }
+        self.ram[self.get_ram_offset(address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled {
+            let offset: usize = self.get_ram_offset(address);
+            self.ram[offset] = value;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        let mut data: Vec<u8> = self.ram.clone();
+        data.extend_from_slice(&self.flash);
+        Some(data)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+        load_ram(&mut self.flash, data.get(self.ram.len()..).unwrap_or(&[]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::create_test_rom;
+
+    // Writes a command byte to a flash address through window 0
+    fn write_command(mbc6: &mut Mbc6, address: usize, value: u8) {
+        mbc6.write_control(0x2000, (address / 0x2000) as u8);
+        mbc6.write_control(0x4000 + (address & 0x1fff) as u16, value);
+    }
+
+    fn create_flash_mbc6() -> Mbc6 {
+        let mut mbc6: Mbc6 = Mbc6::new(create_test_rom(8));
+        mbc6.write_control(0x0c00, 0x01);
+        mbc6.write_control(0x1000, 0x01);
+        mbc6.write_control(0x2800, 0x08);
+        mbc6.write_control(0x3800, 0x08);
+        write_command(&mut mbc6, 0x5555, 0xaa);
+        write_command(&mut mbc6, 0x2aaa, 0x55);
+        mbc6
+    }
+
+    #[test]
+    fn rom_windows_switch_8_kib_banks() {
+        let mut mbc6: Mbc6 = Mbc6::new(create_test_rom(8));
+        mbc6.write_control(0x2000, 0x03);
+        mbc6.write_control(0x3000, 0x04);
+        assert_eq!(mbc6.read_rom(0x4000), 0x01);
+        assert_eq!(mbc6.read_rom(0x6000), 0x02);
+        assert_eq!(mbc6.read_rom(0x0000), 0x00);
+    }
+
+    #[test]
+    fn ram_windows_are_gated_and_switch_4_kib_banks() {
+        let mut mbc6: Mbc6 = Mbc6::new(create_test_rom(8));
+        mbc6.write_ram(0xa000, 0x12);
+        assert_eq!(mbc6.read_ram(0xa000), 0xff);
+        mbc6.write_control(0x0000, 0x0a);
+        mbc6.write_control(0x0400, 0x05);
+        mbc6.write_ram(0xa000, 0x12);
+        mbc6.write_control(0x0800, 0x05);
+        assert_eq!(mbc6.read_ram(0xb000), 0x12);
+        mbc6.write_control(0x0800, 0x04);
+        assert_eq!(mbc6.read_ram(0xb000), 0x00);
+    }
+
+    #[test]
+    fn flash_identifies_and_programs() {
+        let mut mbc6: Mbc6 = create_flash_mbc6();
+        write_command(&mut mbc6, 0x5555, 0x90);
+        assert_eq!(mbc6.read_rom(0x6000), FLASH_MANUFACTURER_ID);
+        assert_eq!(mbc6.read_rom(0x6001), FLASH_DEVICE_ID);
+        write_command(&mut mbc6, 0x0000, 0xf0);
+        assert_eq!(mbc6.read_rom(0x6000), 0xff);
+
+        write_command(&mut mbc6, 0x5555, 0xaa);
+        write_command(&mut mbc6, 0x2aaa, 0x55);
+        write_command(&mut mbc6, 0x5555, 0xa0);
+        write_command(&mut mbc6, 0x0000, 0x3c);
+        assert_eq!(mbc6.read_rom(0x6000), 0x3c);
+        // Programming can't set bits again
+        write_command(&mut mbc6, 0x5555, 0xaa);
+        write_command(&mut mbc6, 0x2aaa, 0x55);
+        write_command(&mut mbc6, 0x5555, 0xa0);
+        write_command(&mut mbc6, 0x0000, 0xc3);
+        assert_eq!(mbc6.read_rom(0x6000), 0x00);
+    }
+
+    #[test]
+    fn flash_erases_a_sector() {
+        let mut mbc6: Mbc6 = create_flash_mbc6();
+        write_command(&mut mbc6, 0x5555, 0xa0);
+        write_command(&mut mbc6, 0x0000, 0x00);
+        assert_eq!(mbc6.read_rom(0x6000), 0x00);
+        write_command(&mut mbc6, 0x5555, 0xaa);
+        write_command(&mut mbc6, 0x2aaa, 0x55);
+        write_command(&mut mbc6, 0x5555, 0x80);
+        write_command(&mut mbc6, 0x5555, 0xaa);
+        write_command(&mut mbc6, 0x2aaa, 0x55);
+        write_command(&mut mbc6, 0x0000, 0x30);
+        assert_eq!(mbc6.read_rom(0x6000), 0xff);
+    }
+
+    #[test]
+    fn flash_ignores_writes_while_write_protected() {
+        let mut mbc6: Mbc6 = create_flash_mbc6();
+        mbc6.write_control(0x1000, 0x00);
+        write_command(&mut mbc6, 0x5555, 0xa0);
+        write_command(&mut mbc6, 0x0000, 0x00);
+        assert_eq!(mbc6.read_rom(0x6000), 0xff);
+    }
+
+    #[test]
+    fn ram_and_flash_are_saved() {
+        let mut mbc6: Mbc6 = create_flash_mbc6();
+        write_command(&mut mbc6, 0x5555, 0xa0);
+        write_command(&mut mbc6, 0x0000, 0x3c);
+        mbc6.write_control(0x0000, 0x0a);
+        mbc6.write_control(0x0400, 0x07);
+        mbc6.write_ram(0xa123, 0x5a);
+        let mut loaded: Mbc6 = Mbc6::new(create_test_rom(8));
+        loaded.load_save_data(&mbc6.save_data().unwrap());
+        loaded.write_control(0x0000, 0x0a);
+        loaded.write_control(0x0400, 0x07);
+        assert_eq!(loaded.read_ram(0xa123), 0x5a);
+        loaded.write_control(0x0c00, 0x01);
+        loaded.write_control(0x3800, 0x08);
+        assert_eq!(loaded.read_rom(0x6000), 0x3c);
+        assert_eq!(loaded.read_rom(0x6001), 0xff);
+    }
+}
@@ -0,0 +1,423 @@
+use super::{Mapper, read_rom_bank};
+
+// Latched accelerometer value when flat, and how much one g moves it
+const ACCELEROMETER_CENTER: f32 = 0x81d0 as f32;
+const ACCELEROMETER_G: f32 = 0x70 as f32;
+const EEPROM_WORDS: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EepromState {
+    Standby,
+    Command,
+    Read,
+    Write,
+}
+
+// Accelerometer and a 93LC56 serial EEPROM of 128 16-bit words
+#[derive(Debug)]
+pub(crate) struct Mbc7 {
+    rom: Vec<u8>,
+    rom_bank: u8,
+    ram_enabled_1: bool,
+    ram_enabled_2: bool,
+    tilt: (f32, f32),
+    latch_erased: bool,
+    latch_x: u16,
+    latch_y: u16,
+    eeprom: [u16; EEPROM_WORDS],
+    eeprom_state: EepromState,
+    eeprom_write_enabled: bool,
+    eeprom_cs: bool,
+    eeprom_clk: bool,
+    eeprom_di: bool,
+    eeprom_do: bool,
+    eeprom_shift: u16,
+    eeprom_bits: u8,
+    eeprom_address: u8,
+    eeprom_write_all: bool,
+}
+
+impl Mbc7 {
+    pub(crate) fn new(rom: Vec<u8>) -> Mbc7 {
+        Mbc7 {
+            rom,
+            rom_bank: 1,
+            ram_enabled_1: false,
+            ram_enabled_2: false,
+            tilt: (0.0, 0.0),
+            latch_erased: false,
+            latch_x: 0x8000,
+            latch_y: 0x8000,
+            eeprom: [0xffff; EEPROM_WORDS],
+            eeprom_state: EepromState::Standby,
+            eeprom_write_enabled: false,
+            eeprom_cs: false,
+            eeprom_clk: false,
+            eeprom_di: false,
+            eeprom_do: true,
+            eeprom_shift: 0,
+            eeprom_bits: 0,
+            eeprom_address: 0,
+            eeprom_write_all: false,
+        }
+    }
+
+    fn is_ram_enabled(&self) -> bool {
+        self.ram_enabled_1 && self.ram_enabled_2
+    }
+
+    fn read_eeprom_port(&self) -> u8 {
+        ((self.eeprom_cs as u8) << 7) | ((self.eeprom_clk as u8) << 6) | ((self.eeprom_di as u8) << 1) | (self.eeprom_do as u8)
+    }
+
+    // Bit 7 is chip select, bit 6 the clock and bit 1 data in, bits are sampled on the rising clock edge
+    fn write_eeprom_port(&mut self, value: u8) {
+        let (cs, clk, di): (bool, bool, bool) = (value & 0x80 != 0, value & 0x40 != 0, value & 0x02 != 0);
+        if self.eeprom_cs && !cs {
+            self.eeprom_state = EepromState::Standby;
+            self.eeprom_do = true;
+        }
+        if cs && !self.eeprom_clk && clk {
+            self.clock_eeprom(di);
+        }
+        self.eeprom_cs = cs;
+        self.eeprom_clk = clk;
+        self.eeprom_di = di;
+    }
+
+    fn clock_eeprom(&mut self, di: bool) {
+        match self.eeprom_state {
+            // Leading zeros are ignored until the start bit
+            EepromState::Standby if di => {
+                self.eeprom_state = EepromState::Command;
+                self.eeprom_shift = 0;
+                self.eeprom_bits = 0;
+            },
+            EepromState::Standby => {},
+            EepromState::Command => {
+                self.eeprom_shift = (self.eeprom_shift << 1) | di as u16;
+                self.eeprom_bits += 1;
+                if self.eeprom_bits == 10 {
+                    self.run_eeprom_command();
+                }
+            },
+            // A dummy zero comes first, then the words from the address onward
+            EepromState::Read => {
+                if self.eeprom_bits == 16 {
+                    self.eeprom_address = (self.eeprom_address + 1) % EEPROM_WORDS as u8;
+                    self.eeprom_shift = self.eeprom[self.eeprom_address as usize];
+                    self.eeprom_bits = 0;
+                }
+                self.eeprom_do = self.eeprom_shift & 0x8000 != 0;
+                self.eeprom_shift <<= 1;
+                self.eeprom_bits += 1;
+            },
+            EepromState::Write => {
+                self.eeprom_shift = (self.eeprom_shift << 1) | di as u16;
+                self.eeprom_bits += 1;
+                if self.eeprom_bits == 16 {
+                    if self.eeprom_write_enabled {
+                        if self.eeprom_write_all {
+                            self.eeprom.fill(self.eeprom_shift);
+                        } else {
+                            self.eeprom[self.eeprom_address as usize] = self.eeprom_shift;
+                        }
+                    }
+                    self.eeprom_do = true;
+                    self.eeprom_state = EepromState::Standby;
+                }
+            },
+        }
+    }
+
+    // Two opcode bits followed by eight address bits, the top one unused in 16-bit mode
+    fn run_eeprom_command(&mut self) {
+        let opcode: u16 = self.eeprom_shift >> 8;
+        let address: u8 = (self.eeprom_shift & 0xff) as u8;
+        self.eeprom_address = address & 0x7f;
+        self.eeprom_state = EepromState::Standby;
+        match opcode {
+            0b10 => {
+                self.eeprom_shift = self.eeprom[self.eeprom_address as usize];
+                self.eeprom_bits = 0;
+                self.eeprom_do = false;
+                self.eeprom_state = EepromState::Read;
+            },
+            0b01 => {
+                self.eeprom_write_all = false;
+                self.eeprom_bits = 0;
+                self.eeprom_state = EepromState::Write;
+            },
+            0b11 => {
+                if self.eeprom_write_enabled {
+                    self.eeprom[self.eeprom_address as usize] = 0xffff;
+                }
+                self.eeprom_do = true;
+            },
+            _ => {
+                match address >> 6 {
+                    0b11 => { self.eeprom_write_enabled = true },
+                    0b00 => { self.eeprom_write_enabled = false },
+                    0b10 => {
+                        if self.eeprom_write_enabled {
+                            self.eeprom.fill(0xffff);
+                        }
+                        self.eeprom_do = true;
+                    },
+                    _ => {
+                        self.eeprom_write_all = true;
+                        self.eeprom_bits = 0;
+                        self.eeprom_state = EepromState::Write;
+                    },
+                }
+            },
+        }
+    }
+}
+
+impl Mapper for Mbc7 {
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(&self.rom, 0, address),
+            _ => read_rom_bank(&self.rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_control(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1fff => { self.ram_enabled_1 = value == 0x0a },
+            0x2000..=0x3fff => { self.rom_bank = value & 0x7f },
+            0x4000..=0x5fff => { self.ram_enabled_2 = value == 0x40 },
+            _ => {},
+        }
+    }
+
+    // Registers repeat every 16 bytes through 0xa000-0xafff
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.is_ram_enabled() || address >= 0xb000 {
+            return 0xff;
+        }
+        match (address >> 4) & 0xf {
+            0x2 => self.latch_x as u8,
+            0x3 => (self.latch_x >> 8) as u8,
+            0x4 => self.latch_y as u8,
+            0x5 => (self.latch_y >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.read_eeprom_port(),
+            _ => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.is_ram_enabled() || address >= 0xb000 {
+            return;
+        }
+        match (address >> 4) & 0xf {
+            0x0 if value == 0x55 => {
+                self.latch_erased = true;
+                self.latch_x = 0x8000;
+                self.latch_y = 0x8000;
+            },
+            0x1 if value == 0xaa && self.latch_erased => {
+                self.latch_erased = false;
+                self.latch_x = (ACCELEROMETER_CENTER + ACCELEROMETER_G * self.tilt.0) as u16;
+                self.latch_y = (ACCELEROMETER_CENTER - ACCELEROMETER_G * self.tilt.1) as u16;
+            },
+            0x8 => self.write_eeprom_port(value),
+            _ => {},
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.eeprom.iter().flat_map(|word| word.to_le_bytes()).collect())
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        for (word, bytes) in self.eeprom.iter_mut().zip(data.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x.clamp(-1.0, 1.0), y.clamp(-1.0, 1.0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::create_test_rom;
+
+    fn read_latch(mbc7: &Mbc7) -> (u16, u16) {
+        let x: u16 = ((mbc7.read_ram(0xa030) as u16) << 8) | mbc7.read_ram(0xa020) as u16;
+        let y: u16 = ((mbc7.read_ram(0xa050) as u16) << 8) | mbc7.read_ram(0xa040) as u16;
+        (x, y)
+    }
+
+    fn latch(mbc7: &mut Mbc7) {
+        mbc7.write_ram(0xa000, 0x55);
+        mbc7.write_ram(0xa010, 0xaa);
+    }
+
+    #[test]
+    fn rom_banks_switch() {
+        let mut mbc7: Mbc7 = Mbc7::new(create_test_rom(128));
+        assert_eq!(mbc7.read_rom(0x4000), 0x01);
+        mbc7.write_control(0x2000, 0x7f);
+        assert_eq!(mbc7.read_rom(0x4000), 0x7f);
+    }
+
+    #[test]
+    fn registers_need_both_enables() {
+        let mut mbc7: Mbc7 = Mbc7::new(create_test_rom(4));
+        mbc7.write_control(0x0000, 0x0a);
+        assert_eq!(mbc7.read_ram(0xa020), 0xff);
+        mbc7.write_control(0x4000, 0x40);
+        assert_eq!(mbc7.read_ram(0xa020), 0x00);
+        mbc7.write_control(0x0000, 0x00);
+        assert_eq!(mbc7.read_ram(0xa020), 0xff);
+    }
+
+    #[test]
+    fn accelerometer_latches_the_tilt() {
+        let mut mbc7: Mbc7 = Mbc7::new(create_test_rom(4));
+        mbc7.write_control(0x0000, 0x0a);
+        mbc7.write_control(0x4000, 0x40);
+        assert_eq!(read_latch(&mbc7), (0x8000, 0x8000));
+        latch(&mut mbc7);
+        assert_eq!(read_latch(&mbc7), (0x81d0, 0x81d0));
+        mbc7.set_tilt(1.0, 1.0);
+        assert_eq!(read_latch(&mbc7), (0x81d0, 0x81d0));
+        latch(&mut mbc7);
+        assert_eq!(read_latch(&mbc7), (0x8240, 0x8160));
+        // Latching again needs the erase first
+        mbc7.set_tilt(-2.0, 0.0);
+        mbc7.write_ram(0xa010, 0xaa);
+        assert_eq!(read_latch(&mbc7), (0x8240, 0x8160));
+        latch(&mut mbc7);
+        assert_eq!(read_latch(&mbc7), (0x8160, 0x81d0));
+    }
+
+    fn create_eeprom_mbc7() -> Mbc7 {
+        let mut mbc7: Mbc7 = Mbc7::new(create_test_rom(4));
+        mbc7.write_control(0x0000, 0x0a);
+        mbc7.write_control(0x4000, 0x40);
+        mbc7
+    }
+
+    // Data in is sampled on the rising clock edge
+    fn clock_bit(mbc7: &mut Mbc7, bit: bool) {
+        let di: u8 = (bit as u8) << 1;
+        mbc7.write_ram(0xa080, 0x80 | di);
+        mbc7.write_ram(0xa080, 0xc0 | di);
+    }
+
+    fn read_do(mbc7: &Mbc7) -> bool {
+        mbc7.read_ram(0xa080) & 0x1 == 1
+    }
+
+    // Chip select, the start bit, then the opcode and address and any data, MSB first
+    fn send_command(mbc7: &mut Mbc7, command: u16, data: Option<u16>) {
+        mbc7.write_ram(0xa080, 0x00);
+        mbc7.write_ram(0xa080, 0x80);
+        // Leading zeros before the start bit are ignored
+        clock_bit(mbc7, false);
+        clock_bit(mbc7, true);
+        for bit in (0..10).rev() {
+            clock_bit(mbc7, command & (1 << bit) != 0);
+        }
+        if let Some(data) = data {
+            for bit in (0..16).rev() {
+                clock_bit(mbc7, data & (1 << bit) != 0);
+            }
+        }
+    }
+
+    fn deselect(mbc7: &mut Mbc7) {
+        mbc7.write_ram(0xa080, 0x00);
+    }
+
+    fn read_word(mbc7: &mut Mbc7) -> u16 {
+        (0..16).fold(0, |word, _| {
+            clock_bit(mbc7, false);
+            (word << 1) | read_do(mbc7) as u16
+        })
+    }
+
+    const EWEN: u16 = 0b00_1100_0000;
+    const EWDS: u16 = 0b00_0000_0000;
+    const ERAL: u16 = 0b00_1000_0000;
+    const WRAL: u16 = 0b00_0100_0000;
+    const WRITE: u16 = 0b01_0000_0000;
+    const READ: u16 = 0b10_0000_0000;
+    const ERASE: u16 = 0b11_0000_0000;
+
+    #[test]
+    fn eeprom_reads_a_dummy_zero_then_the_words() {
+        let mut mbc7: Mbc7 = create_eeprom_mbc7();
+        mbc7.eeprom[5] = 0x1234;
+        mbc7.eeprom[6] = 0xabcd;
+        send_command(&mut mbc7, READ | 5, None);
+        assert!(!read_do(&mbc7));
+        assert_eq!(read_word(&mut mbc7), 0x1234);
+        // Reads carry on with the next address
+        assert_eq!(read_word(&mut mbc7), 0xabcd);
+        deselect(&mut mbc7);
+        assert!(read_do(&mbc7));
+    }
+
+    #[test]
+    fn eeprom_writes_need_ewen() {
+        let mut mbc7: Mbc7 = create_eeprom_mbc7();
+        send_command(&mut mbc7, WRITE | 5, Some(0x1234));
+        deselect(&mut mbc7);
+        assert_eq!(mbc7.eeprom[5], 0xffff);
+        send_command(&mut mbc7, EWEN, None);
+        deselect(&mut mbc7);
+        send_command(&mut mbc7, WRITE | 5, Some(0x1234));
+        // DO goes high when the write is done
+        assert!(read_do(&mbc7));
+        deselect(&mut mbc7);
+        assert_eq!(mbc7.eeprom[5], 0x1234);
+        send_command(&mut mbc7, EWDS, None);
+        deselect(&mut mbc7);
+        send_command(&mut mbc7, WRITE | 5, Some(0x5678));
+        deselect(&mut mbc7);
+        assert_eq!(mbc7.eeprom[5], 0x1234);
+        // The top address bit isn't used
+        send_command(&mut mbc7, READ | 0x85, None);
+        assert_eq!(read_word(&mut mbc7), 0x1234);
+    }
+
+    #[test]
+    fn eeprom_erases_and_writes_everything() {
+        let mut mbc7: Mbc7 = create_eeprom_mbc7();
+        send_command(&mut mbc7, EWEN, None);
+        deselect(&mut mbc7);
+        send_command(&mut mbc7, WRAL, Some(0xa5a5));
+        deselect(&mut mbc7);
+        assert!(mbc7.eeprom.iter().all(|&word| word == 0xa5a5));
+        send_command(&mut mbc7, ERASE | 3, None);
+        deselect(&mut mbc7);
+        assert_eq!(mbc7.eeprom[3], 0xffff);
+        assert_eq!(mbc7.eeprom[4], 0xa5a5);
+        send_command(&mut mbc7, ERAL, None);
+        deselect(&mut mbc7);
+        assert!(mbc7.eeprom.iter().all(|&word| word == 0xffff));
+    }
+
+    #[test]
+    fn eeprom_is_saved() {
+        let mut mbc7: Mbc7 = create_eeprom_mbc7();
+        send_command(&mut mbc7, EWEN, None);
+        deselect(&mut mbc7);
+        send_command(&mut mbc7, WRITE | 0x7f, Some(0xbeef));
+        deselect(&mut mbc7);
+        let mut loaded: Mbc7 = create_eeprom_mbc7();
+        loaded.load_save_data(&mbc7.save_data().unwrap());
+        send_command(&mut loaded, READ | 0x7f, None);
+        assert_eq!(read_word(&mut loaded), 0xbeef);
+        // And wraps around to the first word
+        assert_eq!(read_word(&mut loaded), 0xffff);
+    }
+}
@@ -0,0 +1,199 @@
+use super::{Mapper, get_ram_offset, load_ram, read_rom_bank};
+
+// Multicart mapper, the menu in the last 32 KiB sets up the outer banks once and locks them by mapping the game
+#[derive(Debug)]
+pub(crate) struct Mmm01 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+    mapped: bool,
+    ram_enabled: bool,
+    rom_bank_low: u8,
+    rom_bank_mid: u8,
+    rom_bank_high: u8,
+    // Bits of the low ROM bank and RAM bank the game can't change
+    rom_bank_mask: u8,
+    ram_bank_mask: u8,
+    ram_bank_low: u8,
+    ram_bank_high: u8,
+    mode: bool,
+    mode_locked: bool,
+}
+
+impl Mmm01 {
+    pub(crate) fn new(rom: Vec<u8>, ram_size: usize, battery: bool) -> Mmm01 {
+        Mmm01 {
+            rom,
+            ram: vec![0; ram_size.min(0x20000)],
+            battery,
+            mapped: false,
+            ram_enabled: false,
+            rom_bank_low: 0,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            rom_bank_mask: 0,
+            ram_bank_mask: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            mode: false,
+            mode_locked: false,
+        }
+    }
+
+    fn get_outer_bank(&self) -> usize {
+        ((self.rom_bank_high as usize) << 7) | ((self.rom_bank_mid as usize) << 5)
+    }
+
+    fn get_ram_bank(&self) -> usize {
+        let low: u8 = if self.mode { self.ram_bank_low } else { self.ram_bank_low & self.ram_bank_mask };
+        ((self.ram_bank_high as usize) << 2) | low as usize
+    }
+}
+
+impl Mapper for Mmm01 {
+    fn read_rom(&self, address: u16) -> u8 {
+        // Until mapped every upper address line is pulled high, showing the last 32 KiB
+        let bank: usize = match address {
+            0x0000..=0x3fff if !self.mapped => 0x1fe,
+            _ if !self.mapped => 0x1ff,
+            0x0000..=0x3fff => self.get_outer_bank() | (self.rom_bank_low & self.rom_bank_mask) as usize,
+            _ => self.get_outer_bank() | self.rom_bank_low as usize,
+        };
+        read_rom_bank(&self.rom, bank, address)
+    }
+
+    fn write_control(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1fff => {
+                self.ram_enabled = value & 0xf == 0xa;
+                if !self.mapped {
+                    self.ram_bank_mask = (value >> 4) & 0x3;
+                    self.mapped = value & 0x40 != 0;
+                }
+            },
+            0x2000..=0x3fff => {
+                let mut low: u8 = (self.rom_bank_low & self.rom_bank_mask) | (value & 0x1f & !self.rom_bank_mask);
+                // Like MBC1, bank 0 of the writable bits reads bank 1
+                if low & !self.rom_bank_mask & 0x1f == 0 {
+                    low |= 1;
+                }
+                self.rom_bank_low = low;
+                if !self.mapped {
+                    self.rom_bank_mid = (value >> 5) & 0x3;
+                }
+            },
+            0x4000..=0x5fff => {
+                self.ram_bank_low = (self.ram_bank_low & self.ram_bank_mask) | (value & 0x3 & !self.ram_bank_mask);
+                if !self.mapped {
+                    self.ram_bank_high = (value >> 2) & 0x3;
+                    self.rom_bank_high = (value >> 4) & 0x3;
+                    self.mode_locked = value & 0x40 != 0;
+                }
+            },
+            _ => {
+                if !self.mode_locked {
+                    self.mode = value & 0x1 == 1;
+                }
+                if !self.mapped {
+                    self.rom_bank_mask = (value & 0x3c) >> 1;
+                }
+            },
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xff;
+        }
+        get_ram_offset(&self.ram, self.get_ram_bank(), address).map_or(0xff, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if let Some(offset) = get_ram_offset(&self.ram, self.get_ram_bank(), address) {
+            self.ram[offset] = value;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        if self.battery { Some(self.ram.clone()) } else { None }
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::create_test_rom;
+
+    #[test]
+    fn unmapped_reads_show_the_last_32_kib() {
+        let mmm01: Mmm01 = Mmm01::new(create_test_rom(128), 0x8000, false);
+        assert_eq!(mmm01.read_rom(0x0000), 126);
+        assert_eq!(mmm01.read_rom(0x4000), 127);
+    }
+
+    #[test]
+    fn mapping_sets_and_locks_the_outer_bank() {
+        let mut mmm01: Mmm01 = Mmm01::new(create_test_rom(128), 0x8000, false);
+        mmm01.write_control(0x2000, 0x22);
+        mmm01.write_control(0x0000, 0x4a);
+        assert_eq!(mmm01.read_rom(0x0000), 0x20);
+        assert_eq!(mmm01.read_rom(0x4000), 0x22);
+        // The middle bits are locked once mapped, the low ones stay writable
+        mmm01.write_control(0x2000, 0x65);
+        assert_eq!(mmm01.read_rom(0x4000), 0x25);
+        mmm01.write_control(0x2000, 0x00);
+        assert_eq!(mmm01.read_rom(0x4000), 0x21);
+        mmm01.write_control(0x0000, 0x00);
+        assert_eq!(mmm01.read_rom(0x4000), 0x21);
+    }
+
+    #[test]
+    fn rom_bank_mask_fixes_the_low_bits() {
+        let mut mmm01: Mmm01 = Mmm01::new(create_test_rom(128), 0, false);
+        mmm01.write_control(0x2000, 0x06);
+        mmm01.write_control(0x6000, 0x3c);
+        mmm01.write_control(0x0000, 0x40);
+        mmm01.write_control(0x2000, 0x1f);
+        assert_eq!(mmm01.read_rom(0x4000), 0x07);
+        assert_eq!(mmm01.read_rom(0x0000), 0x06);
+    }
+
+    #[test]
+    fn ram_is_gated_by_the_enable_register() {
+        let mut mmm01: Mmm01 = Mmm01::new(create_test_rom(128), 0x8000, true);
+        mmm01.write_control(0x0000, 0x40);
+        mmm01.write_ram(0xa000, 0x12);
+        assert_eq!(mmm01.read_ram(0xa000), 0xff);
+        mmm01.write_control(0x0000, 0x0a);
+        mmm01.write_ram(0xa000, 0x12);
+        assert_eq!(mmm01.read_ram(0xa000), 0x12);
+        // Like MBC1, mode 0 keeps RAM bank 0
+        mmm01.write_control(0x4000, 0x01);
+        assert_eq!(mmm01.read_ram(0xa000), 0x12);
+        mmm01.write_control(0x6000, 0x01);
+        assert_eq!(mmm01.read_ram(0xa000), 0x00);
+        mmm01.write_control(0x4000, 0x00);
+        assert_eq!(mmm01.read_ram(0xa000), 0x12);
+        mmm01.write_control(0x0000, 0x00);
+        assert_eq!(mmm01.read_ram(0xa000), 0xff);
+    }
+
+    #[test]
+    fn battery_ram_is_saved() {
+        let mut mmm01: Mmm01 = Mmm01::new(create_test_rom(128), 0x8000, true);
+        mmm01.write_control(0x0000, 0x4a);
+        mmm01.write_ram(0xa123, 0x5a);
+        let mut loaded: Mmm01 = Mmm01::new(create_test_rom(128), 0x8000, true);
+        loaded.load_save_data(&mmm01.save_data().unwrap());
+        loaded.write_control(0x0000, 0x4a);
+        assert_eq!(loaded.read_ram(0xa123), 0x5a);
+        assert!(Mmm01::new(create_test_rom(128), 0x8000, false).save_data().is_none());
+    }
+}
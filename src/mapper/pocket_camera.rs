@@ -0,0 +1,348 @@
+use super::{Mapper, load_ram, read_rom_bank};
+use crate::camera::{CAMERA_HEIGHT, CAMERA_WIDTH, ImageSource, TestPattern};
+
+const REGISTER_COUNT: usize = 0x36;
+const CAPTURE: usize = 0x0;
+const EDGE_MODE_AND_GAIN: usize = 0x1;
+const EXPOSURE_HIGH: usize = 0x2;
+const EXPOSURE_LOW: usize = 0x3;
+const EDGE_RATIO_AND_INVERT: usize = 0x4;
+const DITHER_MATRIX: usize = 0x6;
+// Edge enhancement ratios selected by bits 4-6 of register 4, as multiples of 1/4
+const EDGE_RATIOS: [i32; 8] = [2, 3, 4, 5, 8, 12, 16, 20];
+// The captured image is stored as 16x14 tiles in RAM bank 0
+const IMAGE_ADDRESS: usize = 0x100;
+
+// Mitsubishi M64282FP sensor behind a mapper with 128 KiB of RAM
+#[derive(Debug)]
+pub(crate) struct PocketCamera {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    // Bank 0x10 maps the sensor registers to 0xa000-0xbfff
+    registers_selected: bool,
+    registers: [u8; REGISTER_COUNT],
+    capture_cycles: u32,
+    image_source: Box<dyn ImageSource>,
+}
+
+impl PocketCamera {
+    pub(crate) fn new(rom: Vec<u8>) -> PocketCamera {
+        PocketCamera {
+            rom,
+            ram: vec![0; 0x20000],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            registers_selected: false,
+            registers: [0; REGISTER_COUNT],
+            capture_cycles: 0,
+            image_source: Box::new(TestPattern::new()),
+        }
+    }
+
+    fn get_exposure(&self) -> u32 {
+        ((self.registers[EXPOSURE_HIGH] as u32) << 8) | self.registers[EXPOSURE_LOW] as u32
+    }
+
+    // Sensor timing in M-cycles, reset and read out plus the exposure in steps of 16
+    fn get_capture_cycles(&self) -> u32 {
+        let reset_cycles: u32 = if self.registers[EDGE_MODE_AND_GAIN] & 0x80 != 0 { 0 } else { 512 };
+        (32446 + reset_cycles + 16 * self.get_exposure()) * 4
+    }
+
+    fn capture(&mut self) {
+        let image: Vec<u8> = self.image_source.capture();
+        let exposure: i32 = self.get_exposure() as i32;
+        // 0x300 is roughly the exposure that passes the source through unchanged
+        let exposed: Vec<i32> = image.iter().map(|&pixel| ((pixel as i32) * exposure / 0x300).min(0xff)).collect();
+        let get_pixel = |x: isize, y: isize| -> i32 {
+            let (x, y) = (x.clamp(0, CAMERA_WIDTH as isize - 1) as usize, y.clamp(0, CAMERA_HEIGHT as isize - 1) as usize);
+            exposed[y * CAMERA_WIDTH + x]
+        };
+        let vertical_edges: bool = self.registers[EDGE_MODE_AND_GAIN] & 0x20 != 0;
+        let horizontal_edges: bool = self.registers[EDGE_MODE_AND_GAIN] & 0x40 != 0;
+        let edge_ratio: i32 = EDGE_RATIOS[((self.registers[EDGE_RATIO_AND_INVERT] >> 4) & 0x7) as usize];
+        let invert: bool = self.registers[EDGE_RATIO_AND_INVERT] & 0x8 != 0;
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let (pixel_x, pixel_y) = (x as isize, y as isize);
+                let center: i32 = get_pixel(pixel_x, pixel_y);
+                let mut edge: i32 = 0;
+                if vertical_edges {
+                    edge += 2 * center - get_pixel(pixel_x, pixel_y - 1) - get_pixel(pixel_x, pixel_y + 1);
+                }
+                if horizontal_edges {
+                    edge += 2 * center - get_pixel(pixel_x - 1, pixel_y) - get_pixel(pixel_x + 1, pixel_y);
+                }
+                let mut value: i32 = (center + edge * edge_ratio / 4).clamp(0, 0xff);
+                if invert {
+                    value = 0xff - value;
+                }
+                self.write_pixel(x, y, self.get_dithered_shade(x, y, value as u8));
+            }
+        }
+    }
+
+    // Each cell of the 4x4 matrix holds the thresholds between the four shades
+    fn get_dithered_shade(&self, x: usize, y: usize, value: u8) -> u8 {
+        let index: usize = DITHER_MATRIX + ((y & 0x3) * 4 + (x & 0x3)) * 3;
+        let thresholds: &[u8] = &self.registers[index..index + 3];
+        if value < thresholds[0] {
+            3
+        } else if value < thresholds[1] {
+            2
+        } else if value < thresholds[2] {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn write_pixel(&mut self, x: usize, y: usize, shade: u8) {
+        let tile: usize = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+        let offset: usize = IMAGE_ADDRESS + tile * 16 + (y % 8) * 2;
+        let bit: u8 = 0x80 >> (x % 8);
+        for plane in 0..2 {
+            if (shade >> plane) & 0x1 == 1 {
+                self.ram[offset + plane] |= bit;
+            } else {
+                self.ram[offset + plane] &= !bit;
+            }
+        }
+    }
+}
+
+impl Mapper for PocketCamera {
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(&self.rom, 0, address),
+            _ => read_rom_bank(&self.rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_control(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000..=0x1fff => { self.ram_enabled = value & 0xf == 0xa },
+            0x2000..=0x3fff => { self.rom_bank = value & 0x3f },
+            0x4000..=0x5fff => {
+                self.registers_selected = value & 0x10 != 0;
+                self.ram_bank = value & 0xf;
+            },
+            _ => {},
+        }
+    }
+
+    // Only the capture register reads back, the rest of the sensor registers are write only
+    fn read_ram(&self, address: u16) -> u8 {
+        if self.registers_selected {
+            return match (address & 0x7f) as usize {
+                CAPTURE => self.registers[CAPTURE],
+                _ => 0x00,
+            };
+        }
+        self.ram[(self.ram_bank as usize) * 0x2000 + (address & 0x1fff) as usize]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.registers_selected {
+            let register: usize = (address & 0x7f) as usize;
+            match register {
+                CAPTURE => {
+                    self.registers[CAPTURE] = value & 0x7;
+                    if value & 0x1 == 1 && self.capture_cycles == 0 {
+                        self.capture_cycles = self.get_capture_cycles();
+                    }
+                },
+                _ if register < REGISTER_COUNT => { self.registers[register] = value },
+                _ => {},
+            }
+            return;
+        }
+        if self.ram_enabled {
+            self.ram[(self.ram_bank as usize) * 0x2000 + (address & 0x1fff) as usize] = value;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        Some(self.ram.clone())
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+
+    // The busy bit stays set until the exposure and read out are done
+    fn tick(&mut self, cycles: u32) {
+        if self.capture_cycles == 0 {
+            return;
+        }
+        self.capture_cycles = self.capture_cycles.saturating_sub(cycles);
+        if self.capture_cycles == 0 {
+            self.capture();
+            self.registers[CAPTURE] &= !0x1;
+        }
+    }
+
+    fn set_image_source(&mut self, image_source: Box<dyn ImageSource>) {
+        self.image_source = image_source;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::create_test_rom;
+
+    #[test]
+    fn rom_banks_switch() {
+        let mut pocket_camera: PocketCamera = PocketCamera::new(create_test_rom(64));
+        assert_eq!(pocket_camera.read_rom(0x4000), 0x01);
+        pocket_camera.write_control(0x2000, 0x3f);
+        assert_eq!(pocket_camera.read_rom(0x4000), 0x3f);
+        pocket_camera.write_control(0x2000, 0x00);
+        assert_eq!(pocket_camera.read_rom(0x4000), 0x00);
+    }
+
+    #[test]
+    fn ram_writes_are_gated_and_banks_switch() {
+        let mut pocket_camera: PocketCamera = PocketCamera::new(create_test_rom(4));
+        pocket_camera.write_control(0x4000, 0x03);
+        pocket_camera.write_ram(0xa000, 0x12);
+        assert_eq!(pocket_camera.read_ram(0xa000), 0x00);
+        pocket_camera.write_control(0x0000, 0x0a);
+        pocket_camera.write_ram(0xa000, 0x12);
+        assert_eq!(pocket_camera.read_ram(0xa000), 0x12);
+        pocket_camera.write_control(0x4000, 0x02);
+        assert_eq!(pocket_camera.read_ram(0xa000), 0x00);
+        // Reads don't need the enable
+        pocket_camera.write_control(0x0000, 0x00);
+        pocket_camera.write_control(0x4000, 0x03);
+        assert_eq!(pocket_camera.read_ram(0xa000), 0x12);
+    }
+
+    #[test]
+    fn capture_stays_busy_until_the_exposure_is_done() {
+        let mut pocket_camera: PocketCamera = PocketCamera::new(create_test_rom(4));
+        pocket_camera.write_control(0x4000, 0x10);
+        pocket_camera.write_ram(0xa002, 0x01);
+        assert_eq!(pocket_camera.read_ram(0xa002), 0x00);
+        pocket_camera.write_ram(0xa000, 0x03);
+        assert_eq!(pocket_camera.read_ram(0xa000), 0x03);
+        let cycles: u32 = pocket_camera.get_capture_cycles();
+        assert_eq!(cycles, (32446 + 512 + 16 * 0x100) * 4);
+        pocket_camera.tick(cycles - 1);
+        assert_eq!(pocket_camera.read_ram(0xa000), 0x03);
+        pocket_camera.tick(1);
+        assert_eq!(pocket_camera.read_ram(0xa000), 0x02);
+    }
+
+    #[test]
+    fn ram_is_saved() {
+        let mut pocket_camera: PocketCamera = PocketCamera::new(create_test_rom(4));
+        pocket_camera.write_control(0x0000, 0x0a);
+        pocket_camera.write_control(0x4000, 0x0f);
+        pocket_camera.write_ram(0xbfff, 0x5a);
+        let mut loaded: PocketCamera = PocketCamera::new(create_test_rom(4));
+        loaded.load_save_data(&pocket_camera.save_data().unwrap());
+        loaded.write_control(0x4000, 0x0f);
+        assert_eq!(loaded.read_ram(0xbfff), 0x5a);
+    }
+
+    #[derive(Debug)]
+    struct FixedImage {
+        image: Vec<u8>,
+    }
+
+    impl ImageSource for FixedImage {
+        fn capture(&mut self) -> Vec<u8> {
+            self.image.clone()
+        }
+    }
+
+    // Left half dark, right half a light grey
+    fn create_image() -> Vec<u8> {
+        (0..CAMERA_WIDTH * CAMERA_HEIGHT).map(|i| if i % CAMERA_WIDTH < 64 { 0x20 } else { 0xa0 }).collect()
+    }
+
+    // Captures with the exposure at 0x300 and every dither cell at 0x40, 0x80 and 0xc0 unless changed,
+    // then reads the 16 bytes of a tile from RAM bank 0
+    fn capture(image: Vec<u8>, registers: &[(usize, u8)], tile: usize) -> Vec<u8> {
+        let mut pocket_camera: PocketCamera = PocketCamera::new(create_test_rom(4));
+        pocket_camera.set_image_source(Box::new(FixedImage { image }));
+        pocket_camera.write_control(0x4000, 0x10);
+        pocket_camera.write_ram(0xa001, 0x80);
+        pocket_camera.write_ram(0xa002, 0x03);
+        pocket_camera.write_ram(0xa003, 0x00);
+        for cell in 0..16 {
+            for (threshold, value) in [0x40, 0x80, 0xc0].into_iter().enumerate() {
+                pocket_camera.write_ram(0xa000 + (DITHER_MATRIX + cell * 3 + threshold) as u16, value);
+            }
+        }
+        for &(register, value) in registers {
+            pocket_camera.write_ram(0xa000 + register as u16, value);
+        }
+        pocket_camera.write_ram(0xa000, 0x01);
+        pocket_camera.tick(pocket_camera.get_capture_cycles());
+        assert_eq!(pocket_camera.read_ram(0xa000) & 0x1, 0);
+        pocket_camera.write_control(0x4000, 0x00);
+        let start: u16 = 0xa000 + (IMAGE_ADDRESS + tile * 16) as u16;
+        (start..start + 16).map(|address| pocket_camera.read_ram(address)).collect()
+    }
+
+    // Eight rows of the same two bitplanes
+    fn fill_tile(low: u8, high: u8) -> Vec<u8> {
+        [low, high].repeat(8)
+    }
+
+    #[test]
+    fn capture_writes_2bpp_tiles_at_0x100() {
+        // Below the first threshold is black, between the second and third light grey
+        assert_eq!(capture(create_image(), &[], 0), fill_tile(0xff, 0xff));
+        assert_eq!(capture(create_image(), &[], 8), fill_tile(0xff, 0x00));
+        // Tiles are stored row by row, 16 to a row
+        assert_eq!(capture(create_image(), &[], 16), fill_tile(0xff, 0xff));
+        assert_eq!(capture(create_image(), &[], 16 * 14 - 1), fill_tile(0xff, 0x00));
+    }
+
+    #[test]
+    fn exposure_scales_the_image() {
+        // Half the exposure turns 0xa0 into 0x50, dark grey
+        assert_eq!(capture(create_image(), &[(EXPOSURE_HIGH, 0x01), (EXPOSURE_LOW, 0x80)], 8), fill_tile(0x00, 0xff));
+        assert_eq!(capture(create_image(), &[(EXPOSURE_HIGH, 0x00)], 8), fill_tile(0xff, 0xff));
+    }
+
+    #[test]
+    fn invert_flips_the_image() {
+        assert_eq!(capture(create_image(), &[(EDGE_RATIO_AND_INVERT, 0x08)], 0), fill_tile(0x00, 0x00));
+        assert_eq!(capture(create_image(), &[(EDGE_RATIO_AND_INVERT, 0x08)], 8), fill_tile(0x00, 0xff));
+    }
+
+    #[test]
+    fn dither_thresholds_apply_per_cell() {
+        // Cell x = 1, y = 0 turns everything black
+        let cell: usize = DITHER_MATRIX + 3;
+        let tile: Vec<u8> = capture(create_image(), &[(cell, 0xff), (cell + 1, 0xff), (cell + 2, 0xff)], 8);
+        assert_eq!(tile[0..2], [0xff, 0x44]);
+        assert_eq!(tile[2..8], [0xff, 0x00, 0xff, 0x00, 0xff, 0x00]);
+        assert_eq!(tile[8..10], [0xff, 0x44]);
+    }
+
+    #[test]
+    fn edge_enhancement_sharpens_edges() {
+        let mut image: Vec<u8> = vec![0x80; CAMERA_WIDTH * CAMERA_HEIGHT];
+        image[4 * CAMERA_WIDTH + 2] = 0xa0;
+        // 0x80 is light grey, so the row reads plane 0 only
+        assert_eq!(capture(image.clone(), &[], 0)[8..10], [0xff, 0x00]);
+        // The bright pixel goes to white at 0xc0, its neighbours to dark grey at 0x70
+        let tile: Vec<u8> = capture(image.clone(), &[(EDGE_MODE_AND_GAIN, 0xc0)], 0);
+        assert_eq!(tile[8..10], [0b1000_1111, 0b0101_0000]);
+        // Vertical edges darken the pixels above and below instead
+        let tile: Vec<u8> = capture(image, &[(EDGE_MODE_AND_GAIN, 0xa0)], 0);
+        assert_eq!(tile[8..10], [0b1101_1111, 0b0000_0000]);
+        assert_eq!(tile[6..8], [0b1101_1111, 0b0010_0000]);
+        assert_eq!(tile[10..12], [0b1101_1111, 0b0010_0000]);
+    }
+}
@@ -0,0 +1,41 @@
+use super::{Mapper, get_ram_offset, load_ram, read_rom_bank};
+
+// 32 KiB of ROM and optionally up to 8 KiB of RAM without any banking
+#[derive(Debug)]
+pub(crate) struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
+}
+
+impl RomOnly {
+    pub(crate) fn new(rom: Vec<u8>, ram_size: usize, battery: bool) -> RomOnly {
+        RomOnly { rom, ram: vec![0; ram_size.min(0x2000)], battery }
+    }
+}
+
+impl Mapper for RomOnly {
+    fn read_rom(&self, address: u16) -> u8 {
+        read_rom_bank(&self.rom, (address >> 14) as usize, address)
+    }
+
+    fn write_control(&mut self, _address: u16, _value: u8) {}
+
+    fn read_ram(&self, address: u16) -> u8 {
+        get_ram_offset(&self.ram, 0, address).map_or(0xff, |offset| self.ram[offset])
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if let Some(offset) = get_ram_offset(&self.ram, 0, address) {
+            self.ram[offset] = value;
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        if self.battery { Some(self.ram.clone()) } else { None }
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
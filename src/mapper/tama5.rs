@@ -0,0 +1,236 @@
+use super::{Mapper, RealTimeClock, load_ram, read_rom_bank};
+
+const SECONDS_PER_DAY: u64 = 86400;
+const DAYS_PER_MONTH: [u64; 12] = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+
+// Nibble registers of the TC8521 RTC, the calendar starts on 2000-01-01
+const RTC_SECONDS: u8 = 0x0;
+const RTC_WEEKDAY: u8 = 0x6;
+const RTC_DAYS: u8 = 0x7;
+
+// Bandai mapper that only exposes a register select at 0xa001 and a data port at 0xa000
+#[derive(Debug)]
+pub(crate) struct Tama5 {
+    rom: Vec<u8>,
+    ram: [u8; 0x20],
+    register: u8,
+    rom_bank: u8,
+    data: u8,
+    // Bit 0 is bit 4 of the RAM address, bits 1-3 the operation run by the next address write
+    command: u8,
+    latch: u8,
+    clock: RealTimeClock,
+}
+
+impl Tama5 {
+    pub(crate) fn new(rom: Vec<u8>) -> Tama5 {
+        Tama5 { rom, ram: [0; 0x20], register: 0, rom_bank: 1, data: 0, command: 0, latch: 0, clock: RealTimeClock::new() }
+    }
+
+    fn get_date(&self) -> [u64; 7] {
+        let mut days: u64 = self.clock.seconds / SECONDS_PER_DAY;
+        let time: u64 = self.clock.seconds % SECONDS_PER_DAY;
+        let weekday: u64 = (days + 6) % 7;
+        let mut year: u64 = 0;
+        while days >= Tama5::get_days_in_year(year) {
+            days -= Tama5::get_days_in_year(year);
+            year += 1;
+        }
+        let mut month: u64 = 0;
+        while days >= Tama5::get_days_in_month(year, month) {
+            days -= Tama5::get_days_in_month(year, month);
+            month += 1;
+        }
+        [time % 60, (time / 60) % 60, time / 3600, weekday, days + 1, month + 1, year % 100]
+    }
+
+    fn set_date(&mut self, [seconds, minutes, hours, _, day, month, year]: [u64; 7]) {
+        let mut days: u64 = (0..year).map(Tama5::get_days_in_year).sum();
+        days += (0..month.clamp(1, 12) - 1).map(|m| Tama5::get_days_in_month(year, m)).sum::<u64>();
+        days += day.max(1) - 1;
+        self.clock.seconds = days * SECONDS_PER_DAY + hours * 3600 + minutes * 60 + seconds;
+    }
+
+    fn get_days_in_year(year: u64) -> u64 {
+        if year.is_multiple_of(4) { 366 } else { 365 }
+    }
+
+    fn get_days_in_month(year: u64, month: u64) -> u64 {
+        if month == 1 && year.is_multiple_of(4) { 29 } else { DAYS_PER_MONTH[month as usize] }
+    }
+
+    // Index into the date and whether the register holds the tens digit, each BCD value spans two registers
+    fn get_rtc_field(register: u8) -> Option<(usize, bool)> {
+        match register {
+            RTC_SECONDS..=0x5 => Some(((register / 2) as usize, !register.is_multiple_of(2))),
+            RTC_WEEKDAY => Some((3, false)),
+            RTC_DAYS..=0xc => Some((register.div_ceil(2) as usize, register.is_multiple_of(2))),
+            _ => None,
+        }
+    }
+
+    fn read_rtc(&self, register: u8) -> u8 {
+        match Tama5::get_rtc_field(register) {
+            Some((index, true)) => (self.get_date()[index] / 10) as u8,
+            Some((index, false)) => (self.get_date()[index] % 10) as u8,
+            None => 0,
+        }
+    }
+
+    fn write_rtc(&mut self, register: u8, value: u8) {
+        let mut date: [u64; 7] = self.get_date();
+        let value: u64 = (value & 0xf) as u64;
+        match Tama5::get_rtc_field(register) {
+            Some((index, true)) => { date[index] = date[index] % 10 + value * 10 },
+            Some((index, false)) => { date[index] = date[index] / 10 * 10 + value },
+            None => return,
+        }
+        self.set_date(date);
+    }
+
+    fn run_command(&mut self, address_low: u8) {
+        let address: u8 = ((self.command & 0x1) << 4) | (address_low & 0xf);
+        match self.command >> 1 {
+            0x0 => { self.ram[address as usize] = self.data },
+            0x1 => { self.latch = self.ram[address as usize] },
+            0x2 => { self.latch = self.read_rtc(address_low & 0xf) },
+            0x4 => self.write_rtc(address_low & 0xf, self.data),
+            _ => {},
+        }
+    }
+}
+
+impl Mapper for Tama5 {
+    fn read_rom(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3fff => read_rom_bank(&self.rom, 0, address),
+            _ => read_rom_bank(&self.rom, self.rom_bank as usize, address),
+        }
+    }
+
+    fn write_control(&mut self, _address: u16, _value: u8) {}
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if address & 0x1 == 1 {
+            return 0xff;
+        }
+        match self.register {
+            // Bit 0 tells the game the mapper is ready for commands
+            0xa => 0xf1,
+            0xc => 0xf0 | (self.latch & 0xf),
+            0xd => 0xf0 | (self.latch >> 4),
+            _ => 0xff,
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if address & 0x1 == 1 {
+            self.register = value & 0xf;
+            return;
+        }
+        let value: u8 = value & 0xf;
+        match self.register {
+            0x0 => { self.rom_bank = (self.rom_bank & 0x10) | value },
+            0x1 => { self.rom_bank = (self.rom_bank & 0x0f) | ((value & 0x1) << 4) },
+            0x4 => { self.data = (self.data & 0xf0) | value },
+            0x5 => { self.data = (self.data & 0x0f) | (value << 4) },
+            0x6 => { self.command = value },
+            0x7 => self.run_command(value),
+            _ => {},
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        let mut data: Vec<u8> = self.ram.to_vec();
+        self.clock.save(&mut data);
+        Some(data)
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+        self.clock.load(data.get(self.ram.len()..).unwrap_or(&[]));
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.clock.tick(cycles);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::create_test_rom;
+
+    fn write_register(tama5: &mut Tama5, register: u8, value: u8) {
+        tama5.write_ram(0xa001, register);
+        tama5.write_ram(0xa000, value);
+    }
+
+    fn read_register(tama5: &mut Tama5, register: u8) -> u8 {
+        tama5.write_ram(0xa001, register);
+        tama5.read_ram(0xa000)
+    }
+
+    #[test]
+    fn rom_bank_is_set_through_two_registers() {
+        let mut tama5: Tama5 = Tama5::new(create_test_rom(32));
+        assert_eq!(tama5.read_rom(0x4000), 0x01);
+        write_register(&mut tama5, 0x0, 0x05);
+        write_register(&mut tama5, 0x1, 0x01);
+        assert_eq!(tama5.read_rom(0x4000), 0x15);
+        write_register(&mut tama5, 0x0, 0x0a);
+        assert_eq!(tama5.read_rom(0x4000), 0x1a);
+        // The control area isn't connected
+        tama5.write_control(0x2000, 0x02);
+        assert_eq!(tama5.read_rom(0x4000), 0x1a);
+    }
+
+    #[test]
+    fn ram_is_written_and_read_through_commands() {
+        let mut tama5: Tama5 = Tama5::new(create_test_rom(4));
+        assert_eq!(read_register(&mut tama5, 0xa), 0xf1);
+        write_register(&mut tama5, 0x4, 0x04);
+        write_register(&mut tama5, 0x5, 0x03);
+        write_register(&mut tama5, 0x6, 0x01);
+        write_register(&mut tama5, 0x7, 0x02);
+        assert_eq!(tama5.ram[0x12], 0x34);
+        write_register(&mut tama5, 0x6, 0x03);
+        write_register(&mut tama5, 0x7, 0x02);
+        assert_eq!(read_register(&mut tama5, 0xc), 0xf4);
+        assert_eq!(read_register(&mut tama5, 0xd), 0xf3);
+        // Odd addresses only select registers
+        assert_eq!(tama5.read_ram(0xa001), 0xff);
+    }
+
+    #[test]
+    fn rtc_reads_the_calendar() {
+        let mut tama5: Tama5 = Tama5::new(create_test_rom(4));
+        // 2000-02-01 13:00
+        tama5.clock.seconds = 31 * SECONDS_PER_DAY + 13 * 3600;
+        write_register(&mut tama5, 0x6, 0x04);
+        let mut read_rtc = |register: u8| {
+            write_register(&mut tama5, 0x7, register);
+            read_register(&mut tama5, 0xc) & 0xf
+        };
+        assert_eq!([read_rtc(0x4), read_rtc(0x5)], [3, 1]);
+        assert_eq!([read_rtc(0x7), read_rtc(0x8)], [1, 0]);
+        assert_eq!([read_rtc(0x9), read_rtc(0xa)], [2, 0]);
+    }
+
+    #[test]
+    fn ram_and_clock_are_saved() {
+        let mut tama5: Tama5 = Tama5::new(create_test_rom(4));
+        write_register(&mut tama5, 0x4, 0x0a);
+        write_register(&mut tama5, 0x5, 0x05);
+        write_register(&mut tama5, 0x6, 0x01);
+        write_register(&mut tama5, 0x7, 0x0f);
+        tama5.clock.seconds = 123456;
+        let mut loaded: Tama5 = Tama5::new(create_test_rom(4));
+        loaded.load_save_data(&tama5.save_data().unwrap());
+        write_register(&mut loaded, 0x6, 0x03);
+        write_register(&mut loaded, 0x7, 0x0f);
+        assert_eq!(read_register(&mut loaded, 0xc), 0xfa);
+        assert_eq!(read_register(&mut loaded, 0xd), 0xf5);
+        assert!((123456..=123457).contains(&loaded.clock.seconds));
+    }
+}
@@ -4,42 +4,126 @@ use std::path::PathBuf;
 
 use super::{bit_logic, TAC};
 use super::gameboy::Gameboy;
+use super::mapper;
+use super::patch;
+
+// Unused bits read as 1 and unmapped registers as 0xff
+const IO_READ_MASKS: [u8; 0x80] = [
+    0xc0, 0x00, 0x7e, 0xff, 0x00, 0x00, 0x00, 0xf8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xe0, // 0xff00
+    0x80, 0x3f, 0x00, 0xff, 0xbf, 0xff, 0x3f, 0x00, 0xff, 0xbf, 0x7f, 0xff, 0x9f, 0xff, 0xbf, 0xff, // 0xff10
+    0xff, 0x00, 0x00, 0xbf, 0x00, 0x00, 0x70, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // 0xff20
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 0xff30
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, // 0xff40
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // 0xff50
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // 0xff60
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // 0xff70
+];
+
+// Bits that writes can change, read-only bits and unmapped registers keep their value
+const IO_WRITE_MASKS: [u8; 0x80] = [
+    0x30, 0xff, 0x83, 0x00, 0xff, 0xff, 0xff, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1f, // 0xff00
+    0x7f, 0xff, 0xff, 0xff, 0xff, 0x00, 0xff, 0xff, 0xff, 0xff, 0x80, 0xff, 0x60, 0xff, 0xff, 0x00, // 0xff10
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 0xff20
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // 0xff30
+    0xff, 0x78, 0xff, 0xff, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, // 0xff40
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 0xff50
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 0xff60
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 0xff70
+];
+
+const KEY1: u16 = 0xff4d;
+const VBK: u16 = 0xff4f;
+const HDMA1: u16 = 0xff51;
+const HDMA2: u16 = 0xff52;
+const HDMA3: u16 = 0xff53;
+const HDMA4: u16 = 0xff54;
+const HDMA5: u16 = 0xff55;
+const BCPS: u16 = 0xff68;
+const BCPD: u16 = 0xff69;
+const OCPS: u16 = 0xff6a;
+const OCPD: u16 = 0xff6b;
+const SVBK: u16 = 0xff70;
 
 impl Gameboy {
-    pub(crate) fn load_cartridge_from_path(&mut self, rom_path: PathBuf) {
-        let mut file = File::open(rom_path).expect("Invalid ROM path");
-        file.read_to_end(&mut self.cartridge).expect("Unable to read ROM");
-        for i in 0..0x8000 {
-            self.rom[i] = self.cartridge[i];
+    // Patches are applied in memory, without explicit ones game.ips, game.ups and game.bps are picked up
+    pub(crate) fn load_cartridge_from_path(&mut self, rom_path: PathBuf, patch_paths: Vec<PathBuf>) -> Result<(), String> {
+        let mut cartridge: Vec<u8> = Vec::new();
+        File::open(&rom_path)
+            .and_then(|mut file| file.read_to_end(&mut cartridge))
+            .map_err(|error| format!("{}: {}", rom_path.display(), error))?;
+        if patch_paths.is_empty() {
+            // Only the first patch found next to the ROM is applied, they're alternatives rather than a chain
+            let found = patch::PATCH_EXTENSIONS.iter().map(|extension| rom_path.with_extension(extension)).find(|path| path.is_file());
+            if let Some(patch_path) = found {
+                match patch::apply_patch_from_path(&cartridge, &patch_path) {
+                    Ok(patched) => { cartridge = patched },
+                    Err(error) => { eprintln!("Skipping patch {}", error) },
+                }
+            }
+        }
+        for patch_path in patch_paths {
+            cartridge = patch::apply_patch_from_path(&cartridge, &patch_path)?;
+        }
+        // Patches may shrink the ROM, so the header is checked after them
+        if cartridge.len() < 0x150 {
+            return Err(format!("{}: ROM is {} bytes, too small for a cartridge header", rom_path.display(), cartridge.len()));
         }
-        match self.cartridge[0x147] {
-            1..=3 => { self.mbc1 = true },
-            5 | 6 => { self.mbc2 = true },
-            _ => {},
+        self.header = cartridge[..0x150].to_vec();
+        self.bus.mapper = mapper::from_cartridge(cartridge);
+        self.save_path = rom_path.with_extension("sav");
+        if let Ok(data) = std::fs::read(&self.save_path) {
+            self.bus.mapper.load_save_data(&data);
         }
+        Ok(())
+    }
+
+    pub(crate) fn write_save_file(&self) {
+        if let Some(data) = self.bus.mapper.save_data() {
+            std::fs::write(&self.save_path, data).expect("Unable to write save file");
+        }
+    }
+
+    pub(crate) fn read_from_vram(&self, bank: u8, address: u16) -> u8 {
+        self.bus.vram.read(bank, address)
     }
 
     fn get_gamepad_state(&self) -> u8 {
-        let mut res: u8 = self.rom[0xff00_usize] ^ 0xff;
-        if !bit_logic::check_bit(res, 4) {
-            res &= (self.gamepad_state >> 4) | 0xf0;
-        } else if !bit_logic::check_bit(res, 5) {
+        let select: u8 = self.bus.io[0xff00];
+        let mut res: u8 = select | 0xcf;
+        // P14 low selects the directions, P15 low the buttons
+        if !bit_logic::check_bit(select, 4) {
             res &= (self.gamepad_state & 0xf) | 0xf0;
         }
+        if !bit_logic::check_bit(select, 5) {
+            res &= (self.gamepad_state >> 4) | 0xf0;
+        }
+        if self.sgb_mode {
+            return self.get_sgb_joypad_state(res);
+        }
         res
     }
 
     pub(crate) fn read_from_memory(&self, address: u16) -> u8 {
+        if let Some(value) = self.read_from_boot_rom(address) {
+            return value;
+        }
         match address {
-            0x4000..=0x7fff => {
-                self.cartridge[((address - 0x4000) + ((self.current_rom_bank as u16) * 0x4000)) as usize]
-            },
-            0xa000..=0xbfff => {
-                self.ram_banks[((address - 0xa000) + ((self.current_ram_bank as u16) * 0x2000)) as usize]
-            },
-            0xfea0..=0xfeff => 0xff,
             0xff00 => self.get_gamepad_state(),
-            _ => self.rom[address as usize],
+            KEY1 if self.cgb_mode => {
+                bit_logic::set_bit_to(self.double_speed, 0x7e, 7) | (self.prepare_speed_switch as u8)
+            },
+            VBK if self.cgb_mode => 0xfe | self.bus.vram.bank,
+            HDMA1..=HDMA4 if self.cgb_mode => 0xff,
+            HDMA5 if self.cgb_mode => bit_logic::set_bit_to(!self.hdma_active, self.hdma_length, 7),
+            BCPS if self.cgb_mode => 0x40 | self.bg_palette_index,
+            BCPD if self.cgb_mode => self.bg_palette_ram[(self.bg_palette_index & 0x3f) as usize],
+            OCPS if self.cgb_mode => 0x40 | self.obj_palette_index,
+            OCPD if self.cgb_mode => self.obj_palette_ram[(self.obj_palette_index & 0x3f) as usize],
+            SVBK if self.cgb_mode => 0xf8 | self.bus.wram.bank,
+            0xff26 => self.read_sound_status(),
+            0xff30..=0xff3f => self.read_wave_ram(address),
+            0xff01..=0xff7f => self.bus.io[address] | IO_READ_MASKS[(address - 0xff00) as usize],
+            _ => self.bus.read(address),
         }
     }
 
@@ -50,133 +134,327 @@ impl Gameboy {
         }
     }
 
-    fn do_ram_bank_enable(&mut self, address: u16, value: u8) {
-        if self.mbc2 && bit_logic::bit_value(address as u8, 4) == 1 {
-            return;
+    // Copies one 16 byte block and stalls the CPU for 8 M-cycles of normal speed time
+    pub(crate) fn do_hdma_block(&mut self) {
+        for i in 0..0x10 {
+            let value: u8 = self.read_from_memory(self.hdma_source.wrapping_add(i));
+            self.write_to_memory(0x8000 | (self.hdma_destination.wrapping_add(i) & 0x1fff), value);
         }
-        let test_data: u8 = value & 0xf;
-        if test_data == 0xa {
-            self.enable_ram = true;
-        } else if test_data == 0x0 {
-            self.enable_ram = false;
+        self.hdma_source = self.hdma_source.wrapping_add(0x10);
+        self.hdma_destination = self.hdma_destination.wrapping_add(0x10) & 0x1ff0;
+        self.dma_cycles += if self.double_speed { 64 } else { 32 };
+        if self.hdma_length == 0 {
+            self.hdma_active = false;
+            self.hdma_length = 0x7f;
+        } else {
+            self.hdma_length -= 1;
         }
     }
-    
-    fn do_change_lo_rom_bank(&mut self, value: u8) {
-        if self.mbc2 {
-            self.current_rom_bank = value & 0xf;
-            if self.current_rom_bank == 0 {
-                self.current_rom_bank += 1;
-            }
+
+    fn write_hdma_control(&mut self, value: u8) {
+        if self.hdma_active && !bit_logic::check_bit(value, 7) {
+            // Cancels the H-blank transfer, the remaining length stays readable
+            self.hdma_active = false;
             return;
         }
-        self.current_rom_bank = (self.current_rom_bank & 224) | (value & 31);
-        if self.current_rom_bank == 0 {
-            self.current_rom_bank += 1;
-        }
-    }
-
-    fn handle_banking(&mut self, address: u16, value: u8) {
-        match address {
-            0..=0x1fff => {
-                if self.mbc1 || self.mbc2 {
-                    self.do_ram_bank_enable(address, value);
-                }
-            },
-            0x2000..=0x3fff => {
-                if self.mbc1 || self.mbc2 {
-                    self.do_change_lo_rom_bank(value);
-                }
-            },
-            0x4000..=0x5fff => {
-                if self.mbc1 {
-                    if self.rom_banking {
-                        self.current_rom_bank = (self.current_rom_bank & 31) | (value & 224);
-                        if self.current_rom_bank == 0 {
-                            self.current_rom_bank += 1;
-                        }
-                    } else {
-                        self.current_ram_bank = value & 0x3;
-                    }
-                }
-            },
-            0x6000..=0x7fff => {
-                if self.mbc1 {
-                    self.rom_banking = (value & 0x1) == 0;
-                    if self.rom_banking {
-                        self.current_ram_bank = 0;
-                    }
-                }
-            },
-            _ => {},
+        self.hdma_length = value & 0x7f;
+        if bit_logic::check_bit(value, 7) {
+            self.hdma_active = true;
+            if !self.is_lcd_enabled() {
+                self.do_hdma_block();
+            }
+        } else {
+            let blocks: u8 = self.hdma_length + 1;
+            for _ in 0..blocks {
+                self.do_hdma_block();
+            }
         }
     }
 
     pub(crate) fn write_to_memory(&mut self, address: u16, value: u8) {
         match address {
-            0..=0x7fff => { self.handle_banking(address, value) },
-            0xa000..=0xbfff => {
-                if self.enable_ram {
-                    self.ram_banks[((address - 0xa000) + (self.current_ram_bank as u16) * 0x2000) as usize] = value
-                }
-            },
-            0xc000..=0xdfff => {
-                self.rom[address as usize] = value;
-                if address + 0x2000 <= 0xfdff {
-                    self.rom[(address + 0x2000) as usize] = value
-                }
-            },
-            0xe000..=0xfdff => {
-                self.rom[address as usize] = value;
-                self.rom[(address - 0x2000) as usize] = value
-            },
-            0xfea0..=0xfeff => {},
             TAC => {
                 let current_freq: u8 = self.read_from_memory(TAC) & 0x3;
-                self.rom[address as usize] = value;
+                self.bus.io[address] = value;
                 let new_freq: u8 = self.read_from_memory(TAC) & 0x3;
                 if current_freq != new_freq {
                     self.set_clock_freq();
                 }
             },
-            0xff04 | 0xff44 => {
-                self.rom[address as usize] = 0;
-                if address == 0xff04 {
-                    self.divider_counter = 0;
+            0xff00 => {
+                if self.sgb_mode {
+                    self.write_sgb_joypad(value);
                 }
+                self.write_io_register(address, value);
+            },
+            0xff04 => {
+                let previous_div: u8 = self.bus.io[address];
+                self.bus.io[address] = 0;
+                self.divider_counter = 0;
+                self.update_frame_sequencer(previous_div);
             },
+            0xff40 => { self.write_lcd_control(value) },
+            0xff41 => { self.write_lcd_status(value) },
+            0xff44 => {},
+            0xff45 => { self.write_lcd_y_compare(value) },
             0xff46 => {
+                self.bus.io[address] = value;
                 self.do_dma_transfer(value);
             },
-            0xff14 => {
-                self.rom[address as usize] = value;
-                if value >> 7 == 1 {
-                    self.reset_sound_channel_1(self.read_from_memory(0xff11) & 0x3f);
+            0xff50 => {
+                if self.boot_rom_enabled && value != 0 {
+                    self.finish_boot_rom();
                 }
             },
-            0xff19 => {
-                self.rom[address as usize] = value;
-                if value >> 7 == 1 {
-                    self.reset_sound_channel_2(self.read_from_memory(0xff16) & 0x3f);
-                }
+            KEY1 if self.cgb_mode => {
+                self.prepare_speed_switch = bit_logic::check_bit(value, 0);
             },
-            0xff1e => {
-                self.rom[address as usize] = value;
-                if value >> 7 == 1 {
-                    self.reset_sound_channel_3(self.read_from_memory(0xff1b));
-                }
+            VBK if self.cgb_mode => {
+                self.bus.vram.bank = value & 0x1;
             },
-            0xff23 => {
-                self.rom[address as usize] = value;
-                if value >> 7 == 1 {
-                    self.reset_sound_channel_4(self.read_from_memory(0xff20) & 0x3f);
-                }
+            HDMA1 if self.cgb_mode => {
+                self.hdma_source = ((value as u16) << 8) | (self.hdma_source & 0xff);
+            },
+            HDMA2 if self.cgb_mode => {
+                self.hdma_source = (self.hdma_source & 0xff00) | ((value as u16) & 0xf0);
+            },
+            HDMA3 if self.cgb_mode => {
+                self.hdma_destination = (((value as u16) & 0x1f) << 8) | (self.hdma_destination & 0xff);
+            },
+            HDMA4 if self.cgb_mode => {
+                self.hdma_destination = (self.hdma_destination & 0xff00) | ((value as u16) & 0xf0);
             },
-            _ => { self.rom[address as usize] = value },
+            HDMA5 if self.cgb_mode => {
+                self.write_hdma_control(value);
+            },
+            BCPS if self.cgb_mode => {
+                Gameboy::write_palette_index(&mut self.bg_palette_index, value);
+            },
+            BCPD if self.cgb_mode => {
+                Gameboy::write_palette_data(&mut self.bg_palette_ram, &mut self.bg_palette_index, value);
+            },
+            OCPS if self.cgb_mode => {
+                Gameboy::write_palette_index(&mut self.obj_palette_index, value);
+            },
+            OCPD if self.cgb_mode => {
+                Gameboy::write_palette_data(&mut self.obj_palette_ram, &mut self.obj_palette_index, value);
+            },
+            SVBK if self.cgb_mode => {
+                // Bank 0 can't be mapped to 0xd000
+                self.bus.wram.bank = (value & 0x7).max(1);
+            },
+            0xff10..=0xff3f => { self.write_audio_register(address, value) },
+            0xff01..=0xff7f => { self.write_io_register(address, value) },
+            _ => { self.bus.write(address, value) },
         }
     }
 
+    pub(crate) fn write_io_register(&mut self, address: u16, value: u8) {
+        let mask: u8 = IO_WRITE_MASKS[(address - 0xff00) as usize];
+        self.bus.io[address] = (self.bus.io[address] & !mask) | (value & mask);
+    }
+
     pub(crate) fn request_interrupt(&mut self, interrupt_id: u8) {
         self.write_to_memory(0xff0f, bit_logic::set_bit(self.read_from_memory(0xff0f), interrupt_id));
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unused bits of the DMG registers as the unused_hwio test expects them, everything else in 0xff00-0xff7f is unmapped
+    const UNUSED_BITS: [(u16, u8); 27] = [
+        (0xff00, 0xc0), (0xff01, 0x00), (0xff02, 0x7e), (0xff04, 0x00), (0xff05, 0x00), (0xff06, 0x00), (0xff07, 0xf8), (0xff0f, 0xe0),
+        (0xff10, 0x80), (0xff11, 0x3f), (0xff12, 0x00), (0xff13, 0xff), (0xff14, 0xbf), (0xff16, 0x3f), (0xff17, 0x00), (0xff18, 0xff),
+        (0xff19, 0xbf), (0xff1a, 0x7f), (0xff1b, 0xff), (0xff1c, 0x9f), (0xff1d, 0xff), (0xff1e, 0xbf), (0xff20, 0xff), (0xff23, 0xbf),
+        (0xff26, 0x70), (0xff41, 0x80), (0xff46, 0x00),
+    ];
+
+    fn get_unused_bits(address: u16) -> u8 {
+        match address {
+            0xff21 | 0xff22 | 0xff24 | 0xff25 | 0xff30..=0xff3f | 0xff40 | 0xff42..=0xff45 | 0xff47..=0xff4b => 0x00,
+            _ => UNUSED_BITS.iter().find(|(register, _)| *register == address).map_or(0xff, |&(_, bits)| bits),
+        }
+    }
+
+    #[test]
+    fn io_read_masks_match_unused_bits() {
+        for address in 0xff00..=0xff7f {
+            assert_eq!(IO_READ_MASKS[(address - 0xff00) as usize], get_unused_bits(address), "register {:04x}", address);
+        }
+    }
+
+    #[test]
+    fn unused_io_bits_read_as_1() {
+        let mut gameboy: Gameboy = Gameboy::new();
+        for address in 0xff00..=0xff7f {
+            gameboy.write_to_memory(address, 0x00);
+        }
+        for address in 0xff00..=0xff7f {
+            let unused_bits: u8 = get_unused_bits(address);
+            assert_eq!(gameboy.read_from_memory(address) & unused_bits, unused_bits, "register {:04x}", address);
+        }
+    }
+
+    #[test]
+    fn io_writes_leave_read_only_bits_alone() {
+        let mut gameboy: Gameboy = Gameboy::new();
+        // The mode and coincidence bits of STAT belong to the LCD
+        gameboy.bus.io[0xff40] = 0x00;
+        gameboy.bus.io[0xff41] = 0x07;
+        gameboy.write_to_memory(0xff41, 0x78);
+        assert_eq!(gameboy.read_from_memory(0xff41), 0xff);
+        gameboy.bus.io[0xff44] = 0x12;
+        gameboy.write_to_memory(0xff44, 0x34);
+        assert_eq!(gameboy.read_from_memory(0xff44), 0x12);
+    }
+
+    // 0x20 bytes counting up at 0xc000 and a transfer to 0x8000 set up
+    fn create_gameboy() -> Gameboy {
+        let mut gameboy: Gameboy = Gameboy::new();
+        gameboy.cgb_mode = true;
+        for i in 0..0x20 {
+            gameboy.write_to_memory(0xc000 + i, i as u8 + 1);
+        }
+        gameboy.write_to_memory(HDMA1, 0xc0);
+        gameboy.write_to_memory(HDMA2, 0x00);
+        gameboy.write_to_memory(HDMA3, 0x80);
+        gameboy.write_to_memory(HDMA4, 0x00);
+        gameboy
+    }
+
+    fn copied_bytes(gameboy: &Gameboy) -> usize {
+        (0..0x20).take_while(|&i| gameboy.read_from_vram(0, 0x8000 + i) == i as u8 + 1).count()
+    }
+
+    #[test]
+    fn general_purpose_dma_copies_everything_at_once() {
+        let mut gameboy: Gameboy = create_gameboy();
+        gameboy.write_to_memory(HDMA5, 0x01);
+        assert_eq!(copied_bytes(&gameboy), 0x20);
+        assert_eq!(gameboy.read_from_memory(HDMA5), 0xff);
+        assert_eq!(gameboy.dma_cycles, 64);
+    }
+
+    #[test]
+    fn general_purpose_dma_takes_as_long_in_double_speed() {
+        let mut gameboy: Gameboy = create_gameboy();
+        gameboy.double_speed = true;
+        gameboy.write_to_memory(HDMA5, 0x01);
+        assert_eq!(copied_bytes(&gameboy), 0x20);
+        // Twice the CPU cycles for the same time
+        assert_eq!(gameboy.dma_cycles, 128);
+    }
+
+    #[test]
+    fn hblank_dma_copies_a_block_per_hblank() {
+        let mut gameboy: Gameboy = create_gameboy();
+        gameboy.bus.io[0xff40] = 0x80;
+        gameboy.write_to_memory(HDMA5, 0x81);
+        assert_eq!(copied_bytes(&gameboy), 0);
+        assert_eq!(gameboy.read_from_memory(HDMA5), 0x01);
+        gameboy.do_hdma_block();
+        assert_eq!(copied_bytes(&gameboy), 0x10);
+        assert_eq!(gameboy.read_from_memory(HDMA5), 0x00);
+        gameboy.do_hdma_block();
+        assert_eq!(copied_bytes(&gameboy), 0x20);
+        assert_eq!(gameboy.read_from_memory(HDMA5), 0xff);
+    }
+
+    #[test]
+    fn hblank_dma_starts_right_away_with_the_lcd_off() {
+        let mut gameboy: Gameboy = create_gameboy();
+        gameboy.bus.io[0xff40] = 0x00;
+        gameboy.write_to_memory(HDMA5, 0x81);
+        assert_eq!(copied_bytes(&gameboy), 0x10);
+        assert_eq!(gameboy.read_from_memory(HDMA5), 0x00);
+    }
+
+    #[test]
+    fn hblank_dma_cancels_with_bit_7_clear() {
+        let mut gameboy: Gameboy = create_gameboy();
+        gameboy.bus.io[0xff40] = 0x80;
+        gameboy.write_to_memory(HDMA5, 0x81);
+        gameboy.do_hdma_block();
+        gameboy.write_to_memory(HDMA5, 0x00);
+        assert!(!gameboy.hdma_active);
+        // Bit 7 set for inactive with the remaining length below it
+        assert_eq!(gameboy.read_from_memory(HDMA5), 0x80);
+        assert_eq!(copied_bytes(&gameboy), 0x10);
+    }
+
+    #[test]
+    fn cartridges_are_patched_in_memory_and_checked_for_a_header() {
+        let directory: PathBuf = std::env::temp_dir().join(format!("rpgbe-cartridge-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let rom_path: PathBuf = directory.join("game.gb");
+        let rom: Vec<u8> = vec![0; 0x8000];
+        let mut target: Vec<u8> = rom.clone();
+        target[0x134..0x138].copy_from_slice(b"HACK");
+        std::fs::write(&rom_path, &rom).unwrap();
+        std::fs::write(rom_path.with_extension("bps"), patch::create_bps(&rom, &target)).unwrap();
+        let mut gameboy: Gameboy = Gameboy::new();
+        assert!(gameboy.load_cartridge_from_path(rom_path.clone(), Vec::new()).is_ok());
+        assert_eq!(gameboy.header[0x134..0x138], *b"HACK");
+        assert_eq!(std::fs::read(&rom_path).unwrap(), rom);
+
+        std::fs::write(&rom_path, [0u8; 0x100]).unwrap();
+        std::fs::remove_file(rom_path.with_extension("bps")).unwrap();
+        assert!(gameboy.load_cartridge_from_path(rom_path.clone(), Vec::new()).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(gameboy.load_cartridge_from_path(rom_path, Vec::new()).is_err());
+    }
+
+    // "IPSX" at 0x140
+    fn create_ips() -> Vec<u8> {
+        let mut ips: Vec<u8> = b"PATCH".to_vec();
+        ips.extend_from_slice(&[0x00, 0x01, 0x40, 0x00, 0x04]);
+        ips.extend_from_slice(b"IPSX");
+        ips.extend_from_slice(b"EOF");
+        ips
+    }
+
+    #[test]
+    fn only_the_first_patch_found_is_applied() {
+        let directory: PathBuf = std::env::temp_dir().join(format!("rpgbe-found-patches-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let rom_path: PathBuf = directory.join("game.gb");
+        let rom: Vec<u8> = vec![0; 0x8000];
+        std::fs::write(&rom_path, &rom).unwrap();
+        std::fs::write(rom_path.with_extension("ips"), create_ips()).unwrap();
+        // The BPS would apply cleanly on top of the IPS
+        let ips_target: Vec<u8> = patch::apply_patch(&rom, &create_ips()).unwrap();
+        let mut bps_target: Vec<u8> = ips_target.clone();
+        bps_target[0x134..0x138].copy_from_slice(b"HACK");
+        std::fs::write(rom_path.with_extension("bps"), patch::create_bps(&ips_target, &bps_target)).unwrap();
+        let mut gameboy: Gameboy = Gameboy::new();
+        assert!(gameboy.load_cartridge_from_path(rom_path.clone(), Vec::new()).is_ok());
+        assert_eq!(gameboy.header[0x140..0x144], *b"IPSX");
+        assert_eq!(gameboy.header[0x134..0x138], [0; 4]);
+
+        // A broken patch next to the ROM is skipped
+        std::fs::write(rom_path.with_extension("ips"), b"PATCH\x00").unwrap();
+        assert!(gameboy.load_cartridge_from_path(rom_path.clone(), Vec::new()).is_ok());
+        assert_eq!(gameboy.header[0x140..0x144], [0; 4]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn given_patches_must_apply() {
+        let directory: PathBuf = std::env::temp_dir().join(format!("rpgbe-given-patches-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let rom_path: PathBuf = directory.join("game.gb");
+        let ips_path: PathBuf = directory.join("hack.ips");
+        std::fs::write(&rom_path, vec![0; 0x8000]).unwrap();
+        std::fs::write(&ips_path, create_ips()).unwrap();
+        let mut gameboy: Gameboy = Gameboy::new();
+        assert!(gameboy.load_cartridge_from_path(rom_path.clone(), vec![ips_path.clone()]).is_ok());
+        assert_eq!(gameboy.header[0x140..0x144], *b"IPSX");
+
+        std::fs::write(&ips_path, b"PATCH\x00").unwrap();
+        assert!(gameboy.load_cartridge_from_path(rom_path.clone(), vec![ips_path.clone()]).is_err());
+        assert!(gameboy.load_cartridge_from_path(rom_path, vec![directory.join("missing.ips")]).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+use super::bit_logic;
+
+// Per channel settings applied on top of NR50 and NR51, they only change what is heard and never the APU state
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MixerChannel {
+    pub(crate) muted: bool,
+    pub(crate) solo: bool,
+    pub(crate) volume: f32,
+    // -1.0 is hard left and 1.0 hard right, None keeps the panning of NR51
+    pub(crate) pan: Option<f32>,
+}
+
+impl MixerChannel {
+    fn new() -> MixerChannel {
+        MixerChannel { muted: false, solo: false, volume: 1.0, pan: None }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Mixer {
+    pub(crate) channels: [MixerChannel; 4],
+}
+
+impl Mixer {
+    pub(crate) fn new() -> Mixer {
+        Mixer { channels: [MixerChannel::new(); 4] }
+    }
+
+    pub(crate) fn toggle_muted(&mut self, channel: usize) {
+        self.channels[channel].muted = !self.channels[channel].muted;
+    }
+
+    pub(crate) fn toggle_solo(&mut self, channel: usize) {
+        self.channels[channel].solo = !self.channels[channel].solo;
+    }
+
+    pub(crate) fn set_volume(&mut self, channel: usize, volume: f32) {
+        self.channels[channel].volume = volume.max(0.0);
+    }
+
+    pub(crate) fn set_pan(&mut self, channel: usize, pan: Option<f32>) {
+        self.channels[channel].pan = pan.map(|pan| pan.clamp(-1.0, 1.0));
+    }
+
+    // Left and right gain of a channel, when any channel is soloed only soloed channels are heard and muting beats soloing
+    pub(crate) fn get_gains(&self, channel: usize, nr51: u8) -> (f32, f32) {
+        let settings: &MixerChannel = &self.channels[channel];
+        let soloing: bool = self.channels.iter().any(|channel| channel.solo);
+        if settings.muted || (soloing && !settings.solo) {
+            return (0.0, 0.0);
+        }
+        let (left, right): (f32, f32) = match settings.pan {
+            Some(pan) => ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0)),
+            None => (
+                bit_logic::check_bit(nr51, channel as u8 + 4) as u8 as f32,
+                bit_logic::check_bit(nr51, channel as u8) as u8 as f32,
+            ),
+        };
+        (left * settings.volume, right * settings.volume)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solo_silences_the_other_channels() {
+        let mut mixer: Mixer = Mixer::new();
+        mixer.toggle_solo(1);
+        assert_eq!(mixer.get_gains(0, 0xff), (0.0, 0.0));
+        assert_eq!(mixer.get_gains(1, 0xff), (1.0, 1.0));
+        assert_eq!(mixer.get_gains(3, 0xff), (0.0, 0.0));
+        mixer.toggle_solo(3);
+        assert_eq!(mixer.get_gains(3, 0xff), (1.0, 1.0));
+        mixer.toggle_solo(1);
+        mixer.toggle_solo(3);
+        assert_eq!(mixer.get_gains(0, 0xff), (1.0, 1.0));
+    }
+
+    #[test]
+    fn mute_beats_solo() {
+        let mut mixer: Mixer = Mixer::new();
+        mixer.toggle_solo(0);
+        mixer.toggle_muted(0);
+        assert_eq!(mixer.get_gains(0, 0xff), (0.0, 0.0));
+        // Still soloing, so the rest stay silent too
+        assert_eq!(mixer.get_gains(1, 0xff), (0.0, 0.0));
+        mixer.toggle_muted(0);
+        assert_eq!(mixer.get_gains(0, 0xff), (1.0, 1.0));
+    }
+
+    #[test]
+    fn pan_replaces_nr51() {
+        let mut mixer: Mixer = Mixer::new();
+        // NR51 sends channel 1 left and channel 2 nowhere
+        assert_eq!(mixer.get_gains(0, 0x10), (1.0, 0.0));
+        assert_eq!(mixer.get_gains(1, 0x10), (0.0, 0.0));
+        mixer.set_pan(0, Some(1.0));
+        assert_eq!(mixer.get_gains(0, 0x10), (0.0, 1.0));
+        mixer.set_pan(1, Some(0.0));
+        assert_eq!(mixer.get_gains(1, 0x10), (1.0, 1.0));
+        mixer.set_pan(1, Some(-2.0));
+        assert_eq!(mixer.get_gains(1, 0x10), (1.0, 0.0));
+        mixer.set_pan(0, Some(-0.5));
+        mixer.set_volume(0, 0.5);
+        assert_eq!(mixer.get_gains(0, 0x00), (0.5, 0.25));
+        mixer.set_pan(0, None);
+        assert_eq!(mixer.get_gains(0, 0x01), (0.0, 0.5));
+    }
+}
@@ -0,0 +1,288 @@
+use std::fs;
+use std::path::PathBuf;
+
+pub(crate) type Color = (u8, u8, u8);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Palette {
+    pub(crate) bg: [Color; 4],
+    pub(crate) obj0: [Color; 4],
+    pub(crate) obj1: [Color; 4],
+}
+
+const fn rgb(hex: u32) -> Color {
+    ((hex >> 16) as u8, (hex >> 8) as u8, hex as u8)
+}
+
+const fn shades(colors: [u32; 4]) -> [Color; 4] {
+    [rgb(colors[0]), rgb(colors[1]), rgb(colors[2]), rgb(colors[3])]
+}
+
+const fn uniform(colors: [u32; 4]) -> Palette {
+    Palette { bg: shades(colors), obj0: shades(colors), obj1: shades(colors) }
+}
+
+const fn split(bg: [u32; 4], obj0: [u32; 4], obj1: [u32; 4]) -> Palette {
+    Palette { bg: shades(bg), obj0: shades(obj0), obj1: shades(obj1) }
+}
+
+pub(crate) const GREY: Palette = uniform([0xffffff, 0xcccccc, 0x777777, 0x000000]);
+
+// The CGB boot ROM colorizes DMG games with one of these, selected by title or by a button combination
+const CGB_RED: [u32; 4] = [0xffffff, 0xff8484, 0x943a3a, 0x000000];
+const CGB_BROWN: [u32; 4] = [0xffffff, 0xffad63, 0x843100, 0x000000];
+
+pub(crate) const PRESETS: [(&str, Palette); 16] = [
+    ("Grey", GREY),
+    ("Classic Green", uniform([0x9bbc0f, 0x8bac0f, 0x306230, 0x0f380f])),
+    ("Pocket", uniform([0xc4cfa1, 0x8b956d, 0x4d533c, 0x1f1f1f])),
+    ("Light", uniform([0x00b581, 0x009a71, 0x00694a, 0x004f3b])),
+    ("High Contrast", uniform([0xffffff, 0xaaaaaa, 0x555555, 0x000000])),
+    ("CGB Up", uniform(CGB_BROWN)),
+    ("CGB Up+A", uniform(CGB_RED)),
+    ("CGB Up+B", uniform([0xffe6c5, 0xce9c84, 0x846b29, 0x5a3108])),
+    ("CGB Left", split([0xffffff, 0x63a5ff, 0x0000ff, 0x000000], CGB_RED, CGB_RED)),
+    ("CGB Left+A", split([0xffffff, 0x8c8cde, 0x52528c, 0x000000], CGB_RED, CGB_BROWN)),
+    ("CGB Left+B", uniform([0xffffff, 0xa5a5a5, 0x525252, 0x000000])),
+    ("CGB Down", uniform([0xffffa5, 0xff9494, 0x9494ff, 0x000000])),
+    ("CGB Down+A", uniform([0xffffff, 0xffff00, 0xff0000, 0x000000])),
+    ("CGB Down+B", split([0xffffff, 0xffff00, 0x7b4a00, 0x000000], [0xffffff, 0x63a5ff, 0x0000ff, 0x000000], [0xffffff, 0x7bff31, 0x008400, 0x000000])),
+    ("CGB Right", split([0xffffff, 0x7bff31, 0x0063c5, 0x000000], CGB_RED, CGB_RED)),
+    ("CGB Right+B", uniform([0x000000, 0x008484, 0xffde00, 0xffffff])),
+];
+
+// CGB boot ROM palettes for DMG cartridges, as 15-bit colors
+const TITLE_COLORS: [[u16; 4]; 30] = [
+    [0x7fff, 0x32bf, 0x00d0, 0x0000],
+    [0x639f, 0x4279, 0x15b0, 0x04cb],
+    [0x7fff, 0x6e31, 0x454a, 0x0000],
+    [0x7fff, 0x1bef, 0x0200, 0x0000],
+    [0x7fff, 0x421f, 0x1cf2, 0x0000],
+    [0x7fff, 0x5294, 0x294a, 0x0000],
+    [0x7fff, 0x03ff, 0x012f, 0x0000],
+    [0x7fff, 0x03ef, 0x01d6, 0x0000],
+    [0x7fff, 0x42b5, 0x3dc8, 0x0000],
+    [0x7e74, 0x03ff, 0x0180, 0x0000],
+    [0x67ff, 0x77ac, 0x1a13, 0x2d6b],
+    [0x7ed6, 0x4bff, 0x2175, 0x0000],
+    [0x53ff, 0x4a5f, 0x7e52, 0x0000],
+    [0x4fff, 0x7ed2, 0x3a4c, 0x1ce0],
+    [0x03ed, 0x7fff, 0x255f, 0x0000],
+    [0x036a, 0x021f, 0x03ff, 0x7fff],
+    [0x7fff, 0x01df, 0x0112, 0x0000],
+    [0x231f, 0x035f, 0x00f2, 0x0009],
+    [0x7fff, 0x03ea, 0x011f, 0x0000],
+    [0x299f, 0x001a, 0x000c, 0x0000],
+    [0x7fff, 0x027f, 0x001f, 0x0000],
+    [0x7fff, 0x03e0, 0x0206, 0x0120],
+    [0x7fff, 0x7eeb, 0x001f, 0x7c00],
+    [0x7fff, 0x3fff, 0x7e00, 0x001f],
+    [0x7fff, 0x03ff, 0x001f, 0x0000],
+    [0x03ff, 0x001f, 0x000c, 0x0000],
+    [0x7fff, 0x033f, 0x0193, 0x0000],
+    [0x0000, 0x4200, 0x037f, 0x7fff],
+    [0x7fff, 0x7e8c, 0x7c00, 0x0000],
+    [0x7fff, 0x1bef, 0x6180, 0x0000],
+];
+
+// OBJ0, OBJ1 and BG as the index of their first color in TITLE_COLORS, a few start in the middle of a palette
+const TITLE_COMBINATIONS: [[usize; 3]; 51] = [
+    [16, 16, 116], [72, 72, 72], [80, 80, 80], [96, 96, 96], [36, 36, 36], [0, 0, 0], [108, 108, 108], [20, 20, 20],
+    [48, 48, 48], [104, 104, 104], [64, 32, 32], [16, 112, 112], [16, 8, 8], [12, 16, 16], [16, 116, 116], [112, 16, 112],
+    [8, 68, 8], [64, 64, 32], [16, 16, 28], [16, 16, 72], [16, 16, 80], [76, 76, 36], [15, 15, 44], [68, 68, 8],
+    [16, 16, 8], [16, 16, 12], [112, 112, 0], [12, 12, 0], [0, 0, 4], [72, 88, 72], [80, 88, 80], [96, 88, 96],
+    [64, 88, 32], [68, 16, 52], [111, 0, 56], [111, 16, 60], [76, 88, 36], [64, 112, 40], [16, 92, 112], [68, 88, 8],
+    [16, 0, 8], [16, 112, 12], [112, 12, 0], [12, 112, 16], [84, 112, 16], [12, 112, 0], [100, 12, 112], [0, 112, 32],
+    [16, 12, 112], [112, 12, 24], [16, 112, 116],
+];
+
+// Sums of the 16 title bytes of Nintendo cartridges, the last ones are shared and told apart by the fourth letter
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xd1, 0xdb, 0xf2, 0x3c, 0x8c, 0x92, 0x3d, 0x5c, 0x58, 0xc9, 0x3e, 0x70,
+    0x1d, 0x59, 0x69, 0x19, 0x35, 0xa8, 0x14, 0xaa, 0x75, 0x95, 0x99, 0x34, 0x6f, 0x15, 0xff, 0x97,
+    0x4b, 0x90, 0x17, 0x10, 0x39, 0xf7, 0xf6, 0xa2, 0x49, 0x4e, 0x43, 0x68, 0xe0, 0x8b, 0xf0, 0xce,
+    0x0c, 0x29, 0xe8, 0xb7, 0x86, 0x9a, 0x52, 0x01, 0x9d, 0x71, 0x9c, 0xbd, 0x5d, 0x6d, 0x67, 0x3f,
+    0x6b,
+    0xb3, 0x46, 0x28, 0xa5, 0xc6, 0xd3, 0x27, 0x61, 0x18, 0x66, 0x6a, 0xbf, 0x0d, 0xf4,
+    0xb3, 0x46, 0x28, 0xa5, 0xc6, 0xd3, 0x27, 0x61, 0x18, 0x66, 0x6a, 0xbf, 0x0d, 0xf4,
+    0xb3,
+];
+const FIRST_SHARED_CHECKSUM: usize = 65;
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+// Index into TITLE_COMBINATIONS for every checksum
+const TITLE_PALETTES: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44,
+    21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18, 9, 3, 2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+    5, 42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0,
+    39,
+    36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50,
+    17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18,
+    29,
+];
+
+// Only cartridges licensed by Nintendo get a palette picked by title, everything else gets the first one
+fn get_title_combination(header: &[u8]) -> [usize; 3] {
+    let nintendo: bool = header[0x14b] == 0x01 || (header[0x14b] == 0x33 && &header[0x144..0x146] == b"01");
+    if !nintendo {
+        return TITLE_COMBINATIONS[0];
+    }
+    let checksum: u8 = header[0x134..0x144].iter().fold(0, |sum: u8, &byte| sum.wrapping_add(byte));
+    let index: usize = TITLE_CHECKSUMS.iter().enumerate()
+        .position(|(index, &title_checksum)| {
+            title_checksum == checksum && (index < FIRST_SHARED_CHECKSUM || FOURTH_LETTERS[index - FIRST_SHARED_CHECKSUM] == header[0x137])
+        })
+        .unwrap_or(0);
+    TITLE_COMBINATIONS[TITLE_PALETTES[index] as usize]
+}
+
+fn get_title_colors(first_color: usize) -> [u16; 4] {
+    [0, 1, 2, 3].map(|color| TITLE_COLORS[(first_color + color) / 4][(first_color + color) % 4])
+}
+
+// BG, OBJ0 and OBJ1 as the CGB boot ROM writes them to palette RAM for a DMG cartridge
+pub(crate) fn get_title_palette_colors(header: &[u8]) -> [[u16; 4]; 3] {
+    let [obj0, obj1, bg]: [usize; 3] = get_title_combination(header);
+    [get_title_colors(bg), get_title_colors(obj0), get_title_colors(obj1)]
+}
+
+// The same colors for DMG hardware, for --palette auto
+pub(crate) fn for_title(header: &[u8]) -> Palette {
+    let [bg, obj0, obj1]: [[Color; 4]; 3] = get_title_palette_colors(header).map(|colors| colors.map(cgb_color_to_rgb));
+    Palette { bg, obj0, obj1 }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ColorCorrection {
+    None,
+    Accurate,
+    GbaOnGbc,
+    Balanced,
+}
+
+pub(crate) const COLOR_CORRECTIONS: [ColorCorrection; 4] = [ColorCorrection::None, ColorCorrection::Accurate, ColorCorrection::GbaOnGbc, ColorCorrection::Balanced];
+
+impl ColorCorrection {
+    pub(crate) fn from_name(name: &str) -> Option<ColorCorrection> {
+        match name {
+            "none" => Some(ColorCorrection::None),
+            "accurate" => Some(ColorCorrection::Accurate),
+            "gba" => Some(ColorCorrection::GbaOnGbc),
+            "balanced" => Some(ColorCorrection::Balanced),
+            _ => None,
+        }
+    }
+
+    // Channel mixing matrix and the gamma of the emulated screen over an sRGB display
+    const fn curve(self) -> ([[f32; 3]; 3], f32) {
+        match self {
+            ColorCorrection::None => ([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]], 1.0),
+            ColorCorrection::Accurate => ([[0.8125, 0.125, 0.0625], [0.0, 0.75, 0.25], [0.1875, 0.125, 0.6875]], 1.1),
+            ColorCorrection::GbaOnGbc => ([[0.86, 0.1, 0.04], [0.03, 0.82, 0.15], [0.12, 0.08, 0.8]], 1.45),
+            ColorCorrection::Balanced => ([[0.9, 0.08, 0.02], [0.04, 0.9, 0.06], [0.06, 0.06, 0.88]], 1.05),
+        }
+    }
+}
+
+fn split_cgb_color(color: u16) -> (u8, u8, u8) {
+    ((color & 0x1f) as u8, ((color >> 5) & 0x1f) as u8, ((color >> 10) & 0x1f) as u8)
+}
+
+// CGB colors are 15-bit little-endian BGR, the top 5 bits of each channel give them back
+pub(crate) fn cgb_color_to_rgb(color: u16) -> Color {
+    let (red, green, blue): (u8, u8, u8) = split_cgb_color(color);
+    ((red << 3) | (red >> 2), (green << 3) | (green >> 2), (blue << 3) | (blue >> 2))
+}
+
+// What a CGB color looks like on the emulated screen
+pub(crate) fn correct_cgb_color(color: u16, correction: ColorCorrection) -> Color {
+    if correction == ColorCorrection::None {
+        return cgb_color_to_rgb(color);
+    }
+    let (red, green, blue): (u8, u8, u8) = split_cgb_color(color);
+    let (matrix, gamma): ([[f32; 3]; 3], f32) = correction.curve();
+    let channels: [f32; 3] = [red, green, blue].map(|channel| ((channel as f32) / 31.0).powf(gamma));
+    let [red, green, blue]: [u8; 3] = matrix.map(|row| {
+        let mixed: f32 = row[0] * channels[0] + row[1] * channels[1] + row[2] * channels[2];
+        (mixed.clamp(0.0, 1.0).powf(1.0 / gamma) * 255.0).round() as u8
+    });
+    (red, green, blue)
+}
+
+impl Palette {
+    // Palette files hold one line per color set, e.g. "bg = ffffff 7bff31 0063c5 000000"
+    pub(crate) fn load_from_path(palette_path: PathBuf) -> Result<Palette, String> {
+        let contents = fs::read_to_string(&palette_path).map_err(|error| format!("{}: {}", palette_path.display(), error))?;
+        let mut palette = GREY;
+        for line in contents.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let (name, values) = line.split_once('=').ok_or(format!("Missing '=' in \"{}\"", line))?;
+            let mut colors = [(0, 0, 0); 4];
+            let mut values = values.split_whitespace();
+            for color in colors.iter_mut() {
+                let value = values.next().ok_or(format!("Expected 4 colors in \"{}\"", line))?;
+                *color = rgb(u32::from_str_radix(value.trim_start_matches('#'), 16).map_err(|error| format!("{}: {}", value, error))?);
+            }
+            match name.trim() {
+                "bg" => { palette.bg = colors },
+                "obj0" => { palette.obj0 = colors },
+                "obj1" => { palette.obj1 = colors },
+                "all" => { palette = Palette { bg: colors, obj0: colors, obj1: colors } },
+                other => { return Err(format!("Unknown color set \"{}\"", other)) },
+            }
+        }
+        Ok(palette)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_header(title: &[u8], licensee: u8) -> Vec<u8> {
+        let mut header: Vec<u8> = vec![0; 0x150];
+        header[0x134..0x134 + title.len()].copy_from_slice(title);
+        header[0x14b] = licensee;
+        header
+    }
+
+    #[test]
+    fn title_checksum_picks_palette() {
+        // POKEMON RED, checksum 0x14
+        let [bg, obj0, obj1]: [[u16; 4]; 3] = get_title_palette_colors(&create_header(b"POKEMON RED", 0x01));
+        assert_eq!(bg, [0x7fff, 0x421f, 0x1cf2, 0x0000]);
+        assert_eq!(obj0, [0x7fff, 0x1bef, 0x0200, 0x0000]);
+        assert_eq!(obj1, bg);
+        // TETRIS, checksum 0xdb
+        let [bg, obj0, obj1]: [[u16; 4]; 3] = get_title_palette_colors(&create_header(b"TETRIS", 0x01));
+        assert_eq!(bg, [0x7fff, 0x03ff, 0x001f, 0x0000]);
+        assert_eq!(obj0, bg);
+        assert_eq!(obj1, bg);
+    }
+
+    #[test]
+    fn fourth_letter_separates_shared_checksums() {
+        // POKEMON BLUE shares 0x61 with VEGAS STAKES and is told apart by its E
+        let [bg, obj0, _]: [[u16; 4]; 3] = get_title_palette_colors(&create_header(b"POKEMON BLUE", 0x01));
+        assert_eq!(bg, [0x7fff, 0x7e8c, 0x7c00, 0x0000]);
+        assert_eq!(obj0, [0x7fff, 0x421f, 0x1cf2, 0x0000]);
+        // Still 0x61 but with a fourth letter in none of the rows
+        let header: Vec<u8> = create_header(b"POKQAON BLUE", 0x01);
+        assert_eq!(get_title_combination(&header), TITLE_COMBINATIONS[0]);
+    }
+
+    #[test]
+    fn other_licensees_get_default_palette() {
+        assert_eq!(get_title_combination(&create_header(b"POKEMON RED", 0x08)), TITLE_COMBINATIONS[0]);
+        let mut header: Vec<u8> = create_header(b"POKEMON RED", 0x33);
+        header[0x144..0x146].copy_from_slice(b"01");
+        assert_ne!(get_title_combination(&header), TITLE_COMBINATIONS[0]);
+    }
+
+    #[test]
+    fn title_palette_converts_to_rgb() {
+        let palette: Palette = for_title(&create_header(b"POKEMON RED", 0x01));
+        assert_eq!(palette.bg, shades([0xffffff, 0xff8484, 0x943939, 0x000000]));
+        assert_eq!(palette.obj0, shades([0xffffff, 0x7bff31, 0x008400, 0x000000]));
+    }
+}
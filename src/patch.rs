@@ -0,0 +1,378 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub(crate) const PATCH_EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+// Source, target and patch CRC32s end UPS and BPS files
+const FOOTER_SIZE: usize = 12;
+
+const BPS_SOURCE_READ: u64 = 0;
+const BPS_TARGET_READ: u64 = 1;
+const BPS_SOURCE_COPY: u64 = 2;
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffffffff;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 0x1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+pub(crate) fn apply_patch_from_path(rom: &[u8], patch_path: &Path) -> Result<Vec<u8>, String> {
+    let patch: Vec<u8> = fs::read(patch_path).map_err(|error| format!("{}: {}", patch_path.display(), error))?;
+    apply_patch(rom, &patch).map_err(|error| format!("{}: {}", patch_path.display(), error))
+}
+
+pub(crate) fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(UPS_MAGIC) {
+        apply_ups(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err("Unknown patch format".to_string())
+    }
+}
+
+// Reads patch data front to back, failing on truncated files
+struct PatchReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [u8], offset: usize) -> PatchReader<'a> {
+        PatchReader { data, offset }
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let bytes: &[u8] = self.data.get(self.offset..self.offset + length).ok_or("Patch is truncated")?;
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    fn read_byte(&mut self) -> Result<u8, String> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_big_endian(&mut self, length: usize) -> Result<usize, String> {
+        Ok(self.read_bytes(length)?.iter().fold(0, |value, &byte| (value << 8) | byte as usize))
+    }
+
+    // UPS and BPS numbers, 7 bits at a time with the implicit +1 that makes every encoding unique
+    fn read_number(&mut self) -> Result<u64, String> {
+        let (mut value, mut shift): (u64, u64) = (0, 1);
+        loop {
+            let byte: u8 = self.read_byte()?;
+            value += ((byte & 0x7f) as u64) * shift;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift <<= 7;
+            value += shift;
+        }
+    }
+}
+
+fn write_number(output: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte: u8 = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.push(byte | 0x80);
+            return;
+        }
+        output.push(byte);
+        value -= 1;
+    }
+}
+
+fn read_footer_crc(patch: &[u8], index: usize) -> u32 {
+    let offset: usize = patch.len() - FOOTER_SIZE + index * 4;
+    u32::from_le_bytes(patch[offset..offset + 4].try_into().unwrap())
+}
+
+fn check_footer(patch: &[u8], source: &[u8]) -> Result<(), String> {
+    if patch.len() < FOOTER_SIZE + 4 {
+        return Err("Patch is truncated".to_string());
+    }
+    if crc32(&patch[..patch.len() - 4]) != read_footer_crc(patch, 2) {
+        return Err("Patch checksum mismatch, the patch file is corrupt".to_string());
+    }
+    let (expected, actual): (u32, u32) = (read_footer_crc(patch, 0), crc32(source));
+    if expected != actual {
+        return Err(format!("Source checksum mismatch, expected {:08x} but the ROM is {:08x}", expected, actual));
+    }
+    Ok(())
+}
+
+fn check_target(patch: &[u8], target: &[u8]) -> Result<(), String> {
+    let (expected, actual): (u32, u32) = (read_footer_crc(patch, 1), crc32(target));
+    if expected != actual {
+        return Err(format!("Target checksum mismatch, expected {:08x} but got {:08x}", expected, actual));
+    }
+    Ok(())
+}
+
+// Records of a 24-bit offset and 16-bit size, a size of 0 is a run of one byte
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let mut target: Vec<u8> = rom.to_vec();
+    let mut reader: PatchReader = PatchReader::new(patch, IPS_MAGIC.len());
+    loop {
+        if reader.data.get(reader.offset..reader.offset + 3) == Some(IPS_EOF) {
+            reader.offset += 3;
+            break;
+        }
+        let offset: usize = reader.read_big_endian(3)?;
+        let size: usize = reader.read_big_endian(2)?;
+        let data: Vec<u8> = if size == 0 {
+            let run_size: usize = reader.read_big_endian(2)?;
+            vec![reader.read_byte()?; run_size]
+        } else {
+            reader.read_bytes(size)?.to_vec()
+        };
+        if target.len() < offset + data.len() {
+            target.resize(offset + data.len(), 0);
+        }
+        target[offset..offset + data.len()].copy_from_slice(&data);
+    }
+    // Lunar IPS truncation extension
+    if let Ok(size) = reader.read_big_endian(3) {
+        target.truncate(size);
+    }
+    Ok(target)
+}
+
+// XOR hunks separated by relative offsets
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    check_footer(patch, rom)?;
+    let mut reader: PatchReader = PatchReader::new(&patch[..patch.len() - FOOTER_SIZE], UPS_MAGIC.len());
+    let source_size: usize = reader.read_number()? as usize;
+    let target_size: usize = reader.read_number()? as usize;
+    if source_size != rom.len() {
+        return Err(format!("Source size mismatch, expected {} bytes but the ROM is {}", source_size, rom.len()));
+    }
+    let mut target: Vec<u8> = rom.to_vec();
+    target.resize(target_size, 0);
+    let mut offset: usize = 0;
+    while reader.offset < reader.data.len() {
+        offset += reader.read_number()? as usize;
+        loop {
+            let byte: u8 = reader.read_byte()?;
+            if byte == 0 {
+                offset += 1;
+                break;
+            }
+            if let Some(value) = target.get_mut(offset) {
+                *value ^= byte;
+            }
+            offset += 1;
+        }
+    }
+    check_target(patch, &target)?;
+    Ok(target)
+}
+
+// Actions that copy from the source, the patch or earlier output
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    check_footer(patch, rom)?;
+    let mut reader: PatchReader = PatchReader::new(&patch[..patch.len() - FOOTER_SIZE], BPS_MAGIC.len());
+    let source_size: usize = reader.read_number()? as usize;
+    let target_size: usize = reader.read_number()? as usize;
+    let metadata_size: usize = reader.read_number()? as usize;
+    reader.read_bytes(metadata_size)?;
+    if source_size != rom.len() {
+        return Err(format!("Source size mismatch, expected {} bytes but the ROM is {}", source_size, rom.len()));
+    }
+    let mut target: Vec<u8> = Vec::with_capacity(target_size);
+    let (mut source_offset, mut target_offset): (i64, i64) = (0, 0);
+    while reader.offset < reader.data.len() {
+        let data: u64 = reader.read_number()?;
+        let length: usize = ((data >> 2) + 1) as usize;
+        match data & 0x3 {
+            BPS_SOURCE_READ => {
+                let start: usize = target.len();
+                target.extend_from_slice(rom.get(start..start + length).ok_or("Source read out of bounds")?);
+            },
+            BPS_TARGET_READ => target.extend_from_slice(reader.read_bytes(length)?),
+            BPS_SOURCE_COPY => {
+                source_offset += read_relative_offset(&mut reader)?;
+                let start: usize = source_offset as usize;
+                target.extend_from_slice(rom.get(start..start + length).ok_or("Source copy out of bounds")?);
+                source_offset += length as i64;
+            },
+            // Target copy
+            _ => {
+                target_offset += read_relative_offset(&mut reader)?;
+                // Byte by byte, the copy may overlap the output it produces
+                for _ in 0..length {
+                    let value: u8 = *target.get(target_offset as usize).ok_or("Target copy out of bounds")?;
+                    target.push(value);
+                    target_offset += 1;
+                }
+            },
+        }
+    }
+    if target.len() != target_size {
+        return Err(format!("Target size mismatch, expected {} bytes but got {}", target_size, target.len()));
+    }
+    check_target(patch, &target)?;
+    Ok(target)
+}
+
+fn read_relative_offset(reader: &mut PatchReader) -> Result<i64, String> {
+    let data: u64 = reader.read_number()?;
+    let offset: i64 = (data >> 1) as i64;
+    Ok(if data & 0x1 == 1 { -offset } else { offset })
+}
+
+// Linear BPS, unchanged runs read from the source and everything else is stored in the patch
+pub(crate) fn create_bps(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch: Vec<u8> = BPS_MAGIC.to_vec();
+    write_number(&mut patch, source.len() as u64);
+    write_number(&mut patch, target.len() as u64);
+    write_number(&mut patch, 0);
+    let mut offset: usize = 0;
+    while offset < target.len() {
+        let unchanged: bool = source.get(offset) == Some(&target[offset]);
+        let length: usize = target[offset..].iter().enumerate()
+            .take_while(|&(i, &value)| (source.get(offset + i) == Some(&value)) == unchanged)
+            .count();
+        if unchanged {
+            write_number(&mut patch, (((length - 1) as u64) << 2) | BPS_SOURCE_READ);
+        } else {
+            write_number(&mut patch, (((length - 1) as u64) << 2) | BPS_TARGET_READ);
+            patch.extend_from_slice(&target[offset..offset + length]);
+        }
+        offset += length;
+    }
+    patch.extend_from_slice(&crc32(source).to_le_bytes());
+    patch.extend_from_slice(&crc32(target).to_le_bytes());
+    let patch_crc: u32 = crc32(&patch);
+    patch.extend_from_slice(&patch_crc.to_le_bytes());
+    patch
+}
+
+// original.gb modified.gb output.bps, for rpgbe patch
+pub(crate) fn create_bps_from_args(mut args: impl Iterator<Item = String>) {
+    let source_path = args.next().map(PathBuf::from).expect("No original ROM path given");
+    let target_path = args.next().map(PathBuf::from).expect("No modified ROM path given");
+    let patch_path = args.next().map(PathBuf::from).expect("No patch path given");
+    create_bps_from_paths(source_path, target_path, patch_path).expect("Unable to create patch");
+}
+
+fn create_bps_from_paths(source_path: PathBuf, target_path: PathBuf, patch_path: PathBuf) -> Result<(), String> {
+    let source: Vec<u8> = fs::read(&source_path).map_err(|error| format!("{}: {}", source_path.display(), error))?;
+    let target: Vec<u8> = fs::read(&target_path).map_err(|error| format!("{}: {}", target_path.display(), error))?;
+    fs::write(&patch_path, create_bps(&source, &target)).map_err(|error| format!("{}: {}", patch_path.display(), error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_source() -> Vec<u8> {
+        (0..0x800).map(|i| (i * 13) as u8).collect()
+    }
+
+    fn create_target() -> Vec<u8> {
+        let mut target: Vec<u8> = create_source();
+        target[0x10..0x20].fill(0xaa);
+        target[0x400] ^= 0xff;
+        target.extend_from_slice(b"TRANSLATED");
+        target
+    }
+
+    // XOR hunks of the bytes that differ, each ending on a zero
+    fn create_ups(source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch: Vec<u8> = UPS_MAGIC.to_vec();
+        write_number(&mut patch, source.len() as u64);
+        write_number(&mut patch, target.len() as u64);
+        let get_xor = |offset: usize| source.get(offset).copied().unwrap_or(0) ^ target[offset];
+        let (mut offset, mut last): (usize, usize) = (0, 0);
+        while offset < target.len() {
+            if get_xor(offset) == 0 {
+                offset += 1;
+                continue;
+            }
+            write_number(&mut patch, (offset - last) as u64);
+            while offset < target.len() && get_xor(offset) != 0 {
+                patch.push(get_xor(offset));
+                offset += 1;
+            }
+            patch.push(0);
+            offset += 1;
+            last = offset;
+        }
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let patch_crc: u32 = crc32(&patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn numbers_round_trip() {
+        for value in [0, 1, 0x7f, 0x80, 0x407f, 0x4080, 0x123456789] {
+            let mut data: Vec<u8> = Vec::new();
+            write_number(&mut data, value);
+            assert_eq!(PatchReader::new(&data, 0).read_number(), Ok(value));
+        }
+    }
+
+    #[test]
+    fn ips_applies_records_runs_and_truncation() {
+        let source: Vec<u8> = create_source();
+        let mut patch: Vec<u8> = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x10, 0x00, 0x03, 1, 2, 3]);
+        patch.extend_from_slice(&[0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x04, 0xee]);
+        patch.extend_from_slice(IPS_EOF);
+        let target: Vec<u8> = apply_patch(&source, &patch).unwrap();
+        assert_eq!(target[0x10..0x13], [1, 2, 3]);
+        assert_eq!(target[0x100..0x104], [0xee; 4]);
+        assert_eq!(target[0x104..], source[0x104..]);
+
+        patch.extend_from_slice(&[0x00, 0x02, 0x00]);
+        assert_eq!(apply_patch(&source, &patch).unwrap().len(), 0x200);
+    }
+
+    #[test]
+    fn ups_round_trips() {
+        let (source, target): (Vec<u8>, Vec<u8>) = (create_source(), create_target());
+        assert_eq!(apply_patch(&source, &create_ups(&source, &target)), Ok(target));
+    }
+
+    #[test]
+    fn bps_round_trips() {
+        let (source, target): (Vec<u8>, Vec<u8>) = (create_source(), create_target());
+        assert_eq!(apply_patch(&source, &create_bps(&source, &target)), Ok(target.clone()));
+        // Shrinking works too
+        assert_eq!(apply_patch(&target, &create_bps(&target, &source)), Ok(source));
+    }
+
+    #[test]
+    fn checksum_mismatches_are_reported() {
+        let (source, target): (Vec<u8>, Vec<u8>) = (create_source(), create_target());
+        let mut wrong_source: Vec<u8> = source.clone();
+        wrong_source[0] ^= 0x1;
+        for patch in [create_ups(&source, &target), create_bps(&source, &target)] {
+            assert!(apply_patch(&wrong_source, &patch).unwrap_err().starts_with("Source checksum mismatch"));
+            let mut corrupt: Vec<u8> = patch.clone();
+            corrupt[8] ^= 0x1;
+            assert!(apply_patch(&source, &corrupt).unwrap_err().starts_with("Patch checksum mismatch"));
+        }
+        assert_eq!(apply_patch(&source, b"NOTAPATCH"), Err("Unknown patch format".to_string()));
+    }
+}
@@ -0,0 +1,138 @@
+use super::palette::{self, Color, ColorCorrection};
+
+// Mixes each frame with the previous one to emulate the slow response of the LCD,
+// which games rely on when flickering sprites for transparency
+#[derive(Debug)]
+pub(crate) struct FrameBlender {
+    pub(crate) enabled: bool,
+    previous_frame: Vec<u8>,
+    blended_frame: Vec<u8>,
+}
+
+impl FrameBlender {
+    pub(crate) fn new(enabled: bool) -> FrameBlender {
+        FrameBlender {
+            enabled,
+            previous_frame: Vec::new(),
+            blended_frame: Vec::new(),
+        }
+    }
+
+    pub(crate) fn process<'a>(&'a mut self, frame: &'a [u8]) -> &'a [u8] {
+        if !self.enabled {
+            return frame;
+        }
+        if self.previous_frame.len() != frame.len() {
+            self.previous_frame = frame.to_vec();
+            self.blended_frame = vec![0; frame.len()];
+        }
+        for ((blended, &current), &previous) in self.blended_frame.iter_mut().zip(frame).zip(&self.previous_frame) {
+            *blended = (((current as u16) + (previous as u16)) / 2) as u8;
+        }
+        self.previous_frame.copy_from_slice(frame);
+        &self.blended_frame
+    }
+}
+
+// Color correction of CGB frames, the renderer outputs raw 15-bit colors scaled to 8 bits
+// and a table of all 32768 corrected colors is only rebuilt when the mode changes
+#[derive(Debug)]
+pub(crate) struct ColorCorrector {
+    correction: ColorCorrection,
+    table: Vec<Color>,
+    corrected_frame: Vec<u8>,
+}
+
+impl ColorCorrector {
+    pub(crate) fn new(correction: ColorCorrection) -> ColorCorrector {
+        ColorCorrector {
+            correction,
+            table: ColorCorrector::build_table(correction),
+            corrected_frame: Vec::new(),
+        }
+    }
+
+    fn build_table(correction: ColorCorrection) -> Vec<Color> {
+        (0..0x8000).map(|color| palette::correct_cgb_color(color, correction)).collect()
+    }
+
+    pub(crate) fn get_correction(&self) -> ColorCorrection {
+        self.correction
+    }
+
+    pub(crate) fn set_correction(&mut self, correction: ColorCorrection) {
+        if correction != self.correction {
+            self.correction = correction;
+            self.table = ColorCorrector::build_table(correction);
+        }
+    }
+
+    pub(crate) fn process<'a>(&'a mut self, frame: &'a [u8]) -> &'a [u8] {
+        if self.correction == ColorCorrection::None {
+            return frame;
+        }
+        self.corrected_frame.resize(frame.len(), 0);
+        for (corrected, pixel) in self.corrected_frame.chunks_exact_mut(3).zip(frame.chunks_exact(3)) {
+            let color: usize = ((pixel[0] >> 3) as usize) | (((pixel[1] >> 3) as usize) << 5) | (((pixel[2] >> 3) as usize) << 10);
+            let (red, green, blue): Color = self.table[color];
+            corrected.copy_from_slice(&[red, green, blue]);
+        }
+        &self.corrected_frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(color: u16) -> [u8; 3] {
+        let (red, green, blue): Color = palette::cgb_color_to_rgb(color);
+        [red, green, blue]
+    }
+
+    #[test]
+    fn corrector_maps_raw_colors_through_the_curve() {
+        let mut corrector: ColorCorrector = ColorCorrector::new(ColorCorrection::Accurate);
+        let frame: Vec<u8> = [0x0000, 0x7fff, 0x001f, 0x1234].iter().flat_map(|&color| pixel(color)).collect();
+        let expected: Vec<u8> = [0x0000, 0x7fff, 0x001f, 0x1234].iter().flat_map(|&color| {
+            let (red, green, blue): Color = palette::correct_cgb_color(color, ColorCorrection::Accurate);
+            [red, green, blue]
+        }).collect();
+        assert_eq!(corrector.process(&frame), expected.as_slice());
+        // Pure red bleeds into the other channels
+        assert_ne!(&expected[6..9], &pixel(0x001f));
+    }
+
+    #[test]
+    fn corrector_rebuilds_its_table_when_the_mode_changes() {
+        let mut corrector: ColorCorrector = ColorCorrector::new(ColorCorrection::Accurate);
+        let frame: Vec<u8> = pixel(0x001f).to_vec();
+        corrector.set_correction(ColorCorrection::None);
+        assert_eq!(corrector.process(&frame), frame.as_slice());
+        corrector.set_correction(ColorCorrection::GbaOnGbc);
+        let (red, green, blue): Color = palette::correct_cgb_color(0x001f, ColorCorrection::GbaOnGbc);
+        assert_eq!(corrector.process(&frame), &[red, green, blue]);
+    }
+
+    #[test]
+    fn disabled_blender_passes_frames_through() {
+        let mut blender: FrameBlender = FrameBlender::new(false);
+        assert_eq!(blender.process(&[0, 100, 200]), &[0, 100, 200]);
+        assert_eq!(blender.process(&[200, 100, 0]), &[200, 100, 0]);
+    }
+
+    #[test]
+    fn enabled_blender_averages_with_previous_frame() {
+        let mut blender: FrameBlender = FrameBlender::new(true);
+        assert_eq!(blender.process(&[0, 100, 255]), &[0, 100, 255]);
+        assert_eq!(blender.process(&[255, 100, 0]), &[127, 100, 127]);
+        assert_eq!(blender.process(&[255, 100, 0]), &[255, 100, 0]);
+    }
+
+    #[test]
+    fn blender_restarts_when_the_frame_size_changes() {
+        let mut blender: FrameBlender = FrameBlender::new(true);
+        blender.process(&[0, 0]);
+        assert_eq!(blender.process(&[50, 50, 50]), &[50, 50, 50]);
+    }
+}
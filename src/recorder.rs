@@ -0,0 +1,222 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const RIFF_SIZE_OFFSET: u64 = 4;
+// The sample frame count of the fact chunk, after the 18 byte fmt chunk of float formats
+const FACT_SAMPLE_COUNT_OFFSET: u64 = 46;
+const STEM_COUNT: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SampleFormat {
+    Int16,
+    Float32,
+}
+
+impl SampleFormat {
+    pub(crate) fn from_name(name: &str) -> Option<SampleFormat> {
+        match name {
+            "s16" => Some(SampleFormat::Int16),
+            "f32" => Some(SampleFormat::Float32),
+            _ => None,
+        }
+    }
+
+    fn get_bytes_per_sample(self) -> u16 {
+        match self {
+            SampleFormat::Int16 => 2,
+            SampleFormat::Float32 => 4,
+        }
+    }
+
+    // RIFF, fmt and data chunk headers, float formats need cbSize in fmt and a fact chunk
+    fn get_header_size(self) -> u32 {
+        match self {
+            SampleFormat::Int16 => 44,
+            SampleFormat::Float32 => 58,
+        }
+    }
+
+    // WAVE_FORMAT_PCM or WAVE_FORMAT_IEEE_FLOAT
+    fn get_format_tag(self) -> u16 {
+        match self {
+            SampleFormat::Int16 => 1,
+            SampleFormat::Float32 => 3,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct WavWriter {
+    writer: BufWriter<File>,
+    format: SampleFormat,
+    block_align: u16,
+    data_size: u32,
+}
+
+impl WavWriter {
+    pub(crate) fn create(path: &Path, channels: u16, sample_rate: u32, format: SampleFormat) -> io::Result<WavWriter> {
+        let mut writer: BufWriter<File> = BufWriter::new(File::create(path)?);
+        let block_align: u16 = channels * format.get_bytes_per_sample();
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(format.get_header_size() - 8).to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        let fmt_size: u32 = if format == SampleFormat::Float32 { 18 } else { 16 };
+        writer.write_all(&fmt_size.to_le_bytes())?;
+        writer.write_all(&format.get_format_tag().to_le_bytes())?;
+        writer.write_all(&channels.to_le_bytes())?;
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&(format.get_bytes_per_sample() * 8).to_le_bytes())?;
+        if format == SampleFormat::Float32 {
+            writer.write_all(&0u16.to_le_bytes())?;
+            writer.write_all(b"fact")?;
+            writer.write_all(&4u32.to_le_bytes())?;
+            writer.write_all(&0u32.to_le_bytes())?;
+        }
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter { writer, format, block_align, data_size: 0 })
+    }
+
+    pub(crate) fn write_samples(&mut self, samples: impl Iterator<Item = f32>) -> io::Result<()> {
+        for sample in samples {
+            let sample: f32 = sample.clamp(-1.0, 1.0);
+            match self.format {
+                SampleFormat::Int16 => self.writer.write_all(&((sample * i16::MAX as f32) as i16).to_le_bytes())?,
+                SampleFormat::Float32 => self.writer.write_all(&sample.to_le_bytes())?,
+            }
+            self.data_size += self.format.get_bytes_per_sample() as u32;
+        }
+        Ok(())
+    }
+
+    // Rewrites the chunk sizes, after every write so the file stays playable if rpgbe is killed
+    pub(crate) fn update_header(&mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(RIFF_SIZE_OFFSET))?;
+        self.writer.write_all(&(self.format.get_header_size() - 8 + self.data_size).to_le_bytes())?;
+        if self.format == SampleFormat::Float32 {
+            self.writer.seek(SeekFrom::Start(FACT_SAMPLE_COUNT_OFFSET))?;
+            self.writer.write_all(&(self.data_size / self.block_align as u32).to_le_bytes())?;
+        }
+        self.writer.seek(SeekFrom::Start(self.format.get_header_size() as u64 - 4))?;
+        self.writer.write_all(&self.data_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        let _ = self.update_header();
+    }
+}
+
+// The stereo mix and optionally every sound channel as a mono file next to it, out-1.wav to out-4.wav
+#[derive(Debug)]
+pub(crate) struct AudioRecorder {
+    mix: WavWriter,
+    stems: Vec<WavWriter>,
+}
+
+impl AudioRecorder {
+    pub(crate) fn create(path: &Path, sample_rate: u32, format: SampleFormat, stems: bool) -> io::Result<AudioRecorder> {
+        let mix: WavWriter = WavWriter::create(path, 2, sample_rate, format)?;
+        let stems: Vec<WavWriter> = if stems {
+            (1..=STEM_COUNT).map(|channel| WavWriter::create(&AudioRecorder::get_stem_path(path, channel), 1, sample_rate, format)).collect::<io::Result<_>>()?
+        } else {
+            Vec::new()
+        };
+        Ok(AudioRecorder { mix, stems })
+    }
+
+    fn get_stem_path(path: &Path, channel: usize) -> PathBuf {
+        let stem: String = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+        path.with_file_name(format!("{}-{}.wav", stem, channel))
+    }
+
+    // Takes interleaved stereo samples and interleaved four channel stem samples
+    pub(crate) fn write(&mut self, mix: &[f32], stems: &[f32]) -> io::Result<()> {
+        self.mix.write_samples(mix.iter().copied())?;
+        self.mix.update_header()?;
+        for (channel, writer) in self.stems.iter_mut().enumerate() {
+            writer.write_samples(stems.iter().skip(channel).step_by(STEM_COUNT).copied())?;
+            writer.update_header()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u16(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([data[offset], data[offset + 1]])
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    // Three stereo frames written twice, with the stems alongside
+    fn record(name: &str, format: SampleFormat) -> (Vec<u8>, Vec<u8>) {
+        let path: PathBuf = std::env::temp_dir().join(format!("rpgbe-{}-{}.wav", name, std::process::id()));
+        let mut audio_recorder: AudioRecorder = AudioRecorder::create(&path, 48000, format, true).unwrap();
+        for _ in 0..2 {
+            audio_recorder.write(&[0.5, -0.5, 0.5, -0.5, 0.5, -0.5], &[0.25; 12]).unwrap();
+        }
+        drop(audio_recorder);
+        let (mix, stem): (Vec<u8>, Vec<u8>) = (std::fs::read(&path).unwrap(), std::fs::read(AudioRecorder::get_stem_path(&path, 1)).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        for channel in 1..=STEM_COUNT {
+            std::fs::remove_file(AudioRecorder::get_stem_path(&path, channel)).unwrap();
+        }
+        (mix, stem)
+    }
+
+    #[test]
+    fn pcm_wavs_have_a_16_byte_fmt_chunk() {
+        let (mix, stem): (Vec<u8>, Vec<u8>) = record("pcm", SampleFormat::Int16);
+        assert_eq!(mix.len(), 44 + 24);
+        assert_eq!(mix[0..4], *b"RIFF");
+        assert_eq!(read_u32(&mix, 4), mix.len() as u32 - 8);
+        assert_eq!(mix[8..16], *b"WAVEfmt ");
+        assert_eq!(read_u32(&mix, 16), 16);
+        assert_eq!(read_u16(&mix, 20), 1);
+        assert_eq!(read_u16(&mix, 22), 2);
+        assert_eq!(read_u32(&mix, 24), 48000);
+        assert_eq!(read_u32(&mix, 28), 48000 * 4);
+        assert_eq!(read_u16(&mix, 32), 4);
+        assert_eq!(read_u16(&mix, 34), 16);
+        assert_eq!(mix[36..40], *b"data");
+        assert_eq!(read_u32(&mix, 40), 24);
+        assert_eq!(read_u16(&mix, 44), (0.5 * i16::MAX as f32) as i16 as u16);
+        assert_eq!(read_u16(&stem, 22), 1);
+        assert_eq!(read_u32(&stem, 40), 12);
+    }
+
+    #[test]
+    fn float_wavs_have_cb_size_and_a_fact_chunk() {
+        let (mix, stem): (Vec<u8>, Vec<u8>) = record("float", SampleFormat::Float32);
+        assert_eq!(mix.len(), 58 + 48);
+        assert_eq!(read_u32(&mix, 4), mix.len() as u32 - 8);
+        assert_eq!(read_u32(&mix, 16), 18);
+        assert_eq!(read_u16(&mix, 20), 3);
+        assert_eq!(read_u16(&mix, 22), 2);
+        assert_eq!(read_u32(&mix, 28), 48000 * 8);
+        assert_eq!(read_u16(&mix, 32), 8);
+        assert_eq!(read_u16(&mix, 34), 32);
+        assert_eq!(read_u16(&mix, 36), 0);
+        assert_eq!(mix[38..42], *b"fact");
+        assert_eq!(read_u32(&mix, 42), 4);
+        // Sample frames rather than samples
+        assert_eq!(read_u32(&mix, 46), 6);
+        assert_eq!(mix[50..54], *b"data");
+        assert_eq!(read_u32(&mix, 54), 48);
+        assert_eq!(f32::from_le_bytes(mix[58..62].try_into().unwrap()), 0.5);
+        assert_eq!(read_u32(&stem, 46), 6);
+        assert_eq!(read_u32(&stem, 54), 24);
+    }
+}
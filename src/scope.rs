@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::CYCLES_PER_SECOND;
+
+// Enough for a few frames, readers that fall further behind skip ahead
+const SCOPE_SIZE: usize = 4096;
+// Clocks between samples, 32768 samples per second
+const SCOPE_DECIMATION: u32 = 128;
+
+// The DAC output of every sound channel before mixing and the NR50 and NR51 routing it was mixed with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ScopeSample {
+    pub(crate) channels: [f32; 4],
+    pub(crate) nr50: u8,
+    pub(crate) nr51: u8,
+}
+
+// Ring buffer of scope samples, for the visualizer and CSV dumps
+#[derive(Debug)]
+pub(crate) struct Scope {
+    samples: Vec<ScopeSample>,
+    // Samples written since the start, readers keep their own position in this count
+    written: u64,
+    clocks: u32,
+}
+
+impl Scope {
+    pub(crate) fn new() -> Scope {
+        Scope { samples: vec![ScopeSample { channels: [0.0; 4], nr50: 0, nr51: 0 }; SCOPE_SIZE], written: 0, clocks: 0 }
+    }
+
+    pub(crate) fn push(&mut self, channels: &[f32; 4], nr50: u8, nr51: u8) {
+        self.clocks += 1;
+        if self.clocks < SCOPE_DECIMATION {
+            return;
+        }
+        self.clocks = 0;
+        self.samples[(self.written % SCOPE_SIZE as u64) as usize] = ScopeSample { channels: *channels, nr50, nr51 };
+        self.written += 1;
+    }
+
+    pub(crate) fn get_written(&self) -> u64 {
+        self.written
+    }
+
+    // Samples from a position on with their position, starting later if they were overwritten already
+    pub(crate) fn read_from(&self, position: u64) -> impl Iterator<Item = (u64, ScopeSample)> + '_ {
+        let start: u64 = position.max(self.written.saturating_sub(SCOPE_SIZE as u64));
+        (start..self.written).map(|position| (position, self.samples[(position % SCOPE_SIZE as u64) as usize]))
+    }
+
+    // Seconds since the scope started
+    pub(crate) fn get_time(position: u64) -> f64 {
+        (position * SCOPE_DECIMATION as u64) as f64 / CYCLES_PER_SECOND as f64
+    }
+}
+
+// What the registers of a channel currently ask for, volume is 0 to 15 for every channel
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ChannelStatus {
+    pub(crate) enabled: bool,
+    pub(crate) frequency: f32,
+    pub(crate) volume: u8,
+    // Percentage of the period the square channels are high
+    pub(crate) duty: Option<u8>,
+}
+
+// One row per scope sample, the time in seconds, the four channels and NR50 and NR51
+#[derive(Debug)]
+pub(crate) struct ScopeCsvWriter {
+    writer: BufWriter<File>,
+    position: u64,
+}
+
+impl ScopeCsvWriter {
+    pub(crate) fn create(path: &Path) -> io::Result<ScopeCsvWriter> {
+        let mut writer: BufWriter<File> = BufWriter::new(File::create(path)?);
+        writeln!(writer, "time,channel_1,channel_2,channel_3,channel_4,nr50,nr51")?;
+        Ok(ScopeCsvWriter { writer, position: 0 })
+    }
+
+    // Writes everything the scope got since the last call
+    pub(crate) fn write(&mut self, scope: &Scope) -> io::Result<()> {
+        for (position, sample) in scope.read_from(self.position) {
+            let [channel_1, channel_2, channel_3, channel_4] = sample.channels;
+            writeln!(self.writer, "{:.6},{},{},{},{},{},{}", Scope::get_time(position), channel_1, channel_2, channel_3, channel_4, sample.nr50, sample.nr51)?;
+        }
+        self.position = scope.get_written();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // One decimated sample with every channel at the value
+    fn push_sample(scope: &mut Scope, value: f32, nr50: u8, nr51: u8) {
+        for _ in 0..SCOPE_DECIMATION {
+            scope.push(&[value, -value, value / 2.0, 0.0], nr50, nr51);
+        }
+    }
+
+    #[test]
+    fn samples_are_decimated() {
+        let mut scope: Scope = Scope::new();
+        for _ in 0..SCOPE_DECIMATION - 1 {
+            scope.push(&[1.0; 4], 0x77, 0xff);
+        }
+        assert_eq!(scope.get_written(), 0);
+        scope.push(&[1.0; 4], 0x77, 0xff);
+        assert_eq!(scope.get_written(), 1);
+        assert_eq!(scope.read_from(0).collect::<Vec<_>>(), [(0, ScopeSample { channels: [1.0; 4], nr50: 0x77, nr51: 0xff })]);
+    }
+
+    #[test]
+    fn readers_skip_overwritten_samples() {
+        let mut scope: Scope = Scope::new();
+        for i in 0..SCOPE_SIZE + 10 {
+            push_sample(&mut scope, i as f32, 0, 0);
+        }
+        let samples: Vec<(u64, ScopeSample)> = scope.read_from(0).collect();
+        assert_eq!(samples.len(), SCOPE_SIZE);
+        assert_eq!(samples[0].0, 10);
+        assert_eq!(samples[0].1.channels[0], 10.0);
+        assert_eq!(samples[SCOPE_SIZE - 1].1.channels[0], (SCOPE_SIZE + 9) as f32);
+        // The newest samples wrapped around to the start of the buffer
+        assert_eq!(scope.samples[9].channels[0], (SCOPE_SIZE + 9) as f32);
+        assert_eq!(scope.read_from(SCOPE_SIZE as u64 + 8).map(|(position, _)| position).collect::<Vec<u64>>(), [SCOPE_SIZE as u64 + 8, SCOPE_SIZE as u64 + 9]);
+        assert_eq!(scope.read_from(SCOPE_SIZE as u64 + 10).count(), 0);
+    }
+
+    #[test]
+    fn csv_rows_hold_the_channels_and_routing() {
+        let path: PathBuf = std::env::temp_dir().join(format!("rpgbe-scope-{}.csv", std::process::id()));
+        let mut scope_csv_writer: ScopeCsvWriter = ScopeCsvWriter::create(&path).unwrap();
+        let mut scope: Scope = Scope::new();
+        push_sample(&mut scope, 0.5, 0x77, 0xf3);
+        scope_csv_writer.write(&scope).unwrap();
+        push_sample(&mut scope, 1.0, 0x12, 0x0f);
+        scope_csv_writer.write(&scope).unwrap();
+        drop(scope_csv_writer);
+        let csv: String = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(csv, "time,channel_1,channel_2,channel_3,channel_4,nr50,nr51\n0.000000,0.5,-0.5,0.25,0,119,243\n0.000031,1,-1,0.5,0,18,15\n");
+    }
+}
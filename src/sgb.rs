@@ -0,0 +1,394 @@
+use super::{bit_logic, WIDTH, HEIGHT, SGB_WIDTH, SGB_HEIGHT};
+use super::gameboy::{Gameboy, Sgb, SGB_SCREEN_DATA_SIZE};
+use super::palette::{self, Color};
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0a;
+const PAL_TRN: u8 = 0x0b;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+// The attribute map covers the game screen in 8x8 tiles
+const ATTRIBUTE_WIDTH: usize = 20;
+const ATTRIBUTE_HEIGHT: usize = 18;
+const ATTRIBUTE_FILE_SIZE: usize = 90;
+
+// Position of the game screen inside the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+const DEFAULT_PALETTE: [u16; 4] = [0x7fff, 0x5294, 0x294a, 0x0000];
+
+impl Sgb {
+    pub(crate) fn new() -> Sgb {
+        Sgb {
+            previous_lines: 0x30,
+            packet: [0; 16],
+            packet_bits: 0,
+            receiving: false,
+            packets: Vec::new(),
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![0; 512 * 4],
+            attribute_map: [0; ATTRIBUTE_WIDTH * ATTRIBUTE_HEIGHT],
+            attribute_files: vec![0; 45 * ATTRIBUTE_FILE_SIZE],
+            mask: 0,
+            pending_transfer: None,
+            border_tiles: vec![0; 256 * 32],
+            border_map: vec![0; 0x800],
+            border_palettes: [0; 64],
+            players: 1,
+            current_player: 0,
+            screen_data: vec![0; SGB_SCREEN_DATA_SIZE as usize],
+        }
+    }
+
+    fn set_attribute(&mut self, x: usize, y: usize, palette: u8) {
+        if x < ATTRIBUTE_WIDTH && y < ATTRIBUTE_HEIGHT {
+            self.attribute_map[y * ATTRIBUTE_WIDTH + x] = palette & 0x3;
+        }
+    }
+
+    fn apply_attribute_file(&mut self, file: u8) {
+        let start: usize = ((file as usize) % 45) * ATTRIBUTE_FILE_SIZE;
+        for (index, attribute) in self.attribute_map.iter_mut().enumerate() {
+            let byte: u8 = self.attribute_files[start + index / 4];
+            *attribute = (byte >> (6 - (index % 4) * 2)) & 0x3;
+        }
+    }
+
+    // Color 0 is shared by every palette, the last write wins
+    fn set_palette_pair(&mut self, data: &[u8], first: usize, second: usize) {
+        let color = |index: usize| bit_logic::compose_bytes(data[1 + index * 2], data[2 + index * 2]);
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color(0);
+        }
+        for i in 1..4 {
+            self.palettes[first][i] = color(i);
+            self.palettes[second][i] = color(i + 3);
+        }
+    }
+
+    fn attr_blk(&mut self, data: &[u8]) {
+        let sets: usize = (data[1] & 0x1f) as usize;
+        for set in data[2..].chunks_exact(6).take(sets) {
+            let (control, palettes): (u8, u8) = (set[0] & 0x7, set[1]);
+            let (inside, border, outside): (u8, u8, u8) = (palettes & 0x3, (palettes >> 2) & 0x3, (palettes >> 4) & 0x3);
+            let (x1, y1, x2, y2): (usize, usize, usize, usize) = ((set[2] & 0x1f) as usize, (set[3] & 0x1f) as usize, (set[4] & 0x1f) as usize, (set[5] & 0x1f) as usize);
+            // When only the inside or the outside is changed, the border follows it
+            let border: Option<u8> = match control {
+                1 => Some(inside),
+                4 => Some(outside),
+                _ if bit_logic::check_bit(control, 1) => Some(border),
+                _ => None,
+            };
+            for y in 0..ATTRIBUTE_HEIGHT {
+                for x in 0..ATTRIBUTE_WIDTH {
+                    let in_rectangle: bool = x >= x1 && x <= x2 && y >= y1 && y <= y2;
+                    let on_border: bool = in_rectangle && (x == x1 || x == x2 || y == y1 || y == y2);
+                    if on_border {
+                        if let Some(border) = border {
+                            self.set_attribute(x, y, border);
+                        }
+                    } else if in_rectangle {
+                        if bit_logic::check_bit(control, 0) {
+                            self.set_attribute(x, y, inside);
+                        }
+                    } else if bit_logic::check_bit(control, 2) {
+                        self.set_attribute(x, y, outside);
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, data: &[u8]) {
+        let lines: usize = data[1] as usize;
+        for &line in data[2..].iter().take(lines) {
+            let (number, palette): (usize, u8) = ((line & 0x1f) as usize, (line >> 5) & 0x3);
+            if bit_logic::check_bit(line, 7) {
+                for x in 0..ATTRIBUTE_WIDTH {
+                    self.set_attribute(x, number, palette);
+                }
+            } else {
+                for y in 0..ATTRIBUTE_HEIGHT {
+                    self.set_attribute(number, y, palette);
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, data: &[u8]) {
+        let (after, before, on_line): (u8, u8, u8) = (data[1] & 0x3, (data[1] >> 2) & 0x3, (data[1] >> 4) & 0x3);
+        let horizontal: bool = bit_logic::check_bit(data[1], 6);
+        let coordinate: usize = (data[2] & 0x1f) as usize;
+        for y in 0..ATTRIBUTE_HEIGHT {
+            for x in 0..ATTRIBUTE_WIDTH {
+                let position: usize = if horizontal { y } else { x };
+                let palette: u8 = match position.cmp(&coordinate) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on_line,
+                    std::cmp::Ordering::Greater => after,
+                };
+                self.set_attribute(x, y, palette);
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y): (usize, usize) = ((data[1] % 20) as usize, (data[2] % 18) as usize);
+        let count: usize = bit_logic::compose_bytes(data[3], data[4]) as usize;
+        let vertical: bool = data[5] == 1;
+        for index in 0..count.min(ATTRIBUTE_WIDTH * ATTRIBUTE_HEIGHT) {
+            let Some(&byte) = data.get(6 + index / 4) else { break };
+            self.set_attribute(x, y, (byte >> (6 - (index % 4) * 2)) & 0x3);
+            if vertical {
+                y += 1;
+                if y == ATTRIBUTE_HEIGHT {
+                    y = 0;
+                    x = (x + 1) % ATTRIBUTE_WIDTH;
+                }
+            } else {
+                x += 1;
+                if x == ATTRIBUTE_WIDTH {
+                    x = 0;
+                    y = (y + 1) % ATTRIBUTE_HEIGHT;
+                }
+            }
+        }
+    }
+
+    fn pal_set(&mut self, data: &[u8]) {
+        for (index, palette) in self.palettes.iter_mut().enumerate() {
+            let system_palette: usize = (bit_logic::compose_bytes(data[1 + index * 2], data[2 + index * 2]) & 0x1ff) as usize;
+            palette.copy_from_slice(&self.system_palettes[system_palette * 4..system_palette * 4 + 4]);
+        }
+        // Color 0 of palette 0 is shared by all of them
+        let color_0: u16 = self.palettes[0][0];
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color_0;
+        }
+        if bit_logic::check_bit(data[9], 7) {
+            self.apply_attribute_file(data[9] & 0x3f);
+        }
+        if bit_logic::check_bit(data[9], 6) {
+            self.mask = 0;
+        }
+    }
+
+    fn run_command(&mut self) {
+        let data: Vec<u8> = self.packets.iter().flatten().copied().collect();
+        self.packets.clear();
+        match data[0] >> 3 {
+            PAL01 => { self.set_palette_pair(&data, 0, 1) },
+            PAL23 => { self.set_palette_pair(&data, 2, 3) },
+            PAL03 => { self.set_palette_pair(&data, 0, 3) },
+            PAL12 => { self.set_palette_pair(&data, 1, 2) },
+            ATTR_BLK => { self.attr_blk(&data) },
+            ATTR_LIN => { self.attr_lin(&data) },
+            ATTR_DIV => { self.attr_div(&data) },
+            ATTR_CHR => { self.attr_chr(&data) },
+            PAL_SET => { self.pal_set(&data) },
+            MLT_REQ => {
+                self.players = match data[1] & 0x3 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.current_player = 0;
+            },
+            ATTR_SET => {
+                self.apply_attribute_file(data[1] & 0x3f);
+                if bit_logic::check_bit(data[1], 6) {
+                    self.mask = 0;
+                }
+            },
+            MASK_EN => { self.mask = data[1] & 0x3 },
+            command @ (PAL_TRN | CHR_TRN | PCT_TRN | ATTR_TRN) => {
+                self.pending_transfer = Some((command, data[1]));
+            },
+            _ => {},
+        }
+    }
+
+    fn finish_transfer(&mut self, command: u8, argument: u8, data: &[u8]) {
+        match command {
+            PAL_TRN => {
+                for (index, color) in self.system_palettes.iter_mut().enumerate() {
+                    *color = bit_logic::compose_bytes(data[index * 2], data[index * 2 + 1]);
+                }
+            },
+            CHR_TRN => {
+                let start: usize = if bit_logic::check_bit(argument, 0) { 0x1000 } else { 0 };
+                self.border_tiles[start..start + 0x1000].copy_from_slice(data);
+            },
+            PCT_TRN => {
+                self.border_map.copy_from_slice(&data[..0x800]);
+                for (index, color) in self.border_palettes.iter_mut().enumerate() {
+                    *color = bit_logic::compose_bytes(data[0x800 + index * 2], data[0x801 + index * 2]);
+                }
+            },
+            ATTR_TRN => {
+                self.attribute_files.copy_from_slice(&data[..45 * ATTRIBUTE_FILE_SIZE]);
+            },
+            _ => {},
+        }
+    }
+}
+
+impl Gameboy {
+    pub(crate) fn is_sgb_cartridge(&self) -> bool {
+        self.header[0x146] == 0x03 && self.header[0x14b] == 0x33
+    }
+
+    // Packets are sent a bit at a time over P14 (0) and P15 (1), after a pulse on both resets the transfer
+    pub(crate) fn write_sgb_joypad(&mut self, value: u8) {
+        // The SGB boot ROM only unlocks packets for cartridges that declare SGB support
+        if !self.is_sgb_cartridge() {
+            return;
+        }
+        let lines: u8 = value & 0x30;
+        let previous_lines: u8 = self.sgb.previous_lines;
+        self.sgb.previous_lines = lines;
+        match lines {
+            0x00 => {
+                self.sgb.receiving = true;
+                self.sgb.packet = [0; 16];
+                self.sgb.packet_bits = 0;
+            },
+            0x10 | 0x20 if previous_lines == 0x30 && self.sgb.receiving => {
+                let bit: bool = lines == 0x10;
+                if self.sgb.packet_bits == 128 {
+                    // The stop bit has to be 0
+                    self.sgb.receiving = false;
+                    if !bit {
+                        self.receive_sgb_packet();
+                    }
+                } else {
+                    let index: usize = (self.sgb.packet_bits / 8) as usize;
+                    self.sgb.packet[index] = bit_logic::set_bit_to(bit, self.sgb.packet[index], self.sgb.packet_bits % 8);
+                    self.sgb.packet_bits += 1;
+                }
+            },
+            0x30 if previous_lines == 0x10 && self.sgb.players > 1 => {
+                // Releasing P15 moves on to the next controller
+                self.sgb.current_player = (self.sgb.current_player + 1) % self.sgb.players;
+            },
+            _ => {},
+        }
+    }
+
+    fn receive_sgb_packet(&mut self) {
+        let packet: [u8; 16] = self.sgb.packet;
+        self.sgb.packets.push(packet);
+        let length: usize = (self.sgb.packets[0][0] & 0x7).max(1) as usize;
+        if self.sgb.packets.len() >= length {
+            self.sgb.run_command();
+        }
+    }
+
+    pub(crate) fn get_sgb_joypad_state(&self, state: u8) -> u8 {
+        if self.bus.io[0xff00] & 0x30 == 0x30 {
+            // With both lines high the low nibble holds the current controller
+            0xf0 | (0xf - self.sgb.current_player)
+        } else if self.sgb.current_player != 0 {
+            state | 0xf
+        } else {
+            state
+        }
+    }
+
+    // VRAM transfers take the 256 tiles displayed in the top of the background, left to right
+    fn get_sgb_transfer_data(&self) -> Vec<u8> {
+        let lcd_control: u8 = self.read_from_memory(0xff40);
+        let background_memory: u16 = if bit_logic::check_bit(lcd_control, 3) { 0x9c00 } else { 0x9800 };
+        let mut data: Vec<u8> = Vec::with_capacity(0x1000);
+        for tile in 0u16..256 {
+            let tile_num: u8 = self.read_from_vram(0, background_memory + (tile / 20) * 32 + tile % 20);
+            let tile_location: u16 = if bit_logic::check_bit(lcd_control, 4) {
+                0x8000 + (tile_num as u16) * 16
+            } else {
+                (0x9000 + ((tile_num as i8) as i32) * 16) as u16
+            };
+            data.extend((0..16).map(|i| self.read_from_vram(0, tile_location + i)));
+        }
+        data
+    }
+
+    fn draw_sgb_border(&mut self) {
+        let backdrop: Color = palette::cgb_color_to_rgb(self.sgb.palettes[0][0]);
+        for y in 0..SGB_HEIGHT as usize {
+            for x in 0..SGB_WIDTH as usize {
+                // The game screen is drawn separately so MASK_EN can freeze it
+                if (SCREEN_X..SCREEN_X + WIDTH as usize).contains(&x) && (SCREEN_Y..SCREEN_Y + HEIGHT as usize).contains(&y) {
+                    continue;
+                }
+                let entry: u16 = bit_logic::compose_bytes(self.sgb.border_map[((y / 8) * 32 + x / 8) * 2], self.sgb.border_map[((y / 8) * 32 + x / 8) * 2 + 1]);
+                let tile: usize = (entry & 0xff) as usize;
+                let palette: usize = (((entry >> 10) & 0x7) as usize).saturating_sub(4);
+                let row: usize = if bit_logic::check_bit((entry >> 8) as u8, 7) { 7 - y % 8 } else { y % 8 };
+                let column: u8 = if bit_logic::check_bit((entry >> 8) as u8, 6) { (x % 8) as u8 } else { 7 - (x % 8) as u8 };
+                // SNES tiles store bitplanes 0 and 1 interleaved, then 2 and 3
+                let planes: [u8; 4] = [
+                    self.sgb.border_tiles[tile * 32 + row * 2],
+                    self.sgb.border_tiles[tile * 32 + row * 2 + 1],
+                    self.sgb.border_tiles[tile * 32 + 16 + row * 2],
+                    self.sgb.border_tiles[tile * 32 + 16 + row * 2 + 1],
+                ];
+                let color_num: usize = planes.iter().enumerate().fold(0, |color, (plane, &byte)| color | ((bit_logic::bit_value(byte, column) as usize) << plane));
+                let (red, green, blue): Color = if color_num == 0 {
+                    backdrop
+                } else {
+                    palette::cgb_color_to_rgb(self.sgb.border_palettes[palette * 16 + color_num])
+                };
+                let xy: usize = (y * SGB_WIDTH as usize + x) * 3;
+                self.sgb.screen_data[xy] = red;
+                self.sgb.screen_data[xy + 1] = green;
+                self.sgb.screen_data[xy + 2] = blue;
+            }
+        }
+    }
+
+    fn draw_sgb_screen(&mut self) {
+        // MASK_EN 1 freezes the last frame
+        if self.sgb.mask == 1 {
+            return;
+        }
+        for y in 0..HEIGHT as usize {
+            for x in 0..WIDTH as usize {
+                let (red, green, blue): Color = match self.sgb.mask {
+                    2 => (0, 0, 0),
+                    3 => palette::cgb_color_to_rgb(self.sgb.palettes[0][0]),
+                    _ => {
+                        let palette: usize = self.sgb.attribute_map[(y / 8) * ATTRIBUTE_WIDTH + x / 8] as usize;
+                        let shade: usize = self.screen_shades[y * WIDTH as usize + x] as usize;
+                        palette::cgb_color_to_rgb(self.sgb.palettes[palette][shade])
+                    },
+                };
+                let xy: usize = ((SCREEN_Y + y) * SGB_WIDTH as usize + SCREEN_X + x) * 3;
+                self.sgb.screen_data[xy] = red;
+                self.sgb.screen_data[xy + 1] = green;
+                self.sgb.screen_data[xy + 2] = blue;
+            }
+        }
+    }
+
+    pub(crate) fn update_sgb_frame(&mut self) {
+        if let Some((command, argument)) = self.sgb.pending_transfer.take() {
+            let data: Vec<u8> = self.get_sgb_transfer_data();
+            self.sgb.finish_transfer(command, argument, &data);
+        }
+        self.draw_sgb_border();
+        self.draw_sgb_screen();
+    }
+}
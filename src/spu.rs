@@ -1,6 +1,10 @@
-use super::TIME_BETWEEN_AUDIO_SAMPLING;
 use super::bit_logic;
+use super::CYCLES_PER_SECOND;
+use super::blip::AudioResampler;
+use super::scope::ChannelStatus;
+use super::vgm::VgmLogger;
 use super::gameboy::{Gameboy, SoundChannel1, SoundChannel2, SoundChannel3, SoundChannel4};
+use std::mem;
 
 const WAVE_FORM: [[u8; 8]; 4] = [
     [0, 0, 0, 0, 0, 0, 0, 1,],
@@ -10,151 +14,487 @@ const WAVE_FORM: [[u8; 8]; 4] = [
 ];
 
 const SOUND_CHANNEL_4_DIVISOR: [u8; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+const DUTY_PERCENTAGES: [u8; 4] = [12, 25, 50, 75];
+// Channel 3 output levels of NR32 as an envelope volume
+const SOUND_CHANNEL_3_VOLUMES: [u8; 4] = [0, 15, 7, 3];
+
+const DIV: u16 = 0xff04;
+const NR10: u16 = 0xff10;
+const NR11: u16 = 0xff11;
+const NR12: u16 = 0xff12;
+const NR14: u16 = 0xff14;
+const NR21: u16 = 0xff16;
+const NR22: u16 = 0xff17;
+const NR24: u16 = 0xff19;
+const NR30: u16 = 0xff1a;
+const NR31: u16 = 0xff1b;
+const NR34: u16 = 0xff1e;
+const NR41: u16 = 0xff20;
+const NR42: u16 = 0xff21;
+const NR44: u16 = 0xff23;
+const NR52: u16 = 0xff26;
+const WAVE_RAM: u16 = 0xff30;
+
+// Length counter shared by all channels, stepped at 256 Hz while NRx4 bit 6 is set
+fn clock_length(nrx4: u8, length: &mut u16, enabled: &mut bool) {
+    if bit_logic::check_bit(nrx4, 6) && *length != 0 {
+        *length -= 1;
+        if *length == 0 {
+            *enabled = false;
+        }
+    }
+}
+
+// Volume envelope shared by channels 1, 2 and 4, stepped at 64 Hz
+fn clock_envelope(nrx2: u8, amplitude: &mut i16, envelope_sweeps: &mut u8, envelope_enabled: &mut bool) {
+    let period: u8 = nrx2 & 0b111;
+    if period == 0 || !*envelope_enabled {
+        return;
+    }
+    *envelope_sweeps = envelope_sweeps.saturating_sub(1);
+    if *envelope_sweeps == 0 {
+        *envelope_sweeps = period;
+        let new_amplitude = *amplitude + if bit_logic::check_bit(nrx2, 3) { 1 } else { -1 };
+        if (0..=15).contains(&new_amplitude) {
+            *amplitude = new_amplitude;
+        } else {
+            *envelope_enabled = false;
+        }
+    }
+}
+
+// The DACs turn digital 0 to 15 into -1.0 to 1.0, a DAC that is off outputs nothing
+fn convert_dac(digital: u8, dac_enabled: bool) -> f32 {
+    if dac_enabled {
+        digital as f32 / 7.5 - 1.0
+    } else {
+        0.0
+    }
+}
+
+// "Zombie mode", writing NRx2 while the channel plays changes the volume without a trigger
+fn write_envelope_while_playing(old_nrx2: u8, nrx2: u8, amplitude: &mut i16, envelope_enabled: bool) {
+    if old_nrx2 & 0b111 == 0 && envelope_enabled {
+        *amplitude += 1;
+    } else if !bit_logic::check_bit(old_nrx2, 3) {
+        *amplitude += 2;
+    }
+    if bit_logic::check_bit(old_nrx2 ^ nrx2, 3) {
+        *amplitude = 16 - *amplitude;
+    }
+    *amplitude &= 0xf;
+}
 
 impl Gameboy {
     pub(crate) fn update_audio(&mut self, cycles: u8) {
-        let nr10 = self.read_from_memory(0xff10);
-        let nr11 = self.read_from_memory(0xff11);
-        let nr12 = self.read_from_memory(0xff12);
-        let nr21 = self.read_from_memory(0xff16);
-        let nr22 = self.read_from_memory(0xff17);
-        let nr23 = self.read_from_memory(0xff18);
-        let nr24 = self.read_from_memory(0xff19);
-        let nr30 = self.read_from_memory(0xff1a);
-        let nr32 = self.read_from_memory(0xff1c);
-        let nr33 = self.read_from_memory(0xff1d);
-        let nr34 = self.read_from_memory(0xff1e);
-        let nr42 = self.read_from_memory(0xff21);
-        let nr43 = self.read_from_memory(0xff22);
-        let nr44 = self.read_from_memory(0xff23);
-        let mut nr50: u8;
-        let mut channel_1: f32;
-        let mut channel_2: f32;
-        let mut channel_3: f32;
-        let mut channel_4: f32;
-        let mut nr51: u8;
-        let mut left_results: f32;
-        let mut right_results: f32;
+        let nr21 = self.bus.io[0xff16];
+        let nr32 = self.bus.io[0xff1c];
+        let nr43 = self.bus.io[0xff22];
+        if let Some(vgm_logger) = self.vgm_logger.as_mut() {
+            vgm_logger.tick(cycles);
+        }
 
         for _ in 0..cycles {
-            self.update_sound_channel_1(&nr10, &nr11, &nr12);
-            self.update_sound_channel_2(&nr22, &nr23, &nr24);
-            self.update_sound_channel_3(&nr33, &nr34);
-            self.update_sound_channel_4(&nr42, &nr43, &nr44);
-
-            if self.audio_fill_timer == 0 {
-                self.audio_fill_timer = TIME_BETWEEN_AUDIO_SAMPLING;
-                let (_enable_left_vin, left_volume, _enable_right_vin, right_volume) = {
-                    nr50 = self.read_from_memory(0xff24);
-                    (
-                        nr50 & 0x80 != 0,
-                        (nr50 & 0x70) >> 4,
-                        nr50 & 0x8 != 0,
-                        nr50 & 0x7
-                    )
-                };
-                channel_1 = self.get_amplitude_sound_channel_1();
-                channel_2 = self.get_amplitude_sound_channel_2(&nr21);
-                channel_3 = self.get_amplitude_sound_channel_3(&nr30, &nr32);
-                channel_4 = self.get_amplitude_sound_channel_4();
-                nr51 = self.read_from_memory(0xff25);
-                if nr51 != 0 {
-                    left_results = 0.0;
-                    left_results += if bit_logic::check_bit(nr51, 4) { channel_1 } else { 0.0 };
-                    left_results += if bit_logic::check_bit(nr51, 5) { channel_2 } else { 0.0 };
-                    left_results += if bit_logic::check_bit(nr51, 6) { channel_3 } else { 0.0 };
-                    left_results += if bit_logic::check_bit(nr51, 7) { channel_4 } else { 0.0 };
-                    left_results *= left_volume as f32 / 7.0;
-                    self.audio_data.push(left_results);
-                    right_results = 0.0;
-                    right_results += if bit_logic::check_bit(nr51, 0) { channel_1 } else { 0.0 };
-                    right_results += if bit_logic::check_bit(nr51, 1) { channel_2 } else { 0.0 };
-                    right_results += if bit_logic::check_bit(nr51, 2) { channel_3 } else { 0.0 };
-                    right_results += if bit_logic::check_bit(nr51, 3) { channel_4 } else { 0.0 };
-                    right_results *= right_volume as f32 / 7.0;
-                    self.audio_data.push(right_results);
-                } else {
-                    self.audio_data.push(0.0);
-                    self.audio_data.push(0.0);
+            self.update_sound_channel_1();
+            self.update_sound_channel_2();
+            self.update_sound_channel_3();
+            self.update_sound_channel_4(&nr43);
+
+            let channels: [f32; 4] = [
+                self.get_amplitude_sound_channel_1(),
+                self.get_amplitude_sound_channel_2(&nr21),
+                self.get_amplitude_sound_channel_3(&nr32),
+                self.get_amplitude_sound_channel_4(),
+            ];
+            let (left, right) = self.mix_sound_channels(&channels);
+            self.audio_resampler.push(&[left, right]);
+            if let Some(record_resampler) = self.record_resampler.as_mut() {
+                record_resampler.push(&[left, right]);
+            }
+            if let Some(stem_resampler) = self.stem_resampler.as_mut() {
+                stem_resampler.push(&channels);
+            }
+            if let Some(scope) = self.scope.as_mut() {
+                scope.push(&channels, self.bus.io[0xff24], self.bus.io[0xff25]);
+            }
+        }
+    }
+
+    fn mix_sound_channels(&self, channels: &[f32; 4]) -> (f32, f32) {
+        let (_enable_left_vin, left_volume, _enable_right_vin, right_volume) = {
+            let nr50: u8 = self.bus.io[0xff24];
+            (
+                nr50 & 0x80 != 0,
+                (nr50 & 0x70) >> 4,
+                nr50 & 0x8 != 0,
+                nr50 & 0x7
+            )
+        };
+        let nr51: u8 = self.bus.io[0xff25];
+        // The mixer replaces the NR51 panning, so muting or panning a channel leaves the APU untouched
+        let (mut left_results, mut right_results): (f32, f32) = (0.0, 0.0);
+        for (channel, amplitude) in channels.iter().enumerate() {
+            let (left_gain, right_gain) = self.mixer.get_gains(channel, nr51);
+            left_results += amplitude * left_gain;
+            right_results += amplitude * right_gain;
+        }
+        // NR50 volumes 0 to 7 scale by 1/8 to 8/8, and the four channels share the output range
+        left_results *= (left_volume + 1) as f32 / 8.0 / 4.0;
+        right_results *= (right_volume + 1) as f32 / 8.0 / 4.0;
+        (left_results, right_results)
+    }
+
+    // Resamples everything mixed since the last call into audio_data, and record_data and stem_data while recording
+    pub(crate) fn flush_audio(&mut self) {
+        self.audio_resampler.end_frame(&mut self.audio_data);
+        if let Some(record_resampler) = self.record_resampler.as_mut() {
+            record_resampler.end_frame(&mut self.record_data);
+        }
+        if let Some(stem_resampler) = self.stem_resampler.as_mut() {
+            stem_resampler.end_frame(&mut self.stem_data);
+        }
+    }
+
+    pub(crate) fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.audio_resampler = AudioResampler::new(sample_rate, self.model.is_cgb(), 2);
+    }
+
+    // Only what is played is stretched, recordings keep the exact sample rate
+    pub(crate) fn set_audio_rate_adjustment(&mut self, adjustment: f64) {
+        self.audio_resampler.set_rate_adjustment(adjustment);
+    }
+
+    fn get_square_frequency(&self, low: u16, high: u16, clock: f32) -> f32 {
+        let period: u16 = 2048 - ((((self.bus.io[high] as u16) & 0b111) << 8) | (self.bus.io[low] as u16));
+        clock / period as f32
+    }
+
+    // Frequency, volume and duty of every channel for the visualizer
+    pub(crate) fn get_channel_status(&self) -> [ChannelStatus; 4] {
+        let nr43: u8 = self.bus.io[0xff22];
+        [
+            ChannelStatus {
+                enabled: self.sound_channel_1.enabled,
+                frequency: self.get_square_frequency(0xff13, NR14, 131072.0),
+                volume: self.sound_channel_1.amplitude as u8,
+                duty: Some(DUTY_PERCENTAGES[(self.bus.io[NR11] >> 6) as usize]),
+            },
+            ChannelStatus {
+                enabled: self.sound_channel_2.enabled,
+                frequency: self.get_square_frequency(0xff18, NR24, 131072.0),
+                volume: self.sound_channel_2.amplitude as u8,
+                duty: Some(DUTY_PERCENTAGES[(self.bus.io[NR21] >> 6) as usize]),
+            },
+            ChannelStatus {
+                enabled: self.sound_channel_3.enabled,
+                frequency: self.get_square_frequency(0xff1d, NR34, 65536.0),
+                volume: SOUND_CHANNEL_3_VOLUMES[((self.bus.io[0xff1c] >> 5) & 0b11) as usize],
+                duty: None,
+            },
+            ChannelStatus {
+                enabled: self.sound_channel_4.enabled,
+                frequency: CYCLES_PER_SECOND as f32 / ((SOUND_CHANNEL_4_DIVISOR[(nr43 & 0b111) as usize] as u32) << (nr43 >> 4)) as f32,
+                volume: self.sound_channel_4.amplitude as u8,
+                duty: None,
+            },
+        ]
+    }
+
+    // Logs the current registers first, so players starting from powered on hardware end up in the same state
+    pub(crate) fn start_vgm_logging(&mut self) {
+        let mut vgm_logger: VgmLogger = VgmLogger::new();
+        vgm_logger.write_register(NR52, self.bus.io[NR52]);
+        for address in (NR10..NR52).filter(|&address| address != 0xff15 && address != 0xff1f) {
+            let value: u8 = match address {
+                // Without the trigger bit, a trigger would restart the channel from scratch
+                NR14 | NR24 | NR34 | NR44 => self.bus.io[address] & 0x7f,
+                _ => self.bus.io[address],
+            };
+            vgm_logger.write_register(address, value);
+        }
+        for address in WAVE_RAM..WAVE_RAM + 0x10 {
+            vgm_logger.write_register(address, self.bus.io[address]);
+        }
+        self.vgm_logger = Some(vgm_logger);
+    }
+
+    // Stems are interleaved four channel samples in stem_data, in step with record_data
+    pub(crate) fn set_recording_enabled(&mut self, enabled: bool, stems: bool) {
+        let sample_rate: u32 = self.audio_resampler.sample_rate;
+        self.record_resampler = enabled.then(|| AudioResampler::new(sample_rate, self.model.is_cgb(), 2));
+        self.stem_resampler = (enabled && stems).then(|| AudioResampler::new(sample_rate, self.model.is_cgb(), 4));
+        self.record_data.clear();
+        self.stem_data.clear();
+    }
+
+    // The frame sequencer steps on the falling edge of DIV bit 4, bit 5 in double speed where DIV runs twice as fast
+    pub(crate) fn update_frame_sequencer(&mut self, previous_div: u8) {
+        let bit: u8 = if self.double_speed { 5 } else { 4 };
+        if self.is_sound_enabled() && bit_logic::check_bit(previous_div, bit) && !bit_logic::check_bit(self.bus.io[DIV], bit) {
+            self.step_frame_sequencer();
+        }
+    }
+
+    // 512 Hz, length on even steps, sweep on steps 2 and 6 and the envelopes on step 7
+    fn step_frame_sequencer(&mut self) {
+        let step: u8 = self.frame_sequencer;
+        self.frame_sequencer = (self.frame_sequencer + 1) & 0x7;
+        if step.is_multiple_of(2) {
+            clock_length(self.bus.io[NR14], &mut self.sound_channel_1.length, &mut self.sound_channel_1.enabled);
+            clock_length(self.bus.io[NR24], &mut self.sound_channel_2.length, &mut self.sound_channel_2.enabled);
+            clock_length(self.bus.io[NR34], &mut self.sound_channel_3.length, &mut self.sound_channel_3.enabled);
+            clock_length(self.bus.io[NR44], &mut self.sound_channel_4.length, &mut self.sound_channel_4.enabled);
+        }
+        if step == 2 || step == 6 {
+            self.clock_sweep();
+        }
+        if step == 7 {
+            clock_envelope(self.bus.io[NR12], &mut self.sound_channel_1.amplitude, &mut self.sound_channel_1.envelope_sweeps, &mut self.sound_channel_1.envelope_enabled);
+            clock_envelope(self.bus.io[NR22], &mut self.sound_channel_2.amplitude, &mut self.sound_channel_2.envelope_sweeps, &mut self.sound_channel_2.envelope_enabled);
+            clock_envelope(self.bus.io[NR42], &mut self.sound_channel_4.amplitude, &mut self.sound_channel_4.envelope_sweeps, &mut self.sound_channel_4.envelope_enabled);
+        }
+    }
+
+    fn is_sound_enabled(&self) -> bool {
+        bit_logic::check_bit(self.bus.io[NR52], 7)
+    }
+
+    // NR52 only stores the power bit, the low bits report which channels are playing
+    pub(crate) fn read_sound_status(&self) -> u8 {
+        (self.bus.io[NR52] & 0x80) | 0x70
+            | (self.sound_channel_1.enabled as u8)
+            | (self.sound_channel_2.enabled as u8) << 1
+            | (self.sound_channel_3.enabled as u8) << 2
+            | (self.sound_channel_4.enabled as u8) << 3
+    }
+
+    // While channel 3 plays the CPU sees the byte it's playing, on DMG only in the cycle the channel fetches it
+    fn get_wave_ram_address(&self, address: u16) -> Option<u16> {
+        if !self.sound_channel_3.enabled {
+            Some(address)
+        } else if self.model.is_cgb() || self.sound_channel_3.wave_ram_accessible {
+            Some(WAVE_RAM + (self.sound_channel_3.wave_index as u16) / 2)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn read_wave_ram(&self, address: u16) -> u8 {
+        self.get_wave_ram_address(address).map_or(0xff, |address| self.bus.io[address])
+    }
+
+    fn write_wave_ram(&mut self, address: u16, value: u8) {
+        if let Some(address) = self.get_wave_ram_address(address) {
+            self.bus.io[address] = value;
+        }
+    }
+
+    // Powering off clears every register but wave RAM, DMG keeps its length counters powered
+    fn write_sound_control(&mut self, value: u8) {
+        let enabled: bool = bit_logic::check_bit(value, 7);
+        if enabled == self.is_sound_enabled() {
+            return;
+        }
+        if enabled {
+            self.frame_sequencer = 0;
+        } else {
+            for address in NR10..NR52 {
+                self.bus.io[address] = 0;
+            }
+            let lengths: [u16; 4] = [
+                mem::replace(&mut self.sound_channel_1, SoundChannel1::new()).length,
+                mem::replace(&mut self.sound_channel_2, SoundChannel2::new()).length,
+                mem::replace(&mut self.sound_channel_3, SoundChannel3::new()).length,
+                mem::replace(&mut self.sound_channel_4, SoundChannel4::new()).length,
+            ];
+            if !self.model.is_cgb() {
+                self.sound_channel_1.length = lengths[0];
+                self.sound_channel_2.length = lengths[1];
+                self.sound_channel_3.length = lengths[2];
+                self.sound_channel_4.length = lengths[3];
+            }
+        }
+        self.bus.io[NR52] = bit_logic::set_bit_to(enabled, self.bus.io[NR52], 7);
+    }
+
+    // Matches the channels to registers written without side effects, like the ones left behind by the boot ROM
+    pub(crate) fn sync_sound_channels(&mut self) {
+        let nr52: u8 = self.bus.io[NR52];
+        self.sound_channel_1.dac_enabled = self.bus.io[NR12] & 0xf8 != 0;
+        self.sound_channel_2.dac_enabled = self.bus.io[NR22] & 0xf8 != 0;
+        self.sound_channel_3.dac_enabled = bit_logic::check_bit(self.bus.io[NR30], 7);
+        self.sound_channel_4.dac_enabled = self.bus.io[NR42] & 0xf8 != 0;
+        self.sound_channel_1.enabled = self.sound_channel_1.dac_enabled && bit_logic::check_bit(nr52, 0);
+        self.sound_channel_2.enabled = self.sound_channel_2.dac_enabled && bit_logic::check_bit(nr52, 1);
+        self.sound_channel_3.enabled = self.sound_channel_3.dac_enabled && bit_logic::check_bit(nr52, 2);
+        self.sound_channel_4.enabled = self.sound_channel_4.dac_enabled && bit_logic::check_bit(nr52, 3);
+    }
+
+    fn write_sound_length(&mut self, address: u16, value: u8) {
+        match address {
+            NR11 => { self.sound_channel_1.length = 64 - (value & 0x3f) as u16 },
+            NR21 => { self.sound_channel_2.length = 64 - (value & 0x3f) as u16 },
+            NR31 => { self.sound_channel_3.length = 256 - value as u16 },
+            _ => { self.sound_channel_4.length = 64 - (value & 0x3f) as u16 },
+        }
+    }
+
+    // Writes that only change a register value go through the I/O masks, the rest have side effects on the channels
+    pub(crate) fn write_audio_register(&mut self, address: u16, value: u8) {
+        if let Some(vgm_logger) = self.vgm_logger.as_mut() {
+            vgm_logger.write_register(address, value);
+        }
+        if address == NR52 {
+            self.write_sound_control(value);
+            return;
+        }
+        if address >= WAVE_RAM {
+            self.write_wave_ram(address, value);
+            return;
+        }
+        if !self.is_sound_enabled() {
+            if !self.model.is_cgb() && matches!(address, NR11 | NR21 | NR31 | NR41) {
+                self.write_sound_length(address, value);
+            }
+            return;
+        }
+        let old_value: u8 = self.bus.io[address];
+        self.write_io_register(address, value);
+        match address {
+            // Leaving negate mode after a negated sweep calculation disables the channel
+            NR10 if self.sound_channel_1.sweep_negated && !bit_logic::check_bit(value, 3) => {
+                self.sound_channel_1.enabled = false;
+            },
+            NR11 | NR21 | NR31 | NR41 => self.write_sound_length(address, value),
+            // The DAC is on while any of the upper 5 bits of NRx2 are set, turning it off also stops the channel
+            NR12 => {
+                if self.sound_channel_1.enabled {
+                    write_envelope_while_playing(old_value, value, &mut self.sound_channel_1.amplitude, self.sound_channel_1.envelope_enabled);
                 }
-            } else {
-                self.audio_fill_timer -= 1;
+                self.sound_channel_1.dac_enabled = value & 0xf8 != 0;
+                self.sound_channel_1.enabled &= self.sound_channel_1.dac_enabled;
+            },
+            NR22 => {
+                if self.sound_channel_2.enabled {
+                    write_envelope_while_playing(old_value, value, &mut self.sound_channel_2.amplitude, self.sound_channel_2.envelope_enabled);
+                }
+                self.sound_channel_2.dac_enabled = value & 0xf8 != 0;
+                self.sound_channel_2.enabled &= self.sound_channel_2.dac_enabled;
+            },
+            NR30 => {
+                self.sound_channel_3.dac_enabled = bit_logic::check_bit(value, 7);
+                self.sound_channel_3.enabled &= self.sound_channel_3.dac_enabled;
+            },
+            NR42 => {
+                if self.sound_channel_4.enabled {
+                    write_envelope_while_playing(old_value, value, &mut self.sound_channel_4.amplitude, self.sound_channel_4.envelope_enabled);
+                }
+                self.sound_channel_4.dac_enabled = value & 0xf8 != 0;
+                self.sound_channel_4.enabled &= self.sound_channel_4.dac_enabled;
+            },
+            NR14 | NR24 | NR34 | NR44 => self.write_sound_channel_control(address, old_value, value),
+            _ => {},
+        }
+    }
+
+    fn write_sound_channel_control(&mut self, address: u16, old_value: u8, value: u8) {
+        let trigger: bool = bit_logic::check_bit(value, 7);
+        // Enabling length in the half of the period where the next step doesn't clock it clocks it once right away
+        let extra_length_clock: bool = !self.frame_sequencer.is_multiple_of(2);
+        let (length, enabled): (&mut u16, &mut bool) = match address {
+            NR14 => (&mut self.sound_channel_1.length, &mut self.sound_channel_1.enabled),
+            NR24 => (&mut self.sound_channel_2.length, &mut self.sound_channel_2.enabled),
+            NR34 => (&mut self.sound_channel_3.length, &mut self.sound_channel_3.enabled),
+            _ => (&mut self.sound_channel_4.length, &mut self.sound_channel_4.enabled),
+        };
+        if extra_length_clock && !bit_logic::check_bit(old_value, 6) && bit_logic::check_bit(value, 6) && *length != 0 {
+            *length -= 1;
+            if *length == 0 && !trigger {
+                *enabled = false;
             }
         }
+        if !trigger {
+            return;
+        }
+        // Triggering with an expired length reloads the maximum, minus the extra clock when it applies
+        if *length == 0 {
+            let max_length: u16 = if address == NR34 { 256 } else { 64 };
+            *length = if extra_length_clock && bit_logic::check_bit(value, 6) { max_length - 1 } else { max_length };
+        }
+        match address {
+            NR14 => self.reset_sound_channel_1(),
+            NR24 => self.reset_sound_channel_2(),
+            NR34 => self.reset_sound_channel_3(),
+            _ => self.reset_sound_channel_4(),
+        }
     }
 
-    pub(crate) fn reset_sound_channel_1(&mut self, length: u8) {
-        let nr10: u8 = self.read_from_memory(0xff10);
-        let nr12: u8 = self.read_from_memory(0xff12);
-        let nr13: u8 = self.read_from_memory(0xff13);
-        let nr14: u8 = self.read_from_memory(0xff14);
-        if self.sound_channel_1.length == 0 { self.sound_channel_1.length = 64 - length; }
-        self.sound_channel_1.enabled = true;
+    fn reset_sound_channel_1(&mut self) {
+        let nr10: u8 = self.bus.io[0xff10];
+        let nr12: u8 = self.bus.io[0xff12];
+        let nr13: u8 = self.bus.io[0xff13];
+        let nr14: u8 = self.bus.io[0xff14];
+        self.sound_channel_1.enabled = self.sound_channel_1.dac_enabled;
         self.sound_channel_1.amplitude = (nr12 >> 4) as i16;
         self.sound_channel_1.envelope_sweeps = nr12 & 0b111;
         self.sound_channel_1.envelope_enabled = true;
 
-        let new_frequency_timer = (nr14 as u16) & 0b111 << 8 | (nr13 as u16);
+        let new_frequency_timer = (((nr14 as u16) & 0b111) << 8) | (nr13 as u16);
         self.sound_channel_1.frequency_timer = 8192 - (new_frequency_timer * 4);
 
-        self.sound_channel_1.sweep_period = (nr10 >> 4) & 0b111;
+        // A sweep period of 0 reloads the timer with 8
+        let sweep_period: u8 = (nr10 >> 4) & 0b111;
+        self.sound_channel_1.sweep_period = if sweep_period == 0 { 8 } else { sweep_period };
         let sweep_shift = nr10 & 0b111;
-        let sweep_negate = if bit_logic::check_bit(nr10, 3) { -1 } else { 1 };
-        self.sound_channel_1.sweep_enabled = self.sound_channel_1.sweep_period != 0 && sweep_shift != 0;
-        self.sound_channel_1.sweep_shadow = ((nr14 as i16) & 0b111) << 8 | (nr13 as i16);
-        if sweep_shift != 0 && (self.sound_channel_1.sweep_shadow as i32) + ((self.sound_channel_1.sweep_shadow as i32) >> sweep_shift) * sweep_negate > 2047 {
-            self.sound_channel_1.sweep_enabled = false;
-            self.sound_channel_1.enabled = false;
+        self.sound_channel_1.sweep_enabled = sweep_period != 0 || sweep_shift != 0;
+        self.sound_channel_1.sweep_negated = false;
+        self.sound_channel_1.sweep_shadow = (((nr14 as i16) & 0b111) << 8) | (nr13 as i16);
+        if sweep_shift != 0 {
+            self.calculate_sweep_frequency();
         }
-        if nr12 >> 3 == 0 { self.sound_channel_1.enabled = false; }
     }
 
-    fn update_sound_channel_1(&mut self, nr10: &u8, nr11: &u8, nr12: &u8) {
-        let duty = nr11 >> 6;
-        let nr13 = self.read_from_memory(0xff13);
-        let nr14 = self.read_from_memory(0xff14);
-
-        self.sound_channel_1.frame_sequence_timer -= 1;
-        if self.sound_channel_1.frame_sequence_timer == 0 {
-            // TODO: self.sound_channel_1.frame_sequence_timer = (8192 + 1) & 8; THIS EQUALS 0
-            self.sound_channel_1.frame_sequence_timer = 8192;
-            if self.sound_channel_1.frame_sequence % 2 == 0 && bit_logic::check_bit(nr14, 6) && self.sound_channel_1.length != 0 {
-                self.sound_channel_1.length -= 1;
-                if self.sound_channel_1.length == 0 { self.sound_channel_1.enabled = false; }
-            }
+    // Disables the channel when the next frequency would overflow
+    fn calculate_sweep_frequency(&mut self) -> i16 {
+        let nr10: u8 = self.bus.io[NR10];
+        let delta: i16 = self.sound_channel_1.sweep_shadow >> (nr10 & 0b111);
+        let new_frequency: i16 = if bit_logic::check_bit(nr10, 3) {
+            self.sound_channel_1.sweep_negated = true;
+            self.sound_channel_1.sweep_shadow - delta
+        } else {
+            self.sound_channel_1.sweep_shadow + delta
+        };
+        if new_frequency > 2047 {
+            self.sound_channel_1.enabled = false;
         }
+        new_frequency
+    }
 
-        let nr10_and_b111 = nr10 & 0b111;
-        let nr10_shift_right_4_and_b111 = (nr10 >> 4) & 0b111;
-        if (self.sound_channel_1.frame_sequence == 2 || self.sound_channel_1.frame_sequence == 6) && self.sound_channel_1.frame_sequence_timer == 8192 && nr10_shift_right_4_and_b111 != 0 && nr10_and_b111 != 0 {
-            self.sound_channel_1.sweep_period -= 1;
-            if self.sound_channel_1.sweep_period == 0 {
-                self.sound_channel_1.sweep_period = nr10_shift_right_4_and_b111;
-                if self.sound_channel_1.sweep_period == 0 { self.sound_channel_1.sweep_period = 8; }
-                if nr10_shift_right_4_and_b111 != 0 && self.sound_channel_1.sweep_enabled && nr10_and_b111 != 0 {
-                    let sweep_shift = nr10_and_b111;
-                    let sweep_negate = if bit_logic::check_bit(*nr10, 3) { -1 } else { 1 };
-                    let new_frequency = self.sound_channel_1.sweep_shadow + (self.sound_channel_1.sweep_shadow >> sweep_shift) * sweep_negate;
-                    if new_frequency < 2048 && sweep_shift != 0 {
-                        self.sound_channel_1.sweep_shadow = new_frequency;
-                        self.write_to_memory(0xff13, (self.sound_channel_1.sweep_shadow & 0xff) as u8);
-                        self.write_to_memory(0xff14, ((self.sound_channel_1.sweep_shadow >> 8) & 0b111) as u8);
-                        if self.sound_channel_1.sweep_shadow + (self.sound_channel_1.sweep_shadow >> sweep_shift) * sweep_negate > 2047 {
-                            self.sound_channel_1.enabled = false;
-                            self.sound_channel_1.sweep_enabled = false;
-                        }
-                    }
-                    if new_frequency > 2047 {
-                        self.sound_channel_1.enabled = false;
-                        self.sound_channel_1.sweep_enabled = false;
-                    }
-                    if self.sound_channel_1.sweep_shadow + (self.sound_channel_1.sweep_shadow >> sweep_shift) * sweep_negate > 2047 {
-                        self.sound_channel_1.enabled = false;
-                        self.sound_channel_1.sweep_enabled = false;
-                    }
-                }
-            }
+    // 128 Hz, the new frequency is written back and checked for overflow a second time
+    fn clock_sweep(&mut self) {
+        self.sound_channel_1.sweep_period = self.sound_channel_1.sweep_period.saturating_sub(1);
+        if self.sound_channel_1.sweep_period != 0 {
+            return;
         }
+        let nr10: u8 = self.bus.io[NR10];
+        let sweep_period: u8 = (nr10 >> 4) & 0b111;
+        self.sound_channel_1.sweep_period = if sweep_period == 0 { 8 } else { sweep_period };
+        if !self.sound_channel_1.sweep_enabled || sweep_period == 0 {
+            return;
+        }
+        let new_frequency: i16 = self.calculate_sweep_frequency();
+        if new_frequency <= 2047 && nr10 & 0b111 != 0 {
+            self.sound_channel_1.sweep_shadow = new_frequency;
+            self.bus.io[0xff13] = (new_frequency & 0xff) as u8;
+            self.bus.io[NR14] = (self.bus.io[NR14] & 0xf8) | ((new_frequency >> 8) & 0b111) as u8;
+            self.calculate_sweep_frequency();
+        }
+    }
+
+    fn update_sound_channel_1(&mut self) {
+        let duty = self.bus.io[NR11] >> 6;
+        let nr13 = self.bus.io[0xff13];
+        let nr14 = self.bus.io[0xff14];
 
         if self.sound_channel_1.frequency_timer == 0 {
             self.sound_channel_1.frequency_timer = (2048 - ((((nr14 as u16) & 0b111) << 8) | (nr13 as u16))) * 4;
@@ -168,204 +508,124 @@ impl Gameboy {
         } else {
             0
         };
-        
-        if self.sound_channel_1.frame_sequence == 7 && self.sound_channel_1.frame_sequence_timer == 8192 && self.sound_channel_1.envelope_enabled && (nr12 & 0b111) != 0 {
-            self.sound_channel_1.envelope_sweeps -= 1;
-            if self.sound_channel_1.envelope_sweeps == 0 {
-                self.sound_channel_1.envelope_sweeps = nr12 & 0b111;
-                let new_amplitude = self.sound_channel_1.amplitude + if bit_logic::check_bit(*nr12, 3) { 1 } else { -1 };
-                if new_amplitude > 0 && new_amplitude <= 15 {
-                    self.sound_channel_1.amplitude = new_amplitude;
-                    self.sound_channel_1.frequency = self.sound_channel_1.amplitude;
-                } else {
-                    self.sound_channel_1.envelope_enabled = false;
-                }
-            }
-        }
     }
 
     fn get_amplitude_sound_channel_1(&self) -> f32 {
-        if self.sound_channel_1.enabled {
-            self.sound_channel_1.frequency as f32 / 100.0
-        } else {
-            0.0
-        }
+        let digital: u8 = if self.sound_channel_1.enabled { self.sound_channel_1.frequency as u8 } else { 0 };
+        convert_dac(digital, self.sound_channel_1.dac_enabled)
     }
 
-    pub(crate) fn reset_sound_channel_2(&mut self, length: u8) {
-        let nr22: u8 = self.read_from_memory(0xff17);
-        if self.sound_channel_2.length == 0 { self.sound_channel_2.length = 64 - length; }
-        self.sound_channel_2.enabled = true;
+    fn reset_sound_channel_2(&mut self) {
+        let nr22: u8 = self.bus.io[0xff17];
+        self.sound_channel_2.enabled = self.sound_channel_2.dac_enabled;
         self.sound_channel_2.amplitude = (nr22 >> 4) as i16;
         self.sound_channel_2.envelope_sweeps = nr22 & 0b111;
         self.sound_channel_2.envelope_enabled = true;
-        if nr22 >> 3 == 0 { self.sound_channel_2.enabled = false; }
     }
 
-    fn update_sound_channel_2(&mut self, nr22: &u8, nr23: &u8, nr24: &u8) {
+    fn update_sound_channel_2(&mut self) {
         if self.sound_channel_2.frequency_timer == 0 {
-            let new_frequency_timer = (((*nr24 as u16) & 0b111) << 8) | (*nr23 as u16);
+            let new_frequency_timer = (((self.bus.io[NR24] as u16) & 0b111) << 8) | (self.bus.io[0xff18] as u16);
             self.sound_channel_2.frequency_timer = (2048 - new_frequency_timer) * 4;
             self.sound_channel_2.wave_duty_position = (self.sound_channel_2.wave_duty_position + 1) % 8;
         } else {
             self.sound_channel_2.frequency_timer -= 1;
         }
-
-        self.sound_channel_2.frame_sequence_timer -= 1;
-        if self.sound_channel_2.frame_sequence_timer == 0 {
-            self.sound_channel_2.frame_sequence_timer = 8192;
-            self.sound_channel_2.frame_sequence = (self.sound_channel_2.frame_sequence + 1) & 8;
-
-            if self.sound_channel_2.frame_sequence % 2 == 0 && bit_logic::check_bit(*nr24, 6) && self.sound_channel_2.length != 0 {
-                self.sound_channel_2.length -= 1;
-                if self.sound_channel_2.length == 0 { self.sound_channel_2.enabled = false; }
-            }
-
-            if self.sound_channel_2.frame_sequence == 7 && self.sound_channel_2.envelope_enabled && (nr22 & 0b111) != 0 {
-                self.sound_channel_2.envelope_sweeps -= 1;
-                if self.sound_channel_2.envelope_sweeps == 0 {
-                    self.sound_channel_2.envelope_sweeps = nr22 & 0b111;
-                    let new_amplitude = self.sound_channel_2.amplitude + if bit_logic::check_bit(*nr22, 3) { 1 } else { -1 };
-                    if new_amplitude > 0 && new_amplitude <= 15 {
-                        self.sound_channel_2.amplitude = new_amplitude;
-                    } else {
-                        self.sound_channel_2.envelope_enabled = false;
-                    }
-                }
-            }
-        }
     }
 
     fn get_amplitude_sound_channel_2(&self, nr21: &u8) -> f32 {
-        if self.sound_channel_2.enabled {
-            if WAVE_FORM[(nr21 >> 6) as usize][self.sound_channel_2.wave_duty_position as usize] == 1 {
-                (self.sound_channel_2.amplitude as f32) / 100.0
+        let high: bool = WAVE_FORM[(nr21 >> 6) as usize][self.sound_channel_2.wave_duty_position as usize] == 1;
+        let digital: u8 = if self.sound_channel_2.enabled && high { self.sound_channel_2.amplitude as u8 } else { 0 };
+        convert_dac(digital, self.sound_channel_2.dac_enabled)
+    }
+
+    // Triggering on DMG while the channel is reading wave RAM corrupts its first bytes with the ones being read
+    fn reset_sound_channel_3(&mut self) {
+        if !self.model.is_cgb() && self.sound_channel_3.enabled && self.sound_channel_3.frequency_timer <= 1 {
+            let position: u16 = ((self.sound_channel_3.wave_index as u16 + 1) % 32) / 2;
+            if position < 4 {
+                self.bus.io[WAVE_RAM] = self.bus.io[WAVE_RAM + position];
             } else {
-                0.0
+                for offset in 0..4 {
+                    self.bus.io[WAVE_RAM + offset] = self.bus.io[WAVE_RAM + (position & !0b11) + offset];
+                }
             }
-        } else {
-            0.0
         }
-    }
-
-    pub(crate) fn reset_sound_channel_3(&mut self, length: u8) {
-        let nr33 = self.read_from_memory(0xff1d);
-        let nr34 = self.read_from_memory(0xff1e);
-        let new_frequency = ((nr34 as u16) & 0b111) << 8 | (nr33 as u16);
-        self.sound_channel_3.frequency_timer = (2048 - new_frequency) * 2;
-        if self.sound_channel_3.length == 0 { self.sound_channel_3.length = 256 - (length as u16); }
-        self.sound_channel_3.enabled = true;
+        let nr33 = self.bus.io[0xff1d];
+        let nr34 = self.bus.io[0xff1e];
+        let new_frequency = (((nr34 as u16) & 0b111) << 8) | (nr33 as u16);
+        // The first sample is read 3 APU cycles late, until then the stale sample buffer keeps playing
+        self.sound_channel_3.frequency_timer = (2048 - new_frequency) * 2 + 6;
+        self.sound_channel_3.enabled = self.sound_channel_3.dac_enabled;
         self.sound_channel_3.wave_index = 0;
-        if self.read_from_memory(0xff1a) >> 6 == 0 { self.sound_channel_3.enabled = false; }
     }
 
-    fn update_sound_channel_3(&mut self, nr33: &u8, nr34: &u8) {
+    // The channel reads one byte of wave RAM into its sample buffer each time it moves to the next sample
+    fn update_sound_channel_3(&mut self) {
         if self.sound_channel_3.frequency_timer == 0 {
-            let new_frequency_timer = (((*nr34 as u16) & 0b111) << 8) | (*nr33 as u16);
+            let new_frequency_timer = (((self.bus.io[NR34] as u16) & 0b111) << 8) | (self.bus.io[0xff1d] as u16);
             self.sound_channel_3.frequency_timer = (2048 - new_frequency_timer) * 2;
             self.sound_channel_3.wave_index = (self.sound_channel_3.wave_index + 1) % 32;
+            self.sound_channel_3.sample_buffer = self.bus.io[WAVE_RAM + (self.sound_channel_3.wave_index as u16) / 2];
+            self.sound_channel_3.wave_ram_accessible = true;
         } else {
             self.sound_channel_3.frequency_timer -= 1;
-        }
-
-        self.sound_channel_3.frame_sequence_timer -= 1;
-        if self.sound_channel_3.frame_sequence_timer == 0 {
-            self.sound_channel_3.frame_sequence_timer = 8192;
-            self.sound_channel_3.frame_sequence = (self.sound_channel_3.frame_sequence + 1) & 8;
-
-            if self.sound_channel_3.frame_sequence % 2 == 0 && bit_logic::check_bit(*nr34, 6)  && self.sound_channel_3.length != 0 {
-                self.sound_channel_3.length -= 1;
-                if self.sound_channel_3.length == 0 { self.sound_channel_3.enabled = false; }
-            }
+            self.sound_channel_3.wave_ram_accessible = false;
         }
     }
 
-    fn get_amplitude_sound_channel_3(&self, nr30: &u8, nr32: &u8) -> f32 {
-        if self.sound_channel_3.enabled {
-            let mut wave = self.read_from_memory(0xff30 + ((self.sound_channel_3.wave_index as u16) / 2));
-            wave = if self.sound_channel_3.wave_index % 2 != 0 {
-                wave & 0xf
-            } else {
-                wave >> 4
-            };
-            let volume = (nr32 >> 5) & 0b11;
-            wave >>= if volume != 0 {
-                volume - 1
-            } else {
-                4
-            };
-            if nr30 >> 7 != 0 {
-                (wave as f32) / 100.0
-            } else {
-                0.0
-            }
+    // High nibble first, NR32 shifts it right by 0, 1 or 2, or mutes it
+    fn get_amplitude_sound_channel_3(&self, nr32: &u8) -> f32 {
+        let mut sample: u8 = if self.sound_channel_3.wave_index.is_multiple_of(2) {
+            self.sound_channel_3.sample_buffer >> 4
         } else {
-            0.0
-        }
+            self.sound_channel_3.sample_buffer & 0xf
+        };
+        let volume = (nr32 >> 5) & 0b11;
+        sample >>= if volume != 0 {
+            volume - 1
+        } else {
+            4
+        };
+        let digital: u8 = if self.sound_channel_3.enabled { sample } else { 0 };
+        convert_dac(digital, self.sound_channel_3.dac_enabled)
     }
 
-    pub(crate) fn reset_sound_channel_4(&mut self, length: u8) {
-        let nr42 = self.read_from_memory(0xff21);
-        let nr43 = self.read_from_memory(0xff22);
-        if self.sound_channel_4.length == 0 { self.sound_channel_4.length = 64 - length; }
-        self.sound_channel_4.enabled = true;
-        self.sound_channel_4.frequency_timer = (SOUND_CHANNEL_4_DIVISOR[(nr43 as usize) & 0b111] as u16) << ((nr43 as u16) >> 4);
+    fn reset_sound_channel_4(&mut self) {
+        let nr42 = self.bus.io[0xff21];
+        let nr43 = self.bus.io[0xff22];
+        self.sound_channel_4.enabled = self.sound_channel_4.dac_enabled;
+        self.sound_channel_4.frequency_timer = (SOUND_CHANNEL_4_DIVISOR[(nr43 as usize) & 0b111] as u32) << (nr43 >> 4);
         self.sound_channel_4.lfsr = 0x7fff;
         self.sound_channel_4.amplitude = nr42 as i16 >> 4;
         self.sound_channel_4.envelope_sweeps = nr42 & 0b111;
         self.sound_channel_4.envelope_enabled = true;
-        if nr42 >> 3 == 0 { self.sound_channel_4.enabled = false; }
     }
 
-    fn update_sound_channel_4(&mut self, nr42: &u8, nr43: &u8, nr44: &u8) {
-        self.sound_channel_4.frame_sequence_timer -= 1;
-        if self.sound_channel_4.frame_sequence_timer == 0 {
-            self.sound_channel_4.frame_sequence_timer = 8192;
-            self.sound_channel_4.frame_sequence = (self.sound_channel_4.frame_sequence + 1) & 8;
-
-            if self.sound_channel_4.frame_sequence % 2 == 0 && bit_logic::check_bit(*nr44, 6) && self.sound_channel_4.length != 0 {
-                self.sound_channel_4.length -= 1;
-                if self.sound_channel_4.length == 0 { self.sound_channel_4.enabled = false; }
-            }
-
-            if self.sound_channel_4.frame_sequence == 7 && self.sound_channel_4.envelope_enabled && (nr42 & 0b111) != 0 {
-                self.sound_channel_4.envelope_sweeps -= 1;
-                if self.sound_channel_4.envelope_sweeps == 0 {
-                    self.sound_channel_4.envelope_sweeps = nr42 & 0b111;
-                    if self.sound_channel_4.envelope_sweeps != 0 {
-                        let new_amplitude = self.sound_channel_4.amplitude + if bit_logic::check_bit(*nr42, 3) { 1 } else { -1 };
-                        if new_amplitude > 0 && new_amplitude <= 15 {
-                            self.sound_channel_4.amplitude = new_amplitude;
-                        } else {
-                            self.sound_channel_4.envelope_enabled = false;
-                        }
-                    }
-                }
-            }
-        }
-
+    // The 15-bit LFSR shifts right with the XOR of its two low bits fed into bit 14, and into bit 6 too in 7-bit mode.
+    // Shifts of 14 and 15 never clock it, which silences the channel
+    fn update_sound_channel_4(&mut self, nr43: &u8) {
         if self.sound_channel_4.frequency_timer == 0 {
-            self.sound_channel_4.frequency_timer = (SOUND_CHANNEL_4_DIVISOR[(*nr43 as usize) & 0b111] as u16) << ((*nr43 as u16) >> 4);
+            let shift: u8 = *nr43 >> 4;
+            self.sound_channel_4.frequency_timer = (SOUND_CHANNEL_4_DIVISOR[(*nr43 as usize) & 0b111] as u32) << shift;
+            if shift >= 14 {
+                return;
+            }
             let xor_rs = (self.sound_channel_4.lfsr & 1) ^ ((self.sound_channel_4.lfsr & 0b10) >> 1);
             self.sound_channel_4.lfsr = (self.sound_channel_4.lfsr >> 1) | (xor_rs << 14);
             if bit_logic::check_bit(*nr43, 3) {
-                self.sound_channel_4.lfsr = (self.sound_channel_4.lfsr | (xor_rs << 6)) & 0x7f;
+                self.sound_channel_4.lfsr = (self.sound_channel_4.lfsr & !(1 << 6)) | (xor_rs << 6);
             }
         } else {
             self.sound_channel_4.frequency_timer -= 1;
         }
     }
 
+    // Bit 0 of the LFSR inverted is the output
     fn get_amplitude_sound_channel_4(&self) -> f32 {
-        if self.sound_channel_4.enabled {
-            if (self.sound_channel_4.lfsr & 1) != 0 {
-                0.0
-            } else {
-                (self.sound_channel_4.amplitude as f32) / 100.0
-            }
-        } else {
-            0.0
-        }
+        let high: bool = self.sound_channel_4.lfsr & 1 == 0;
+        let digital: u8 = if self.sound_channel_4.enabled && high { self.sound_channel_4.amplitude as u8 } else { 0 };
+        convert_dac(digital, self.sound_channel_4.dac_enabled)
     }
 }
 
@@ -373,16 +633,16 @@ impl SoundChannel1 {
     pub(crate) fn new() -> SoundChannel1 {
         SoundChannel1 {
             enabled: false,
+            dac_enabled: false,
             amplitude: 0,
             frequency: 0,
             frequency_timer: 0,
-            frame_sequence: 0,
-            frame_sequence_timer: 8192,
             wave_duty_position: 0,
             envelope_enabled: false,
             envelope_sweeps: 0,
             length: 0,
             sweep_enabled: false,
+            sweep_negated: false,
             sweep_period: 0,
             sweep_shadow: 0,
         }
@@ -393,10 +653,9 @@ impl SoundChannel2 {
     pub(crate) fn new() -> SoundChannel2 {
         SoundChannel2 {
             enabled: false,
+            dac_enabled: false,
             amplitude: 0,
             frequency_timer: 0,
-            frame_sequence: 8,
-            frame_sequence_timer: 8192,
             wave_duty_position: 0,
             envelope_enabled: false,
             envelope_sweeps: 0,
@@ -409,10 +668,11 @@ impl SoundChannel3 {
     pub(crate) fn new() -> SoundChannel3 {
         SoundChannel3 {
             enabled: false,
+            dac_enabled: false,
             frequency_timer: 0,
-            frame_sequence: 8,
-            frame_sequence_timer: 8192,
             wave_index: 0,
+            wave_ram_accessible: false,
+            sample_buffer: 0,
             length: 0,
         }
     }
@@ -422,14 +682,184 @@ impl SoundChannel4 {
     pub(crate) fn new() -> SoundChannel4 {
         SoundChannel4 {
             enabled: false,
+            dac_enabled: false,
             amplitude: 0,
             frequency_timer: 0,
-            frame_sequence: 7,
-            frame_sequence_timer: 8192,
             envelope_enabled: false,
             envelope_sweeps: 0,
             length: 0,
             lfsr: 0,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::boot::Model;
+
+    fn create_gameboy() -> Gameboy {
+        let mut gameboy: Gameboy = Gameboy::new();
+        gameboy.write_to_memory(0xff26, 0x80);
+        gameboy
+    }
+
+    #[test]
+    fn trigger_loads_the_upper_frequency_bits() {
+        let mut gameboy: Gameboy = create_gameboy();
+        gameboy.write_to_memory(0xff12, 0xf0);
+        gameboy.write_to_memory(0xff13, 0x34);
+        gameboy.write_to_memory(0xff14, 0x87);
+        assert_eq!(gameboy.sound_channel_1.frequency_timer, (2048 - 0x734) * 4);
+        assert_eq!(gameboy.sound_channel_1.sweep_shadow, 0x734);
+
+        gameboy.write_to_memory(0xff1a, 0x80);
+        gameboy.write_to_memory(0xff1d, 0x34);
+        gameboy.write_to_memory(0xff1e, 0x87);
+        assert_eq!(gameboy.sound_channel_3.frequency_timer, (2048 - 0x734) * 2 + 6);
+    }
+
+    #[test]
+    fn recordings_ignore_the_rate_adjustment() {
+        let mut gameboy: Gameboy = create_gameboy();
+        gameboy.set_sample_rate(48000);
+        gameboy.set_recording_enabled(true, true);
+        gameboy.set_audio_rate_adjustment(0.995);
+        for _ in 0..(4 * 70224 / 4) {
+            gameboy.update_audio(4);
+        }
+        gameboy.flush_audio();
+        // Four frames at exactly 48000 Hz, stereo
+        let expected: usize = (4.0 * 70224.0 * 48000.0 / CYCLES_PER_SECOND as f64).round() as usize * 2;
+        assert!(gameboy.record_data.len().abs_diff(expected) <= 2);
+        assert_eq!(gameboy.stem_data.len(), gameboy.record_data.len() * 2);
+        assert!(gameboy.audio_data.len() < gameboy.record_data.len() - 4);
+    }
+
+    // Bit 0 of the LFSR after every clock, from a channel 4 trigger
+    fn get_lfsr_output(nr43: u8, clocks: usize) -> Vec<bool> {
+        let mut gameboy: Gameboy = create_gameboy();
+        gameboy.write_to_memory(0xff21, 0xf0);
+        gameboy.write_to_memory(0xff23, 0x80);
+        (0..clocks).map(|_| {
+            gameboy.sound_channel_4.frequency_timer = 0;
+            gameboy.update_sound_channel_4(&nr43);
+            gameboy.sound_channel_4.lfsr & 1 == 1
+        }).collect()
+    }
+
+    // The output of an n-bit LFSR with taps at bits 0 and 1 follows a(k + n) = a(k) ^ a(k + 1) from all ones
+    fn get_known_sequence(width: usize, clocks: usize) -> Vec<bool> {
+        let mut sequence: Vec<bool> = vec![true; width];
+        while sequence.len() < clocks + 1 {
+            let k: usize = sequence.len() - width;
+            sequence.push(sequence[k] ^ sequence[k + 1]);
+        }
+        sequence[1..].to_vec()
+    }
+
+    #[test]
+    fn lfsr_follows_the_15_bit_sequence() {
+        let output: Vec<bool> = get_lfsr_output(0x00, 32767 * 2);
+        assert_eq!(output, get_known_sequence(15, 32767 * 2));
+        // A maximal length sequence, high for 2^14 - 1 of its 2^15 - 1 clocks
+        assert_eq!(output[..32767], output[32767..]);
+        assert_eq!(output[..32767].iter().filter(|&&bit| !bit).count(), 16383);
+    }
+
+    #[test]
+    fn lfsr_follows_the_7_bit_sequence() {
+        let output: Vec<bool> = get_lfsr_output(0x08, 127 * 4);
+        assert_eq!(output, get_known_sequence(7, 127 * 4));
+        assert_eq!(output[..127], output[127..254]);
+        assert_eq!(output[..127].iter().filter(|&&bit| !bit).count(), 63);
+    }
+
+    #[test]
+    fn lfsr_stops_at_shifts_14_and_15() {
+        assert!(get_lfsr_output(0xe0, 100).iter().all(|&bit| bit));
+        assert!(get_lfsr_output(0xf8, 100).iter().all(|&bit| bit));
+    }
+
+    // Retriggers channel 3 as it is about to read the sample after wave_index
+    fn retrigger_wave_channel(gameboy: &mut Gameboy, wave_index: u8) -> [u8; 16] {
+        for (offset, address) in (WAVE_RAM..WAVE_RAM + 0x10).enumerate() {
+            gameboy.write_to_memory(address, (offset as u8) * 0x11);
+        }
+        gameboy.write_to_memory(0xff1a, 0x80);
+        gameboy.write_to_memory(0xff1e, 0x80);
+        gameboy.sound_channel_3.wave_index = wave_index;
+        gameboy.sound_channel_3.frequency_timer = 1;
+        gameboy.write_to_memory(0xff1e, 0x80);
+        std::array::from_fn(|offset| gameboy.bus.io[WAVE_RAM + offset as u16])
+    }
+
+    #[test]
+    fn dmg_retrigger_copies_a_byte_from_the_first_4() {
+        let mut gameboy: Gameboy = create_gameboy();
+        let wave_ram: [u8; 16] = retrigger_wave_channel(&mut gameboy, 3);
+        assert_eq!(wave_ram[0], 0x22);
+        assert_eq!(wave_ram[1..], [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+    }
+
+    #[test]
+    fn dmg_retrigger_copies_the_aligned_4_bytes_past_the_first_4() {
+        let mut gameboy: Gameboy = create_gameboy();
+        let wave_ram: [u8; 16] = retrigger_wave_channel(&mut gameboy, 11);
+        assert_eq!(wave_ram[..8], [0x44, 0x55, 0x66, 0x77, 0x44, 0x55, 0x66, 0x77]);
+        assert_eq!(wave_ram[8..], [0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+        let mut gameboy: Gameboy = create_gameboy();
+        let wave_ram: [u8; 16] = retrigger_wave_channel(&mut gameboy, 30);
+        assert_eq!(wave_ram[..4], [0xcc, 0xdd, 0xee, 0xff]);
+    }
+
+    #[test]
+    fn retrigger_leaves_wave_ram_alone_on_cgb_or_away_from_a_read() {
+        let mut gameboy: Gameboy = create_gameboy();
+        gameboy.model = Model::Cgb;
+        assert_eq!(retrigger_wave_channel(&mut gameboy, 11)[..4], [0x00, 0x11, 0x22, 0x33]);
+        let mut gameboy: Gameboy = create_gameboy();
+        retrigger_wave_channel(&mut gameboy, 11);
+        gameboy.write_to_memory(0xff1a, 0x00);
+        gameboy.write_to_memory(0xff1a, 0x80);
+        for (offset, address) in (WAVE_RAM..WAVE_RAM + 0x10).enumerate() {
+            gameboy.write_to_memory(address, (offset as u8) * 0x11);
+        }
+        gameboy.write_to_memory(0xff1e, 0x80);
+        gameboy.sound_channel_3.frequency_timer = 2;
+        gameboy.write_to_memory(0xff1e, 0x80);
+        assert_eq!(gameboy.bus.io[WAVE_RAM], 0x00);
+    }
+
+    #[test]
+    fn mixer_leaves_the_apu_alone() {
+        let mut gameboys: [Gameboy; 2] = [create_gameboy(), create_gameboy()];
+        for gameboy in gameboys.iter_mut() {
+            gameboy.write_to_memory(0xff24, 0x77);
+            gameboy.write_to_memory(0xff25, 0xf3);
+            gameboy.write_to_memory(0xff12, 0xf3);
+            gameboy.write_to_memory(0xff14, 0x87);
+            gameboy.write_to_memory(0xff17, 0xa1);
+            gameboy.write_to_memory(0xff19, 0x86);
+        }
+        gameboys[1].mixer.toggle_muted(0);
+        gameboys[1].mixer.toggle_solo(1);
+        gameboys[1].mixer.set_pan(1, Some(-1.0));
+        gameboys[1].mixer.set_volume(1, 0.25);
+        // Half a second, long enough for the envelopes to move
+        for _ in 0..(4194304 / 2 / 128) {
+            for gameboy in gameboys.iter_mut() {
+                gameboy.update_timer(128);
+                gameboy.update_audio(128);
+            }
+        }
+        assert_ne!(gameboys[0].sound_channel_1.amplitude, 0xf);
+        assert_eq!(format!("{:?}", gameboys[0].sound_channel_1), format!("{:?}", gameboys[1].sound_channel_1));
+        assert_eq!(format!("{:?}", gameboys[0].sound_channel_2), format!("{:?}", gameboys[1].sound_channel_2));
+        assert_eq!(gameboys[1].bus.io[0xff24], 0x77);
+        assert_eq!(gameboys[1].bus.io[0xff25], 0xf3);
+        // Only the soloed channel 2 is heard, on the left at a quarter volume
+        assert_eq!(gameboys[1].mix_sound_channels(&[1.0; 4]), (0.25 / 4.0, 0.0));
+        assert_eq!(gameboys[0].mix_sound_channels(&[1.0; 4]), (1.0, 0.5));
+    }
+}
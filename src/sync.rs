@@ -0,0 +1,207 @@
+use std::time::{Duration, Instant};
+
+// Audio kept queued ahead of the speakers
+const TARGET_LATENCY: Duration = Duration::from_millis(50);
+// Largest change of the resampling ratio, half a percent of pitch isn't audible
+const MAX_RATE_DELTA: f64 = 0.005;
+// The queue is dropped past this many times the target, after the window was dragged or the host stalled
+const MAX_LATENCY_FACTOR: f64 = 4.0;
+// Displays refreshing this much faster than the Game Boy get paced, closer ones are left to the rate control
+const MAX_REFRESH_RATIO: f64 = 1.02;
+// Presented frames timed before the refresh rate is judged
+const REFRESH_MEASURE_FRAMES: u32 = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SyncMode {
+    // Frames wait for the audio queue to drain, timing follows the sound card
+    Audio,
+    // Frames wait for vsync and the resampling ratio is nudged to keep the audio queue level
+    Video,
+}
+
+impl SyncMode {
+    pub(crate) fn from_name(name: &str) -> Option<SyncMode> {
+        match name {
+            "audio" => Some(SyncMode::Audio),
+            "video" => Some(SyncMode::Video),
+            _ => None,
+        }
+    }
+}
+
+// Tracks the fill level of the audio queue in stereo samples against the target latency
+#[derive(Debug)]
+pub(crate) struct RateControl {
+    sample_rate: u32,
+    target_samples: f64,
+}
+
+impl RateControl {
+    pub(crate) fn new(sample_rate: u32) -> RateControl {
+        RateControl {
+            sample_rate,
+            target_samples: TARGET_LATENCY.as_secs_f64() * sample_rate as f64,
+        }
+    }
+
+    pub(crate) fn get_latency(&self, queued_samples: u32) -> Duration {
+        Duration::from_secs_f64(queued_samples as f64 / self.sample_rate as f64)
+    }
+
+    // Fewer samples are made per frame while the queue is above the target and more while it's below
+    pub(crate) fn get_rate_adjustment(&self, queued_samples: u32) -> f64 {
+        let error: f64 = (self.target_samples - queued_samples as f64) / self.target_samples;
+        1.0 + MAX_RATE_DELTA * error.clamp(-1.0, 1.0)
+    }
+
+    // Time until the queue drains back down to the target
+    pub(crate) fn get_wait(&self, queued_samples: u32) -> Duration {
+        self.get_latency(queued_samples).saturating_sub(TARGET_LATENCY)
+    }
+
+    pub(crate) fn is_overfilled(&self, queued_samples: u32) -> bool {
+        queued_samples as f64 > self.target_samples * MAX_LATENCY_FACTOR
+    }
+}
+
+// Video sync waits on vsync, which only keeps the right speed on a display close to 59.73 Hz.
+// The refresh rate is measured over the first frames and faster displays, or drivers that ignore vsync,
+// sleep until a deadline every frame instead
+#[derive(Debug)]
+pub(crate) struct FramePacer {
+    frame_duration: Duration,
+    measure_start: Option<Instant>,
+    measured_frames: u32,
+    measured_refresh: Option<f64>,
+    deadline: Option<Instant>,
+}
+
+impl FramePacer {
+    pub(crate) fn new(frames_per_second: f64) -> FramePacer {
+        FramePacer {
+            frame_duration: Duration::from_secs_f64(1.0 / frames_per_second),
+            measure_start: None,
+            measured_frames: 0,
+            measured_refresh: None,
+            deadline: None,
+        }
+    }
+
+    pub(crate) fn get_measured_refresh(&self) -> Option<f64> {
+        self.measured_refresh
+    }
+
+    // Called right after a frame is presented, the time to sleep before the next one
+    pub(crate) fn get_wait(&mut self, now: Instant) -> Duration {
+        if let Some(deadline) = self.deadline {
+            // After a stall the schedule starts over instead of rushing to catch up
+            let next_deadline: Instant = if deadline + self.frame_duration < now { now } else { deadline + self.frame_duration };
+            self.deadline = Some(next_deadline);
+            return next_deadline.saturating_duration_since(now);
+        }
+        if self.measured_refresh.is_some() {
+            return Duration::ZERO;
+        }
+        let Some(measure_start) = self.measure_start else {
+            self.measure_start = Some(now);
+            return Duration::ZERO;
+        };
+        self.measured_frames += 1;
+        if self.measured_frames == REFRESH_MEASURE_FRAMES {
+            let refresh: f64 = self.measured_frames as f64 / now.duration_since(measure_start).as_secs_f64();
+            self.measured_refresh = Some(refresh);
+            if refresh * self.frame_duration.as_secs_f64() > MAX_REFRESH_RATIO {
+                self.deadline = Some(now);
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48000;
+    // 50 ms at 48000 Hz
+    const TARGET: u32 = 2400;
+
+    #[test]
+    fn sync_modes_parse_by_name() {
+        assert_eq!(SyncMode::from_name("audio"), Some(SyncMode::Audio));
+        assert_eq!(SyncMode::from_name("video"), Some(SyncMode::Video));
+        assert_eq!(SyncMode::from_name("vsync"), None);
+    }
+
+    #[test]
+    fn rate_adjustment_follows_the_queue() {
+        let rate_control: RateControl = RateControl::new(SAMPLE_RATE);
+        assert_eq!(rate_control.get_rate_adjustment(TARGET), 1.0);
+        assert!(rate_control.get_rate_adjustment(TARGET / 2) > 1.0);
+        assert!(rate_control.get_rate_adjustment(TARGET * 3 / 2) < 1.0);
+    }
+
+    #[test]
+    fn rate_adjustment_is_clamped_to_half_a_percent() {
+        let rate_control: RateControl = RateControl::new(SAMPLE_RATE);
+        assert_eq!(rate_control.get_rate_adjustment(0), 1.005);
+        assert_eq!(rate_control.get_rate_adjustment(TARGET * 2), 0.995);
+        assert_eq!(rate_control.get_rate_adjustment(TARGET * 10), 0.995);
+    }
+
+    #[test]
+    fn audio_sync_waits_for_the_queue_to_drain_to_the_target() {
+        let rate_control: RateControl = RateControl::new(SAMPLE_RATE);
+        assert_eq!(rate_control.get_wait(TARGET / 2), Duration::ZERO);
+        assert_eq!(rate_control.get_wait(TARGET * 2), TARGET_LATENCY);
+        assert_eq!(rate_control.get_latency(TARGET), TARGET_LATENCY);
+    }
+
+    #[test]
+    fn queue_is_reset_past_four_times_the_target() {
+        let rate_control: RateControl = RateControl::new(SAMPLE_RATE);
+        assert!(!rate_control.is_overfilled(TARGET * 4));
+        assert!(rate_control.is_overfilled(TARGET * 4 + 1));
+    }
+
+    // Presents frames in time with a display's refresh, sleeping as long as the pacer asks in between.
+    // Returns when the last frame was presented
+    fn present_frames(frame_pacer: &mut FramePacer, refresh: f64, frames: u32, start: Instant) -> Instant {
+        let refresh_seconds: f64 = 1.0 / refresh;
+        let (mut refreshes, mut now): (u64, Instant) = (0, start);
+        for _ in 0..frames {
+            now = start + Duration::from_secs_f64(refreshes as f64 * refresh_seconds);
+            let wait: f64 = frame_pacer.get_wait(now).as_secs_f64();
+            // The next present waits for the first refresh after the sleep
+            refreshes = (refreshes + 1).max((refreshes as f64 + wait / refresh_seconds).ceil() as u64);
+        }
+        now
+    }
+
+    #[test]
+    fn displays_near_the_game_boy_rate_are_not_paced() {
+        let mut frame_pacer: FramePacer = FramePacer::new(59.7275);
+        let now: Instant = present_frames(&mut frame_pacer, 60.0, REFRESH_MEASURE_FRAMES + 1, Instant::now());
+        assert!((frame_pacer.get_measured_refresh().unwrap() - 60.0).abs() < 0.01);
+        assert_eq!(frame_pacer.get_wait(now), Duration::ZERO);
+    }
+
+    #[test]
+    fn fast_displays_are_paced_to_the_game_boy_rate() {
+        let mut frame_pacer: FramePacer = FramePacer::new(59.7275);
+        let start: Instant = present_frames(&mut frame_pacer, 144.0, REFRESH_MEASURE_FRAMES + 1, Instant::now());
+        assert!((frame_pacer.get_measured_refresh().unwrap() - 144.0).abs() < 0.01);
+        let seconds: f64 = present_frames(&mut frame_pacer, 144.0, 600, start).duration_since(start).as_secs_f64();
+        assert!((600.0 / seconds - 59.7275).abs() < 0.5);
+    }
+
+    #[test]
+    fn pacing_starts_over_after_a_stall() {
+        let mut frame_pacer: FramePacer = FramePacer::new(50.0);
+        let start: Instant = present_frames(&mut frame_pacer, 1000.0, REFRESH_MEASURE_FRAMES + 1, Instant::now());
+        assert_eq!(frame_pacer.get_wait(start + Duration::from_millis(5)), Duration::from_millis(15));
+        let now: Instant = start + Duration::from_secs(1);
+        assert_eq!(frame_pacer.get_wait(now), Duration::ZERO);
+        assert_eq!(frame_pacer.get_wait(now + Duration::from_millis(5)), Duration::from_millis(15));
+    }
+}
@@ -30,9 +30,11 @@ impl Gameboy {
 
     pub(crate) fn update_timer(&mut self, cycles: u8) {
         self.divider_counter += cycles as i32;
-        if self.divider_counter >= 255 {
-            self.divider_counter = 0;
-            self.rom[0xff04_usize] += 1;
+        while self.divider_counter >= 256 {
+            self.divider_counter -= 256;
+            let previous_div: u8 = self.bus.io[0xff04];
+            self.bus.io[0xff04] = previous_div.wrapping_add(1);
+            self.update_frame_sequencer(previous_div);
         }
 
         if self.is_clock_enabled() {
@@ -49,4 +51,38 @@ impl Gameboy {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs instructions of 4 to 24 cycles until the total is reached
+    fn run_cycles(gameboy: &mut Gameboy, total: u32) {
+        let mut elapsed: u32 = 0;
+        for length in [4u8, 8, 12, 16, 20, 24].into_iter().cycle() {
+            let length: u8 = (total - elapsed).min(length as u32) as u8;
+            if length == 0 {
+                break;
+            }
+            gameboy.update_timer(length);
+            elapsed += length as u32;
+        }
+    }
+
+    #[test]
+    fn div_keeps_the_leftover_cycles() {
+        let mut gameboy: Gameboy = Gameboy::new();
+        gameboy.write_to_memory(0xff26, 0x80);
+        gameboy.bus.io[0xff04] = 0;
+        gameboy.divider_counter = 0;
+        run_cycles(&mut gameboy, 8188);
+        assert_eq!(gameboy.bus.io[0xff04], 31);
+        assert_eq!(gameboy.frame_sequencer, 0);
+        // 8192 cycles is 32 DIV ticks and a single falling edge of bit 4
+        run_cycles(&mut gameboy, 4);
+        assert_eq!(gameboy.bus.io[0xff04], 32);
+        assert_eq!(gameboy.frame_sequencer, 1);
+        assert_eq!(gameboy.divider_counter, 0);
+    }
+}
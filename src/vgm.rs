@@ -0,0 +1,230 @@
+use super::CYCLES_PER_SECOND;
+
+// VGM files count time in samples at 44100 Hz no matter the chip
+const VGM_SAMPLE_RATE: u64 = 44_100;
+const VGM_VERSION: u32 = 0x171;
+const HEADER_SIZE: usize = 0x100;
+// Header fields, offsets that point somewhere else in the file are relative to the field itself
+const EOF_OFFSET: usize = 0x04;
+const VERSION: usize = 0x08;
+const GD3_OFFSET: usize = 0x14;
+const TOTAL_SAMPLES: usize = 0x18;
+const LOOP_OFFSET: usize = 0x1c;
+const LOOP_SAMPLES: usize = 0x20;
+const DATA_OFFSET: usize = 0x34;
+const GAME_BOY_CLOCK: usize = 0x80;
+
+const COMMAND_GAME_BOY_WRITE: u8 = 0xb3;
+const COMMAND_WAIT: u8 = 0x61;
+const COMMAND_WAIT_NTSC_FRAME: u8 = 0x62;
+const COMMAND_WAIT_PAL_FRAME: u8 = 0x63;
+// 0x70-0x7f wait 1 to 16 samples
+const COMMAND_WAIT_SHORT: u8 = 0x70;
+const COMMAND_END: u8 = 0x66;
+
+const NTSC_FRAME_SAMPLES: u64 = 735;
+const PAL_FRAME_SAMPLES: u64 = 882;
+const GD3_VERSION: u32 = 0x100;
+const SYSTEM_NAME: &str = "Nintendo Game Boy";
+
+// Writes to the sound registers with the time between them, to be turned into a VGM file
+#[derive(Debug)]
+pub(crate) struct VgmLogger {
+    data: Vec<u8>,
+    // Sound clocks since logging started and the samples already waited for
+    cycles: u64,
+    samples: u64,
+    // Offset in the data and samples at the loop point
+    loop_point: Option<(usize, u64)>,
+}
+
+impl VgmLogger {
+    pub(crate) fn new() -> VgmLogger {
+        VgmLogger { data: Vec::new(), cycles: 0, samples: 0, loop_point: None }
+    }
+
+    pub(crate) fn tick(&mut self, cycles: u8) {
+        self.cycles += cycles as u64;
+    }
+
+    // Catches up with the sound clock, computed from the total so the rounding never drifts
+    fn write_wait(&mut self) {
+        let target: u64 = self.cycles * VGM_SAMPLE_RATE / CYCLES_PER_SECOND as u64;
+        let mut wait: u64 = target - self.samples;
+        while wait > 0 {
+            let samples: u64 = match wait {
+                1..=16 => {
+                    self.data.push(COMMAND_WAIT_SHORT + (wait - 1) as u8);
+                    wait
+                },
+                NTSC_FRAME_SAMPLES => {
+                    self.data.push(COMMAND_WAIT_NTSC_FRAME);
+                    wait
+                },
+                PAL_FRAME_SAMPLES => {
+                    self.data.push(COMMAND_WAIT_PAL_FRAME);
+                    wait
+                },
+                _ => {
+                    let samples: u64 = wait.min(u16::MAX as u64);
+                    self.data.push(COMMAND_WAIT);
+                    self.data.extend_from_slice(&(samples as u16).to_le_bytes());
+                    samples
+                },
+            };
+            wait -= samples;
+        }
+        self.samples = target;
+    }
+
+    // Registers are numbered from NR10 at 0xff10
+    pub(crate) fn write_register(&mut self, address: u16, value: u8) {
+        self.write_wait();
+        self.data.extend_from_slice(&[COMMAND_GAME_BOY_WRITE, (address - 0xff10) as u8, value]);
+    }
+
+    pub(crate) fn set_loop_point(&mut self) {
+        self.write_wait();
+        self.loop_point = Some((self.data.len(), self.samples));
+    }
+
+    // Header, commands and the GD3 tags with the cartridge title as the game name
+    pub(crate) fn finish(mut self, title: &str) -> Vec<u8> {
+        self.write_wait();
+        let mut file: Vec<u8> = vec![0; HEADER_SIZE];
+        file[0..4].copy_from_slice(b"Vgm ");
+        write_u32(&mut file, VERSION, VGM_VERSION);
+        write_u32(&mut file, TOTAL_SAMPLES, self.samples as u32);
+        write_u32(&mut file, DATA_OFFSET, (HEADER_SIZE - DATA_OFFSET) as u32);
+        write_u32(&mut file, GAME_BOY_CLOCK, CYCLES_PER_SECOND);
+        if let Some((offset, samples)) = self.loop_point {
+            write_u32(&mut file, LOOP_OFFSET, (HEADER_SIZE + offset - LOOP_OFFSET) as u32);
+            write_u32(&mut file, LOOP_SAMPLES, (self.samples - samples) as u32);
+        }
+        file.extend_from_slice(&self.data);
+        file.push(COMMAND_END);
+        let gd3_position: usize = file.len();
+        write_u32(&mut file, GD3_OFFSET, (gd3_position - GD3_OFFSET) as u32);
+        file.extend_from_slice(&create_gd3(title));
+        let length: usize = file.len();
+        write_u32(&mut file, EOF_OFFSET, (length - EOF_OFFSET) as u32);
+        file
+    }
+}
+
+fn write_u32(file: &mut [u8], offset: usize, value: u32) {
+    file[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+// Null terminated UTF-16 strings, English and Japanese track, game, system and author names
+// followed by the release date, who ripped it and notes
+fn create_gd3(title: &str) -> Vec<u8> {
+    let fields: [&str; 11] = ["", "", title, "", SYSTEM_NAME, "", "", "", "", "", ""];
+    let mut strings: Vec<u8> = Vec::new();
+    for field in fields {
+        for unit in field.encode_utf16().chain([0]) {
+            strings.extend_from_slice(&unit.to_le_bytes());
+        }
+    }
+    let mut gd3: Vec<u8> = b"Gd3 ".to_vec();
+    gd3.extend_from_slice(&GD3_VERSION.to_le_bytes());
+    gd3.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+    gd3.extend_from_slice(&strings);
+    gd3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Command {
+        Write(u8, u8),
+        Wait(u64),
+    }
+
+    fn read_u32(file: &[u8], offset: usize) -> usize {
+        u32::from_le_bytes(file[offset..offset + 4].try_into().unwrap()) as usize
+    }
+
+    fn run(vgm_logger: &mut VgmLogger, mut cycles: u64) {
+        while cycles > 0 {
+            let step: u64 = cycles.min(u8::MAX as u64);
+            vgm_logger.tick(step as u8);
+            cycles -= step;
+        }
+    }
+
+    // Commands from an offset up to the end command, with the offset of each
+    fn parse_commands(file: &[u8], mut offset: usize) -> Vec<(usize, Command)> {
+        let mut commands: Vec<(usize, Command)> = Vec::new();
+        loop {
+            let (command, length): (Command, usize) = match file[offset] {
+                COMMAND_GAME_BOY_WRITE => (Command::Write(file[offset + 1], file[offset + 2]), 3),
+                COMMAND_WAIT => (Command::Wait(u16::from_le_bytes([file[offset + 1], file[offset + 2]]) as u64), 3),
+                COMMAND_WAIT_NTSC_FRAME => (Command::Wait(NTSC_FRAME_SAMPLES), 1),
+                COMMAND_WAIT_PAL_FRAME => (Command::Wait(PAL_FRAME_SAMPLES), 1),
+                command @ COMMAND_WAIT_SHORT..=0x7f => (Command::Wait((command - COMMAND_WAIT_SHORT) as u64 + 1), 1),
+                COMMAND_END => return commands,
+                command => panic!("Unexpected command {:02x}", command),
+            };
+            commands.push((offset, command));
+            offset += length;
+        }
+    }
+
+    #[test]
+    fn logged_writes_round_trip() {
+        let mut vgm_logger: VgmLogger = VgmLogger::new();
+        vgm_logger.write_register(0xff26, 0x80);
+        // 735.0004 samples
+        run(&mut vgm_logger, 69906);
+        vgm_logger.write_register(0xff10, 0x00);
+        run(&mut vgm_logger, 1000);
+        vgm_logger.set_loop_point();
+        vgm_logger.write_register(0xff11, 0x3f);
+        run(&mut vgm_logger, CYCLES_PER_SECOND as u64);
+        vgm_logger.write_register(0xff12, 0xf0);
+        let file: Vec<u8> = vgm_logger.finish("TETRIS");
+
+        assert_eq!(&file[0..4], b"Vgm ");
+        assert_eq!(read_u32(&file, EOF_OFFSET), file.len() - EOF_OFFSET);
+        assert_eq!(read_u32(&file, VERSION), 0x171);
+        assert_eq!(read_u32(&file, GAME_BOY_CLOCK), 4_194_304);
+        let data_start: usize = DATA_OFFSET + read_u32(&file, DATA_OFFSET);
+        assert_eq!(data_start, HEADER_SIZE);
+        let gd3_start: usize = GD3_OFFSET + read_u32(&file, GD3_OFFSET);
+        assert_eq!(&file[gd3_start..gd3_start + 4], b"Gd3 ");
+
+        let commands: Vec<(usize, Command)> = parse_commands(&file, data_start);
+        let expected: Vec<Command> = vec![
+            Command::Write(0x16, 0x80),
+            Command::Wait(735),
+            Command::Write(0x00, 0x00),
+            Command::Wait(10),
+            Command::Write(0x01, 0x3f),
+            Command::Wait(44100),
+            Command::Write(0x02, 0xf0),
+        ];
+        assert_eq!(commands.iter().map(|(_, command)| command).collect::<Vec<_>>(), expected.iter().collect::<Vec<_>>());
+        // The one byte encodings are used where they fit
+        assert_eq!(file[commands[1].0], COMMAND_WAIT_NTSC_FRAME);
+        assert_eq!(file[commands[3].0], COMMAND_WAIT_SHORT + 9);
+
+        let total: u64 = commands.iter().map(|(_, command)| match command { Command::Wait(samples) => *samples, _ => 0 }).sum();
+        assert_eq!(read_u32(&file, TOTAL_SAMPLES) as u64, total);
+        // The loop offset is relative to its own field and lands on the write after the loop point
+        assert_eq!(LOOP_OFFSET + read_u32(&file, LOOP_OFFSET), commands[4].0);
+        assert_eq!(read_u32(&file, LOOP_SAMPLES), 44100);
+    }
+
+    #[test]
+    fn long_waits_are_split() {
+        let mut vgm_logger: VgmLogger = VgmLogger::new();
+        run(&mut vgm_logger, CYCLES_PER_SECOND as u64 * 2);
+        let file: Vec<u8> = vgm_logger.finish("");
+        let waits: Vec<Command> = parse_commands(&file, HEADER_SIZE).into_iter().map(|(_, command)| command).collect();
+        assert_eq!(waits, vec![Command::Wait(65535), Command::Wait(88200 - 65535)]);
+        assert_eq!(read_u32(&file, LOOP_OFFSET), 0);
+    }
+}
@@ -0,0 +1,137 @@
+use sdl2::VideoSubsystem;
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use super::bit_logic;
+use super::gameboy::Gameboy;
+use super::scope::ChannelStatus;
+
+// One lane per sound channel, the oscilloscope on the left and its registers on the right, NR50 below them
+const SCOPE_WIDTH: u32 = 384;
+const INFO_WIDTH: u32 = 128;
+const LANE_HEIGHT: u32 = 96;
+const FOOTER_HEIGHT: u32 = 32;
+const WIDTH: u32 = SCOPE_WIDTH + INFO_WIDTH;
+const HEIGHT: u32 = LANE_HEIGHT * 4 + FOOTER_HEIGHT;
+// Pixels between -1.0 and the middle of a lane
+const SCOPE_SCALE: f32 = 40.0;
+
+const BACKGROUND: Color = Color::RGB(16, 16, 16);
+const GRID: Color = Color::RGB(48, 48, 48);
+const TEXT: Color = Color::RGB(224, 224, 224);
+const DIMMED: Color = Color::RGB(80, 80, 80);
+const CHANNEL_COLORS: [Color; 4] = [Color::RGB(255, 96, 96), Color::RGB(255, 208, 64), Color::RGB(96, 160, 255), Color::RGB(128, 255, 128)];
+
+// 3x5 pixel glyphs, the high bit of each row is the left column
+const GLYPH_SCALE: i32 = 2;
+const GLYPH_ADVANCE: i32 = 4 * GLYPH_SCALE;
+const GLYPHS: [(char, [u8; 5]); 18] = [
+    ('0', [0b111, 0b101, 0b101, 0b101, 0b111]),
+    ('1', [0b010, 0b110, 0b010, 0b010, 0b111]),
+    ('2', [0b111, 0b001, 0b111, 0b100, 0b111]),
+    ('3', [0b111, 0b001, 0b111, 0b001, 0b111]),
+    ('4', [0b101, 0b101, 0b111, 0b001, 0b001]),
+    ('5', [0b111, 0b100, 0b111, 0b001, 0b111]),
+    ('6', [0b111, 0b100, 0b111, 0b101, 0b111]),
+    ('7', [0b111, 0b001, 0b001, 0b001, 0b001]),
+    ('8', [0b111, 0b101, 0b111, 0b101, 0b111]),
+    ('9', [0b111, 0b101, 0b111, 0b001, 0b111]),
+    ('H', [0b101, 0b101, 0b111, 0b101, 0b101]),
+    ('Z', [0b111, 0b001, 0b010, 0b100, 0b111]),
+    ('V', [0b101, 0b101, 0b101, 0b101, 0b010]),
+    ('D', [0b110, 0b101, 0b101, 0b101, 0b110]),
+    ('%', [0b101, 0b001, 0b010, 0b100, 0b101]),
+    ('L', [0b100, 0b100, 0b100, 0b100, 0b111]),
+    ('R', [0b110, 0b101, 0b110, 0b101, 0b101]),
+    ('N', [0b110, 0b101, 0b101, 0b101, 0b101]),
+];
+
+// Debug window with an oscilloscope per sound channel, their frequency, volume and duty and the NR50 and NR51 routing
+pub(crate) struct Visualizer {
+    canvas: Canvas<Window>,
+}
+
+impl Visualizer {
+    pub(crate) fn new(video_subsystem: &VideoSubsystem) -> Visualizer {
+        let window = video_subsystem.window("RPGBE - Sound", WIDTH, HEIGHT)
+            .build()
+            .expect("Couldn't create visualizer window from video");
+        let canvas = window.into_canvas().accelerated().build().expect("Couldn't create canvas from visualizer window");
+        Visualizer { canvas }
+    }
+
+    pub(crate) fn get_window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    pub(crate) fn draw(&mut self, gameboy: &Gameboy) {
+        self.canvas.set_draw_color(BACKGROUND);
+        self.canvas.clear();
+        let nr50: u8 = gameboy.bus.io[0xff24];
+        let nr51: u8 = gameboy.bus.io[0xff25];
+        let statuses: [ChannelStatus; 4] = gameboy.get_channel_status();
+        // Twice the width so every channel can line up on a rising edge
+        let samples: Vec<[f32; 4]> = gameboy.scope.as_ref().map_or_else(Vec::new, |scope| {
+            scope.read_from(scope.get_written().saturating_sub(SCOPE_WIDTH as u64 * 2)).map(|(_, sample)| sample.channels).collect()
+        });
+        for (channel, status) in statuses.iter().enumerate() {
+            let top: i32 = (LANE_HEIGHT as usize * channel) as i32;
+            let color: Color = if status.enabled { CHANNEL_COLORS[channel] } else { DIMMED };
+            self.draw_scope(&samples, channel, top, color);
+            let left: i32 = SCOPE_WIDTH as i32 + 8;
+            self.draw_text(&format!("{} {}HZ", channel + 1, status.frequency.round() as u32), left, top + 8, color);
+            let volume: String = match status.duty {
+                Some(duty) => format!("V{} D{}%", status.volume, duty),
+                None => format!("V{}", status.volume),
+            };
+            self.draw_text(&volume, left, top + 28, TEXT);
+            let left_color: Color = if bit_logic::check_bit(nr51, channel as u8 + 4) { TEXT } else { DIMMED };
+            let right_color: Color = if bit_logic::check_bit(nr51, channel as u8) { TEXT } else { DIMMED };
+            self.draw_text("L", left, top + 48, left_color);
+            self.draw_text("R", left + GLYPH_ADVANCE * 2, top + 48, right_color);
+        }
+        let footer: i32 = (LANE_HEIGHT * 4) as i32;
+        self.draw_text(&format!("NR50 L{} R{}", (nr50 >> 4) & 0b111, nr50 & 0b111), 8, footer + 10, TEXT);
+        self.canvas.present();
+    }
+
+    fn draw_scope(&mut self, samples: &[[f32; 4]], channel: usize, top: i32, color: Color) {
+        let middle: i32 = top + (LANE_HEIGHT / 2) as i32;
+        self.canvas.set_draw_color(GRID);
+        let _ = self.canvas.draw_line(Point::new(0, middle), Point::new(SCOPE_WIDTH as i32, middle));
+        let _ = self.canvas.draw_line(Point::new(0, top + LANE_HEIGHT as i32 - 1), Point::new(WIDTH as i32, top + LANE_HEIGHT as i32 - 1));
+        if samples.len() < SCOPE_WIDTH as usize {
+            return;
+        }
+        let (low, high): (f32, f32) = samples.iter().fold((f32::MAX, f32::MIN), |(low, high), sample| (low.min(sample[channel]), high.max(sample[channel])));
+        let threshold: f32 = (low + high) / 2.0;
+        let last_start: usize = samples.len() - SCOPE_WIDTH as usize;
+        let start: usize = (1..=last_start).rev()
+            .find(|&index| samples[index - 1][channel] < threshold && samples[index][channel] >= threshold)
+            .unwrap_or(last_start);
+        let points: Vec<Point> = samples[start..start + SCOPE_WIDTH as usize].iter().enumerate()
+            .map(|(x, sample)| Point::new(x as i32, middle - (sample[channel] * SCOPE_SCALE) as i32))
+            .collect();
+        self.canvas.set_draw_color(color);
+        let _ = self.canvas.draw_lines(points.as_slice());
+    }
+
+    fn draw_text(&mut self, text: &str, left: i32, top: i32, color: Color) {
+        self.canvas.set_draw_color(color);
+        for (index, character) in text.chars().enumerate() {
+            let Some((_, rows)) = GLYPHS.iter().find(|(glyph, _)| *glyph == character) else {
+                continue;
+            };
+            let x: i32 = left + index as i32 * GLYPH_ADVANCE;
+            for (row, bits) in rows.iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) != 0 {
+                        let _ = self.canvas.fill_rect(Rect::new(x + column * GLYPH_SCALE, top + row as i32 * GLYPH_SCALE, GLYPH_SCALE as u32, GLYPH_SCALE as u32));
+                    }
+                }
+            }
+        }
+    }
+}